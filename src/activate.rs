@@ -0,0 +1,221 @@
+//! Per-session activation scripts, for people who would rather not have the
+//! MSYS2 directories on their PATH permanently.
+//!
+//! Each shell gets an activate/deactivate pair. Activating saves the previous
+//! value of every variable it touches in `_RS_EASY_OLD_<NAME>`; deactivating
+//! puts those values back (unsetting variables that were unset before).
+
+use std::path::{Path, PathBuf};
+
+use crate::manifest::Manifest;
+use crate::msys2::to_msys_path;
+
+/// Set while activated, so a second activation is a no-op.
+const ACTIVE: &str = "RS_EASY_INSTALL_ACTIVE";
+
+/// The environment an activated shell gets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Activation {
+    /// Put in front of PATH, in this order.
+    pub prepend: Vec<PathBuf>,
+    /// Put at the end of PATH.
+    pub append: Vec<PathBuf>,
+    /// `PKG_CONFIG_PATH` entries.
+    pub pkg_config: Vec<PathBuf>,
+    /// `CC_*`/`CXX_*` and the like.
+    pub vars: Vec<(String, String)>,
+}
+
+impl Activation {
+    /// Same PATH order as the persistent PATH update: cargo's `bin`, the
+    /// toolchain directories, the rest of PATH, then MSYS2's `usr\bin`.
+    pub fn new(manifest: &Manifest, msys2_root: &Path, cargo_home: &Path) -> Activation {
+        let mut prepend = vec![cargo_home.join("bin")];
+        prepend.extend(manifest.environments().into_iter().map(|env| env.bin_dir(msys2_root)));
+        let prefix = msys2_root.join(manifest.msys2.env.name());
+        Activation {
+            prepend,
+            append: vec![msys2_root.join("usr").join("bin")],
+            pkg_config: vec![prefix.join("lib").join("pkgconfig"), prefix.join("share").join("pkgconfig")],
+            vars: manifest.compiler_vars(msys2_root),
+        }
+    }
+
+    /// Every variable activation changes.
+    fn names(&self) -> Vec<&str> {
+        let mut names = vec!["PATH", "PKG_CONFIG_PATH"];
+        names.extend(self.vars.iter().map(|(name, _)| name.as_str()));
+        names
+    }
+
+    /// `(file name, contents)` of every script.
+    pub fn scripts(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("activate.bat", self.bat()),
+            ("deactivate.bat", self.deactivate_bat()),
+            ("activate.ps1", self.ps1()),
+            ("deactivate.ps1", self.deactivate_ps1()),
+            ("activate.sh", self.bash()),
+            ("deactivate.sh", self.deactivate_bash()),
+        ]
+    }
+
+    fn bat(&self) -> String {
+        let join = |dirs: &[PathBuf]| dirs.iter().map(|dir| dir.display().to_string()).collect::<Vec<_>>().join(";");
+        let mut script = String::from("@echo off\r\nrem Generated by rs-easy-installer-windows. Undo with deactivate.bat.\r\n");
+        script += &format!("if defined {} goto :eof\r\n", ACTIVE);
+        for name in self.names() {
+            script += &format!("set \"_RS_EASY_OLD_{0}=%{0}%\"\r\n", name);
+        }
+        script += &format!("set \"PATH={};%PATH%;{}\"\r\n", join(&self.prepend), join(&self.append));
+        script += &format!("set \"PKG_CONFIG_PATH={}\"\r\n", join(&self.pkg_config));
+        for (name, value) in &self.vars {
+            script += &format!("set \"{}={}\"\r\n", name, value);
+        }
+        script += &format!("set \"{}=1\"\r\n", ACTIVE);
+        script
+    }
+
+    fn deactivate_bat(&self) -> String {
+        let mut script = String::from("@echo off\r\nrem Generated by rs-easy-installer-windows.\r\n");
+        script += &format!("if not defined {} goto :eof\r\n", ACTIVE);
+        for name in self.names() {
+            script += &format!("set \"{0}=%_RS_EASY_OLD_{0}%\"\r\nset \"_RS_EASY_OLD_{0}=\"\r\n", name);
+        }
+        script += &format!("set \"{}=\"\r\n", ACTIVE);
+        script
+    }
+
+    fn ps1(&self) -> String {
+        let join = |dirs: &[PathBuf]| {
+            let dirs: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+            ps_quote(&dirs.join(";"))
+        };
+        let mut script = String::from("# Generated by rs-easy-installer-windows. Undo with deactivate.ps1.\n");
+        script += &format!("if ($env:{}) {{ return }}\n", ACTIVE);
+        for name in self.names() {
+            script += &format!("$env:_RS_EASY_OLD_{0} = $env:{0}\n", name);
+        }
+        script += &format!("$env:PATH = {} + ';' + $env:PATH + ';' + {}\n", join(&self.prepend), join(&self.append));
+        script += &format!("$env:PKG_CONFIG_PATH = {}\n", join(&self.pkg_config));
+        for (name, value) in &self.vars {
+            script += &format!("$env:{} = {}\n", name, ps_quote(value));
+        }
+        script += &format!("$env:{} = '1'\n", ACTIVE);
+        script
+    }
+
+    fn deactivate_ps1(&self) -> String {
+        let mut script = String::from("# Generated by rs-easy-installer-windows.\n");
+        script += &format!("if (-not $env:{}) {{ return }}\n", ACTIVE);
+        // Assigning $null removes a variable that was unset before activation
+        for name in self.names() {
+            script += &format!("$env:{0} = $env:_RS_EASY_OLD_{0}\n$env:_RS_EASY_OLD_{0} = $null\n", name);
+        }
+        script += &format!("$env:{} = $null\n", ACTIVE);
+        script
+    }
+
+    /// For `source` from the MSYS2 `~/.bashrc`; PATH-like values use MSYS
+    /// paths, which MSYS2 converts back for native programs.
+    fn bash(&self) -> String {
+        let join = |dirs: &[PathBuf]| {
+            let dirs: Vec<String> = dirs.iter().map(|dir| to_msys_path(dir)).collect();
+            sh_quote(&dirs.join(":"))
+        };
+        let mut script = String::from("# Generated by rs-easy-installer-windows. Undo with deactivate.sh.\n");
+        script += &format!("if [ -z \"${{{}:-}}\" ]; then\n", ACTIVE);
+        for name in self.names() {
+            // `${NAME+set}` tells an empty variable from an unset one
+            script += &format!(
+                "    if [ -n \"${{{0}+set}}\" ]; then export _RS_EASY_OLD_{0}=\"${0}\"; fi\n",
+                name
+            );
+        }
+        script += &format!("    export PATH={}:\"$PATH\":{}\n", join(&self.prepend), join(&self.append));
+        script += &format!("    export PKG_CONFIG_PATH={}\n", join(&self.pkg_config));
+        for (name, value) in &self.vars {
+            script += &format!("    export {}={}\n", name, sh_quote(value));
+        }
+        script += &format!("    export {}=1\nfi\n", ACTIVE);
+        script
+    }
+
+    fn deactivate_bash(&self) -> String {
+        let mut script = String::from("# Generated by rs-easy-installer-windows.\n");
+        script += &format!("if [ -n \"${{{}:-}}\" ]; then\n", ACTIVE);
+        for name in self.names() {
+            script += &format!(
+                "    if [ -n \"${{_RS_EASY_OLD_{0}+set}}\" ]; then export {0}=\"$_RS_EASY_OLD_{0}\"; else unset {0}; fi\n    unset _RS_EASY_OLD_{0}\n",
+                name
+            );
+        }
+        script += &format!("    unset {}\nfi\n", ACTIVE);
+        script
+    }
+}
+
+/// The line to add to the MSYS2 `~/.bashrc` for `activate.sh` in `dir`.
+pub fn bashrc_line(dir: &Path) -> String {
+    format!("source {}", sh_quote(&to_msys_path(&dir.join("activate.sh"))))
+}
+
+fn ps_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activation() -> Activation {
+        let manifest = Manifest::parse("[rust]\ntargets = [\"i686-pc-windows-gnu\"]\n").unwrap();
+        Activation::new(&manifest, Path::new("C:\\msys64"), Path::new("C:\\Users\\o'neil\\.cargo"))
+    }
+
+    #[test]
+    fn test_activation_environment() {
+        let activation = activation();
+        let root = Path::new("C:\\msys64");
+        assert_eq!(
+            activation.prepend,
+            [
+                Path::new("C:\\Users\\o'neil\\.cargo").join("bin"),
+                root.join("mingw64").join("bin"),
+                root.join("mingw32").join("bin")
+            ]
+        );
+        assert_eq!(activation.pkg_config[0], root.join("mingw64").join("lib").join("pkgconfig"));
+        assert_eq!(activation.vars[0], ("CC_x86_64_pc_windows_gnu".to_string(), "x86_64-w64-mingw32-gcc".to_string()));
+        assert_eq!(activation.vars.len(), 4);
+    }
+
+    #[test]
+    fn test_scripts_set_and_restore_the_same_variables() {
+        let scripts = activation().scripts();
+        let script = |name: &str| &scripts.iter().find(|(file, _)| *file == name).unwrap().1;
+
+        let bat = script("activate.bat");
+        assert!(bat.contains("set \"_RS_EASY_OLD_CC_i686_pc_windows_gnu=%CC_i686_pc_windows_gnu%\"\r\n"));
+        assert!(bat.contains("set \"CXX_x86_64_pc_windows_gnu=x86_64-w64-mingw32-g++\"\r\n"));
+        assert!(script("deactivate.bat").contains("set \"PATH=%_RS_EASY_OLD_PATH%\"\r\n"));
+
+        let ps1 = script("activate.ps1");
+        assert!(ps1.contains("$env:PATH = 'C:\\Users\\o''neil\\.cargo"), "{}", ps1);
+        assert!(script("deactivate.ps1").contains("$env:PKG_CONFIG_PATH = $env:_RS_EASY_OLD_PKG_CONFIG_PATH\n"));
+
+        let bash = script("activate.sh");
+        assert!(bash.contains("export PKG_CONFIG_PATH='/c/msys64/mingw64/lib/pkgconfig:/c/msys64/mingw64/share/pkgconfig'"), "{}", bash);
+        assert!(bash.contains(":\"$PATH\":'/c/msys64/usr/bin'\n"), "{}", bash);
+        assert!(script("deactivate.sh").contains("else unset CC_x86_64_pc_windows_gnu; fi\n"));
+    }
+
+    #[test]
+    fn test_bashrc_line() {
+        assert_eq!(bashrc_line(Path::new("C:\\src\\app\\.cargo")), "source '/c/src/app/.cargo/activate.sh'");
+    }
+}
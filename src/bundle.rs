@@ -0,0 +1,737 @@
+//! Building offline bundles on a connected machine.
+//!
+//! `bundle` downloads the installers, the mingw-w64 packages installed by the
+//! GNU toolchain step together with their dependency closure, and the rustup
+//! toolchain archives into the layout described in [`crate::offline`], then
+//! writes the manifest and checks the result the same way `--offline` will.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::download::{Downloader, Sources};
+use crate::error::InstallError;
+use crate::manifest::{Manifest, RustSection};
+use crate::msys2::Environment;
+use crate::offline::{self, Bundle, MSYS2_INSTALLER, PACKAGES_DIR, RUSTUP_DIST_DIR, RUSTUP_INIT};
+use crate::runner::CommandRunner;
+use crate::verify::{self, VerifyPolicy};
+
+/// Prefix of the archive URLs in official channel manifests; rustup swaps it
+/// for `RUSTUP_DIST_SERVER`, so archives are stored under the same path.
+const OFFICIAL_DIST_SERVER: &str = "https://static.rust-lang.org/";
+
+/// Everything `bundle` needs from the outside world.
+pub struct Fetcher<'a> {
+    pub downloader: &'a Downloader,
+    pub runner: &'a dyn CommandRunner,
+    pub sources: &'a Sources,
+    pub verify: &'a VerifyPolicy,
+    /// What the offline install will set up.
+    pub install: &'a Manifest,
+}
+
+/// Build a bundle at `output`: a directory, or a `.zip` file assembled in a
+/// staging directory next to it.
+pub fn create(fetcher: &Fetcher, output: &Path) -> Result<(), InstallError> {
+    let is_zip = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    if !is_zip {
+        return fill(fetcher, output);
+    }
+
+    let staging = output.with_extension("");
+    fill(fetcher, &staging)?;
+    say!("🗜️  Writing {}...", output.display());
+    zip_dir(&staging, output).map_err(|e| InstallError::Other(format!("Cannot write {}: {}", output.display(), e)))?;
+    fs::remove_dir_all(&staging)?;
+    Ok(())
+}
+
+fn fill(fetcher: &Fetcher, dir: &Path) -> Result<(), InstallError> {
+    fs::create_dir_all(dir)?;
+
+    say!("📥 MSYS2 installer");
+    fetch_installer(
+        fetcher,
+        &fetcher.sources.msys2_installer,
+        &dir.join(MSYS2_INSTALLER),
+        fetcher.verify.msys2_sha256.as_deref(),
+        "--msys2-sha256",
+    )?;
+
+    say!("📥 rustup installer");
+    fetch_installer(
+        fetcher,
+        &fetcher.sources.rustup_init,
+        &dir.join(RUSTUP_INIT),
+        fetcher.verify.rustup_sha256.as_deref(),
+        "--rustup-sha256",
+    )?;
+
+    say!("📦 mingw-w64 packages");
+    fetch_packages(fetcher, &dir.join(PACKAGES_DIR))?;
+
+    say!("🦀 Rust toolchain");
+    fetch_toolchain(fetcher, &dir.join(RUSTUP_DIST_DIR))?;
+
+    offline::write_manifest(dir)?;
+    Bundle::open(dir, fetcher.install).map_err(InstallError::Other)?;
+    say!("✅ Bundle written to {}", dir.display());
+    Ok(())
+}
+
+fn fetch_installer(
+    fetcher: &Fetcher,
+    url: &str,
+    dest: &Path,
+    pinned: Option<&str>,
+    pin_flag: &str,
+) -> Result<(), InstallError> {
+    say!("Downloading from: {}", url);
+    download(fetcher, url, dest)?;
+    // Signatures stay in the bundle so `--offline --verify-signatures` works
+    verify::verify_download(
+        fetcher.downloader,
+        fetcher.runner,
+        url,
+        dest,
+        pinned,
+        pin_flag,
+        fetcher.verify.signature.as_ref(),
+    )?;
+    Ok(())
+}
+
+/// Download `url` to `dest`.
+fn download(fetcher: &Fetcher, url: &str, dest: &Path) -> Result<(), InstallError> {
+    fetcher.downloader.download(url, dest).map_err(|e| InstallError::Download {
+        url: url.to_string(),
+        reason: e.to_string(),
+    })?;
+    Ok(())
+}
+
+/// Download `url` to `dest` and check it against `sha256`.
+fn fetch_checked(fetcher: &Fetcher, url: &str, dest: &Path, sha256: &str) -> Result<(), InstallError> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    download(fetcher, url, dest)?;
+    if let Err(reason) = verify::check_sha256(dest, sha256) {
+        let _ = fs::remove_file(dest);
+        return Err(InstallError::Checksum {
+            path: dest.to_path_buf(),
+            reason,
+        });
+    }
+    Ok(())
+}
+
+fn fetch_packages(fetcher: &Fetcher, dir: &Path) -> Result<(), InstallError> {
+    let install = fetcher.install;
+    fs::create_dir_all(dir)?;
+
+    // Each environment is its own pacman repository; extra packages go to
+    // the one their prefix names, anything else to the primary environment
+    for env in install.environments() {
+        let toolchain = env.toolchain_packages();
+        let extra = install.msys2.packages.iter().filter(|package| {
+            install.package_environment(package).unwrap_or(install.msys2.env) == env
+        });
+        let targets: Vec<&str> = toolchain
+            .iter()
+            .map(|(_, package)| package.as_str())
+            .chain(extra.map(String::as_str))
+            .collect();
+        fetch_repository(fetcher, env, &targets, dir)?;
+    }
+    Ok(())
+}
+
+fn fetch_repository(
+    fetcher: &Fetcher,
+    env: Environment,
+    targets: &[&str],
+    dir: &Path,
+) -> Result<(), InstallError> {
+    let repo = format!("{}/{}", fetcher.sources.mingw_mirror.trim_end_matches('/'), env.name());
+
+    // The sync database is only needed to resolve dependencies
+    let database_path = dir.join(format!("{}.db", env.name()));
+    download(fetcher, &format!("{}/{}.db", repo, env.name()), &database_path)?;
+    let database = fs::read(&database_path)?;
+    fs::remove_file(&database_path)?;
+
+    let packages = read_database(&database).map_err(InstallError::Other)?;
+    let closure = resolve(&packages, targets).map_err(InstallError::Other)?;
+    say!("   {}: {} packages including dependencies", env, closure.len());
+
+    for package in closure {
+        say!("   {}", package.filename);
+        fetch_checked(
+            fetcher,
+            &format!("{}/{}", repo, package.filename),
+            &dir.join(&package.filename),
+            &package.sha256,
+        )?;
+    }
+    Ok(())
+}
+
+fn fetch_toolchain(fetcher: &Fetcher, dir: &Path) -> Result<(), InstallError> {
+    let dist = fetcher.sources.rust_dist.trim_end_matches('/');
+    let install = fetcher.install;
+    let channel_manifest = install.channel_manifest();
+    let channel_url = format!("{}/{}", dist, channel_manifest);
+    let channel_path = local_path(dir, &channel_manifest);
+
+    // rustup checks the channel manifest against its .sha256, so keep both
+    let sidecar_url = format!("{}.sha256", channel_url);
+    let sidecar = fetcher.downloader.fetch_string(&sidecar_url).map_err(|e| InstallError::Download {
+        url: sidecar_url.clone(),
+        reason: e.to_string(),
+    })?;
+    let digest = verify::parse_sidecar(&sidecar).ok_or_else(|| InstallError::Checksum {
+        path: channel_path.clone(),
+        reason: format!("{} does not contain a SHA-256 checksum", sidecar_url),
+    })?;
+    fetch_checked(fetcher, &channel_url, &channel_path, &digest)?;
+    fs::write(local_path(dir, &format!("{}.sha256", channel_manifest)), sidecar)?;
+
+    let channel = fs::read_to_string(&channel_path)?;
+    let archives = toolchain_archives(&channel, install.msys2.env.rust_target(), &install.rust).map_err(InstallError::Other)?;
+    for archive in archives {
+        let relative = archive.url.strip_prefix(OFFICIAL_DIST_SERVER).ok_or_else(|| {
+            InstallError::Other(format!("{} is not on {}", archive.url, OFFICIAL_DIST_SERVER))
+        })?;
+        say!("   {}", relative);
+        fetch_checked(fetcher, &format!("{}/{}", dist, relative), &local_path(dir, relative), &archive.sha256)?;
+    }
+    Ok(())
+}
+
+/// `dir` joined with a `/`-separated relative path.
+fn local_path(dir: &Path, relative: &str) -> std::path::PathBuf {
+    relative.split('/').fold(dir.to_path_buf(), |path, part| path.join(part))
+}
+
+/// One package from a pacman sync database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoPackage {
+    pub name: String,
+    pub filename: String,
+    pub sha256: String,
+    pub depends: Vec<String>,
+    pub provides: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+/// Parse a `desc` entry: `%FIELD%` headers followed by one value per line.
+fn parse_desc(text: &str) -> RepoPackage {
+    let mut package = RepoPackage::default();
+    let mut field = "";
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.starts_with('%') && line.ends_with('%') {
+            field = line;
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let value = line.to_string();
+        match field {
+            "%NAME%" => package.name = value,
+            "%FILENAME%" => package.filename = value,
+            "%SHA256SUM%" => package.sha256 = value,
+            "%DEPENDS%" => package.depends.push(value),
+            "%PROVIDES%" => package.provides.push(value),
+            "%GROUPS%" => package.groups.push(value),
+            _ => {}
+        }
+    }
+    package
+}
+
+/// Read every package from a sync database (a tar archive, optionally
+/// compressed with gzip or zstd).
+pub fn read_database(bytes: &[u8]) -> Result<Vec<RepoPackage>, String> {
+    let reader: Box<dyn Read + '_> = match bytes {
+        [0x1f, 0x8b, ..] => Box::new(flate2::read::GzDecoder::new(bytes)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(
+            ruzstd::decoding::StreamingDecoder::new(bytes)
+                .map_err(|e| format!("Cannot read package database: {}", e))?,
+        ),
+        _ => Box::new(bytes),
+    };
+
+    let error = |e: io::Error| format!("Cannot read package database: {}", e);
+    let mut archive = tar::Archive::new(reader);
+    let mut packages = Vec::new();
+    for entry in archive.entries().map_err(error)? {
+        let mut entry = entry.map_err(error)?;
+        if !entry.path().map_err(error)?.ends_with("desc") {
+            continue;
+        }
+        let mut text = String::new();
+        entry.read_to_string(&mut text).map_err(error)?;
+        packages.push(parse_desc(&text));
+    }
+    Ok(packages)
+}
+
+/// `name` from a dependency such as `name>=1.2` or `name=1.2-1`.
+fn dependency_name(dependency: &str) -> &str {
+    dependency
+        .split(['<', '>', '='])
+        .next()
+        .unwrap_or(dependency)
+}
+
+/// `targets` (package or group names) and everything they depend on.
+pub fn resolve<'a>(packages: &'a [RepoPackage], targets: &[&str]) -> Result<Vec<&'a RepoPackage>, String> {
+    let mut by_name: BTreeMap<&str, &RepoPackage> = BTreeMap::new();
+    let mut providers: BTreeMap<&str, &RepoPackage> = BTreeMap::new();
+    for package in packages {
+        by_name.insert(&package.name, package);
+        for provided in &package.provides {
+            providers.entry(dependency_name(provided)).or_insert(package);
+        }
+    }
+
+    let mut queue: VecDeque<&RepoPackage> = VecDeque::new();
+    for target in targets {
+        let group: Vec<&RepoPackage> = packages
+            .iter()
+            .filter(|package| package.groups.iter().any(|group| group == target))
+            .collect();
+        match by_name.get(target) {
+            Some(package) => queue.push_back(package),
+            None if !group.is_empty() => queue.extend(group),
+            None => return Err(format!("{} is not in the package repository", target)),
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut closure = Vec::new();
+    while let Some(package) = queue.pop_front() {
+        if !seen.insert(package.name.as_str()) {
+            continue;
+        }
+        closure.push(package);
+        for dependency in &package.depends {
+            let name = dependency_name(dependency);
+            let found = by_name.get(name).or_else(|| providers.get(name)).ok_or_else(|| {
+                format!(
+                    "{} (needed by {}) is not in the package repository",
+                    dependency, package.name
+                )
+            })?;
+            queue.push_back(found);
+        }
+    }
+
+    closure.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(closure)
+}
+
+/// An archive rustup downloads when installing a toolchain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub url: String,
+    pub sha256: String,
+}
+
+/// Archives rustup downloads to install `rust` (profile, extra components
+/// and extra targets) for `host`, read from a `channel-rust-*.toml` manifest.
+pub fn toolchain_archives(channel: &str, host: &str, rust: &RustSection) -> Result<Vec<Archive>, String> {
+    let manifest: toml::Table = channel
+        .parse()
+        .map_err(|e| format!("Cannot parse channel manifest: {}", e))?;
+    let missing = |what: &str| format!("Channel manifest has no {}", what);
+
+    let profile: Vec<&str> = manifest
+        .get("profiles")
+        .and_then(|profiles| profiles.get(&rust.profile))
+        .and_then(|profile| profile.as_array())
+        .ok_or_else(|| missing(&format!("{} profile", rust.profile)))?
+        .iter()
+        .filter_map(|component| component.as_str())
+        .collect();
+    let renamed = |pkg: &str| -> String {
+        manifest
+            .get("renames")
+            .and_then(|renames| renames.get(pkg))
+            .and_then(|rename| rename.get("to"))
+            .and_then(|to| to.as_str())
+            .unwrap_or(pkg)
+            .to_string()
+    };
+
+    let pkgs = manifest.get("pkg").ok_or_else(|| missing("packages"))?;
+    let rust_pkg = pkgs
+        .get("rust")
+        .and_then(|rust| rust.get("target"))
+        .and_then(|targets| targets.get(host))
+        .ok_or_else(|| missing(&format!("rust package for {}", host)))?;
+
+    let mut archives = Vec::new();
+    let mut found_components = Vec::new();
+    let mut found_targets = Vec::new();
+    for list in ["components", "extensions"] {
+        for component in rust_pkg.get(list).and_then(|c| c.as_array()).into_iter().flatten() {
+            let (Some(pkg), Some(target)) = (
+                component.get("pkg").and_then(|p| p.as_str()),
+                component.get("target").and_then(|t| t.as_str()),
+            ) else {
+                continue;
+            };
+            let name = renamed(pkg);
+            let for_host = target == host || target == "*";
+            let extra_component = for_host && rust.components.contains(&name);
+            let extra_target = pkg == "rust-std" && rust.targets.iter().any(|t| t == target);
+            // rustup always installs the MinGW runtime for windows-gnu hosts
+            let in_profile = for_host && (profile.contains(&name.as_str()) || pkg == "rust-mingw");
+            if !(in_profile || extra_component || extra_target) {
+                continue;
+            }
+            if extra_component {
+                found_components.push(name.clone());
+            }
+            if extra_target {
+                found_targets.push(target.to_string());
+            }
+
+            let entry = pkgs
+                .get(pkg)
+                .and_then(|p| p.get("target"))
+                .and_then(|targets| targets.get(target))
+                .filter(|entry| entry.get("available").and_then(|a| a.as_bool()) == Some(true))
+                .ok_or_else(|| format!("{} is not available for {}", pkg, target))?;
+            let field = |name: &str| entry.get(name).and_then(|v| v.as_str()).map(str::to_string);
+            let archive = match (field("xz_url"), field("xz_hash")) {
+                (Some(url), Some(sha256)) => Archive { url, sha256 },
+                _ => Archive {
+                    url: field("url").ok_or_else(|| missing(&format!("URL for {}", pkg)))?,
+                    sha256: field("hash").ok_or_else(|| missing(&format!("hash for {}", pkg)))?,
+                },
+            };
+            if !archives.contains(&archive) {
+                archives.push(archive);
+            }
+        }
+    }
+
+    if let Some(component) = rust.components.iter().find(|c| !found_components.contains(c)) {
+        return Err(format!("Component {} is not available for {}", component, host));
+    }
+    if let Some(target) = rust.targets.iter().find(|t| !found_targets.contains(t)) {
+        return Err(format!("Target {} is not available on this channel", target));
+    }
+    Ok(archives)
+}
+
+/// Write every file under `dir` into the zip archive `dest`. The archives
+/// inside are already compressed, so entries are stored as-is.
+fn zip_dir(dir: &Path, dest: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    use zip::write::SimpleFileOptions;
+
+    let mut writer = zip::ZipWriter::new(File::create(dest)?);
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let mut entries: Vec<_> = fs::read_dir(&current)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let name = path
+                .strip_prefix(dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let options = SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored)
+                .large_file(entry.metadata()?.len() >= u32::MAX as u64);
+            writer.start_file(name, options)?;
+            io::copy(&mut File::open(&path)?, &mut writer)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::FakeRunner;
+    use crate::testutil::{Faults, TestServer, fake_bundle, scratch_dir, silenced};
+    use sha2::{Digest, Sha256};
+    use std::time::Duration;
+
+    const HOST: &str = "x86_64-pc-windows-gnu";
+
+    fn sha256_of(bytes: &[u8]) -> String {
+        verify::hex(&Sha256::digest(bytes))
+    }
+
+    fn package(name: &str, depends: &[&str]) -> RepoPackage {
+        RepoPackage {
+            name: name.to_string(),
+            filename: format!("{}-1.0-1-any.pkg.tar.zst", name),
+            sha256: sha256_of(name.as_bytes()),
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            ..RepoPackage::default()
+        }
+    }
+
+    /// The mingw64 toolchain packages, with a group and a provider.
+    fn repository() -> Vec<RepoPackage> {
+        let mut gcc = package("mingw-w64-x86_64-gcc", &["mingw-w64-x86_64-gcc-libs=1.0-1", "mingw-w64-x86_64-libwinpthread"]);
+        gcc.groups.push("mingw-w64-x86_64-toolchain".to_string());
+        let mut binutils = package("mingw-w64-x86_64-binutils", &[]);
+        binutils.groups.push("mingw-w64-x86_64-toolchain".to_string());
+        let mut winpthreads = package("mingw-w64-x86_64-winpthreads-git", &[]);
+        winpthreads.provides.push("mingw-w64-x86_64-libwinpthread=12.0".to_string());
+        vec![
+            gcc,
+            binutils,
+            winpthreads,
+            package("mingw-w64-x86_64-gcc-libs", &[]),
+            package("mingw-w64-x86_64-cmake", &["mingw-w64-x86_64-gcc-libs>=1.0"]),
+            package("mingw-w64-x86_64-pkgconf", &[]),
+            package("mingw-w64-x86_64-openssl", &[]),
+            package("mingw-w64-x86_64-make", &[]),
+            package("mingw-w64-x86_64-python", &[]),
+        ]
+    }
+
+    fn desc(package: &RepoPackage) -> String {
+        let mut text = format!(
+            "%FILENAME%\n{}\n\n%NAME%\n{}\n\n%SHA256SUM%\n{}\n\n",
+            package.filename, package.name, package.sha256
+        );
+        for (field, values) in [
+            ("GROUPS", &package.groups),
+            ("DEPENDS", &package.depends),
+            ("PROVIDES", &package.provides),
+        ] {
+            if !values.is_empty() {
+                text.push_str(&format!("%{}%\n{}\n\n", field, values.join("\n")));
+            }
+        }
+        text
+    }
+
+    /// gzip-compressed sync database for `packages`.
+    fn database(packages: &[RepoPackage]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for package in packages {
+            let text = desc(package);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("{}-1.0-1/desc", package.name), text.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn channel(archives: &[(&str, &str)]) -> String {
+        let mut text = String::from("manifest-version = \"2\"\n\n[pkg.rust.target.x86_64-pc-windows-gnu]\navailable = true\ncomponents = [\n");
+        for (pkg, _) in archives {
+            text.push_str(&format!("  {{ pkg = \"{}\", target = \"x86_64-pc-windows-gnu\" }},\n", pkg));
+        }
+        text.push_str("]\nextensions = [{ pkg = \"rust-src\", target = \"*\" }]\n\n");
+        for (pkg, body) in archives {
+            text.push_str(&format!(
+                "[pkg.{0}.target.x86_64-pc-windows-gnu]\navailable = true\nxz_url = \"https://static.rust-lang.org/dist/2024-09-05/{0}-1.81.0-x86_64-pc-windows-gnu.tar.xz\"\nxz_hash = \"{1}\"\n\n",
+                pkg,
+                sha256_of(body.as_bytes())
+            ));
+        }
+        text.push_str("[renames.clippy-preview]\nto = \"clippy\"\n\n[profiles]\ndefault = [\"rustc\", \"cargo\", \"rust-std\", \"clippy\"]\nminimal = [\"rustc\"]\n");
+        text
+    }
+
+    #[test]
+    fn test_resolve_expands_groups_and_dependencies() {
+        let packages = repository();
+        let toolchain = Environment::Mingw64.toolchain_packages();
+        let targets: Vec<&str> = toolchain.iter().map(|(_, package)| package.as_str()).collect();
+
+        let names: Vec<&str> = resolve(&packages, &targets)
+            .unwrap()
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "mingw-w64-x86_64-binutils",
+                "mingw-w64-x86_64-cmake",
+                "mingw-w64-x86_64-gcc",
+                "mingw-w64-x86_64-gcc-libs",
+                "mingw-w64-x86_64-make",
+                "mingw-w64-x86_64-openssl",
+                "mingw-w64-x86_64-pkgconf",
+                "mingw-w64-x86_64-winpthreads-git",
+            ]
+        );
+
+        let err = resolve(&packages[..1], &["mingw-w64-x86_64-gcc"]).unwrap_err();
+        assert!(err.contains("mingw-w64-x86_64-gcc-libs=1.0-1 (needed by mingw-w64-x86_64-gcc)"));
+    }
+
+    #[test]
+    fn test_read_database() {
+        let packages = repository();
+        assert_eq!(read_database(&database(&packages)).unwrap(), packages);
+    }
+
+    #[test]
+    fn test_toolchain_archives_follow_default_profile() {
+        let text = channel(&[("rustc", "r"), ("rust-docs", "d"), ("rust-mingw", "m"), ("clippy-preview", "c")]);
+
+        let rust = RustSection::default();
+        let urls: Vec<String> = toolchain_archives(&text, HOST, &rust)
+            .unwrap()
+            .into_iter()
+            .map(|archive| archive.url.rsplit('/').next().unwrap().to_string())
+            .collect();
+
+        assert_eq!(
+            urls,
+            vec![
+                "rustc-1.81.0-x86_64-pc-windows-gnu.tar.xz",
+                "rust-mingw-1.81.0-x86_64-pc-windows-gnu.tar.xz",
+                "clippy-preview-1.81.0-x86_64-pc-windows-gnu.tar.xz",
+            ]
+        );
+        assert!(toolchain_archives(&text, "aarch64-pc-windows-msvc", &rust).is_err());
+
+        let text = text
+            + "[pkg.rust-src.target.\"*\"]\navailable = true\nxz_url = \"https://static.rust-lang.org/dist/2024-09-05/rust-src-1.81.0.tar.xz\"\nxz_hash = \"00\"\n";
+        let mut extras = RustSection {
+            components: vec!["rust-src".to_string()],
+            ..RustSection::default()
+        };
+        let archives = toolchain_archives(&text, HOST, &extras).unwrap();
+        assert!(archives.last().unwrap().url.ends_with("/rust-src-1.81.0.tar.xz"));
+
+        extras.targets.push("wasm32-unknown-unknown".to_string());
+        let err = toolchain_archives(&text, HOST, &extras).unwrap_err();
+        assert_eq!(err, "Target wasm32-unknown-unknown is not available on this channel");
+    }
+
+    #[test]
+    fn test_create_bundle_from_local_mirror() {
+        let packages = repository();
+        let db = database(&packages);
+        let channel = channel(&[("rustc", "rustc archive"), ("cargo", "cargo archive")]);
+        let channel_sha256 = format!("{}  channel-rust-stable.toml\n", sha256_of(channel.as_bytes()));
+        let installer_sha256 = sha256_of(b"installer");
+
+        let mut files: Vec<(String, Vec<u8>)> = vec![
+            ("/msys2-x86_64-latest.exe".to_string(), b"installer".to_vec()),
+            ("/msys2-x86_64-latest.exe.sha256".to_string(), installer_sha256.into_bytes()),
+            ("/rustup-init.exe".to_string(), b"rustup-init".to_vec()),
+            ("/mingw/mingw64/mingw64.db".to_string(), db),
+            ("/rust/dist/channel-rust-stable.toml".to_string(), channel.into_bytes()),
+            ("/rust/dist/channel-rust-stable.toml.sha256".to_string(), channel_sha256.into_bytes()),
+        ];
+        for package in &packages {
+            files.push((format!("/mingw/mingw64/{}", package.filename), package.name.clone().into_bytes()));
+        }
+        for pkg in ["rustc", "cargo"] {
+            files.push((
+                format!("/rust/dist/2024-09-05/{}-1.81.0-x86_64-pc-windows-gnu.tar.xz", pkg),
+                format!("{} archive", pkg).into_bytes(),
+            ));
+        }
+        let served: Vec<(&str, &[u8])> = files.iter().map(|(path, body)| (path.as_str(), body.as_slice())).collect();
+        let server = TestServer::start(&served, Faults::default());
+
+        let downloader = Downloader::new(None).with_retries(0, Duration::ZERO);
+        let sources = Sources::from_base(&server.base_url);
+        let verify = VerifyPolicy {
+            rustup_sha256: Some(sha256_of(b"rustup-init")),
+            ..VerifyPolicy::default()
+        };
+        let fetcher = Fetcher {
+            downloader: &downloader,
+            runner: &FakeRunner::new(),
+            sources: &sources,
+            verify: &verify,
+            install: &Manifest::default(),
+        };
+        let dir = scratch_dir("bundle-create").join("bundle");
+
+        silenced(|| create(&fetcher, &dir)).unwrap();
+
+        let bundle = Bundle::open(&dir, &Manifest::default()).unwrap();
+        assert!(bundle.digest("pacman/mingw-w64-x86_64-winpthreads-git-1.0-1-any.pkg.tar.zst").is_some());
+        assert!(bundle.digest("pacman/mingw-w64-x86_64-python-1.0-1-any.pkg.tar.zst").is_none());
+        assert!(bundle.digest("rustup/dist/2024-09-05/cargo-1.81.0-x86_64-pc-windows-gnu.tar.xz").is_some());
+        assert!(!dir.join("pacman").join("mingw64.db").exists());
+    }
+
+    #[test]
+    fn test_failures_carry_their_exit_code() {
+        let downloader = Downloader::new(None).with_retries(0, Duration::ZERO);
+        let runner = FakeRunner::new();
+        let verify = VerifyPolicy::default();
+        let manifest = Manifest::default();
+        let build = |files: &[(&str, &[u8])], name: &str| {
+            let server = TestServer::start(files, Faults::default());
+            let sources = Sources::from_base(&server.base_url);
+            let fetcher = Fetcher {
+                downloader: &downloader,
+                runner: &runner,
+                sources: &sources,
+                verify: &verify,
+                install: &manifest,
+            };
+            create(&fetcher, &scratch_dir(name).join("bundle")).unwrap_err()
+        };
+
+        let err = build(&[], "bundle-unreachable");
+        assert!(matches!(&err, InstallError::Download { url, .. } if url.ends_with("/msys2-x86_64-latest.exe")), "{:?}", err);
+        assert_eq!(err.exit_code(), 5);
+
+        let wrong = sha256_of(b"something else");
+        let err = build(
+            &[
+                ("/msys2-x86_64-latest.exe", b"installer"),
+                ("/msys2-x86_64-latest.exe.sha256", wrong.as_bytes()),
+            ],
+            "bundle-mismatch",
+        );
+        assert!(matches!(err, InstallError::Checksum { .. }), "{:?}", err);
+        assert_eq!(err.exit_code(), 6);
+    }
+
+    #[test]
+    fn test_zip_dir_keeps_bundle_layout() {
+        let dir = scratch_dir("bundle-zip");
+        let bundle = dir.join("bundle");
+        fs::create_dir(&bundle).unwrap();
+        fake_bundle(&bundle);
+
+        zip_dir(&bundle, &dir.join("bundle.zip")).unwrap();
+
+        let archive = zip::ZipArchive::new(File::open(dir.join("bundle.zip")).unwrap()).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert!(names.contains(&offline::MANIFEST));
+        assert!(names.contains(&"rustup/dist/channel-rust-stable.toml"));
+        assert!(names.contains(&"pacman/mingw-w64-x86_64-gcc-1.0-1-any.pkg.tar.zst"));
+    }
+}
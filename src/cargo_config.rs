@@ -0,0 +1,245 @@
+//! Merging the generated settings into an existing `.cargo/config.toml`.
+//!
+//! Only the keys the installer owns (`[target.<triple>]` linker and ar,
+//! `[build] target` and the `[env]` entries it generates) are inserted or
+//! updated; every other table, key and comment is left exactly as it was.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{DocumentMut, Item, Table, TableLike};
+
+/// Where the cargo configuration goes and how the toolchain is selected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// `<project>/.cargo/config.toml` and `rustup override set`.
+    #[default]
+    Project,
+    /// `%CARGO_HOME%\config.toml` and `rustup default`.
+    User,
+    /// Neither; the toolchain is installed but not selected.
+    None,
+}
+
+impl Scope {
+    pub fn parse(value: &str) -> Option<Scope> {
+        match value {
+            "project" => Some(Scope::Project),
+            "user" => Some(Scope::User),
+            "none" => Some(Scope::None),
+            _ => None,
+        }
+    }
+
+    /// The config file for this scope, if it has one.
+    pub fn config_path(self, project_dir: &Path, cargo_home: &Path) -> Option<PathBuf> {
+        match self {
+            Scope::Project => Some(project_dir.join(".cargo").join("config.toml")),
+            Scope::User => Some(cargo_home.join("config.toml")),
+            Scope::None => None,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Project => "project",
+            Scope::User => "user",
+            Scope::None => "none",
+        })
+    }
+}
+
+/// Cargo's home directory: `CARGO_HOME`, else `.cargo` in the user profile.
+pub fn cargo_home(env: &dyn Fn(&str) -> Option<String>) -> PathBuf {
+    if let Some(home) = env("CARGO_HOME").filter(|home| !home.is_empty()) {
+        return PathBuf::from(home);
+    }
+    let profile = env("USERPROFILE")
+        .or_else(|| env("HOME"))
+        .filter(|profile| !profile.is_empty())
+        .unwrap_or_else(|| ".".to_string());
+    Path::new(&profile).join(".cargo")
+}
+
+/// Merge the `generated` config into `existing`, returning the new contents.
+pub fn merge(existing: &str, generated: &str) -> Result<String, String> {
+    let mut document: DocumentMut = existing.parse().map_err(|e| format!("cannot parse existing config: {}", e))?;
+    let generated: DocumentMut = generated.parse().map_err(|e| format!("cannot parse generated config: {}", e))?;
+    merge_table(document.as_table_mut(), generated.as_table())?;
+    Ok(document.to_string())
+}
+
+fn merge_table(existing: &mut dyn TableLike, generated: &Table) -> Result<(), String> {
+    for (key, item) in generated.iter() {
+        match item {
+            Item::Table(table) => {
+                let entry = existing.entry(key).or_insert_with(|| {
+                    let mut new = Table::new();
+                    new.set_implicit(table.is_implicit());
+                    Item::Table(new)
+                });
+                let entry = entry
+                    .as_table_like_mut()
+                    .ok_or_else(|| format!("`{}` is not a table in the existing config", key))?;
+                merge_table(entry, table)?;
+            }
+            Item::Value(value) => match existing.get_mut(key) {
+                // Keep the key's comments and spacing, replace only the value
+                Some(Item::Value(old)) => {
+                    let decor = old.decor().clone();
+                    *old = value.clone();
+                    *old.decor_mut() = decor;
+                }
+                _ => {
+                    existing.insert(key, item.clone());
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Lines removed (`-`) and added (`+`) going from `old` to `new`.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Longest common subsequence table, filled from the end
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            changes.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+/// First of `config.toml.bak`, `config.toml.bak.1`, ... that does not exist
+/// yet, so the user's original is never overwritten by a later backup.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    let first = PathBuf::from(name);
+    let mut candidate = first.clone();
+    let mut n = 1;
+    while candidate.exists() {
+        let mut name = first.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        candidate = PathBuf::from(name);
+        n += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::scratch_dir;
+
+    const GENERATED: &str = r#"[target.x86_64-pc-windows-gnu]
+linker = "x86_64-w64-mingw32-gcc"
+ar = "x86_64-w64-mingw32-ar"
+
+[build]
+target = "x86_64-pc-windows-gnu"
+
+[env]
+CC_x86_64_pc_windows_gnu = "x86_64-w64-mingw32-gcc"
+"#;
+
+    #[test]
+    fn test_merge_keeps_foreign_settings() {
+        let existing = r#"# Team settings
+[alias]
+b = "build" # short
+
+[build]
+target = "x86_64-pc-windows-msvc"  # was msvc
+jobs = 4
+
+[registries.internal]
+index = "sparse+https://example.com/index/"
+"#;
+        let merged = merge(existing, GENERATED).unwrap();
+
+        assert!(merged.starts_with("# Team settings\n[alias]\nb = \"build\" # short\n"), "{}", merged);
+        assert!(merged.contains("target = \"x86_64-pc-windows-gnu\"  # was msvc\njobs = 4\n"), "{}", merged);
+        assert!(merged.contains("[registries.internal]\nindex = \"sparse+https://example.com/index/\"\n"));
+        assert!(merged.contains("[target.x86_64-pc-windows-gnu]\nlinker = \"x86_64-w64-mingw32-gcc\""));
+        assert!(!merged.contains("\n[target]\n"), "{}", merged);
+        assert!(merged.contains("[env]\nCC_x86_64_pc_windows_gnu"));
+
+        // Merging again changes nothing
+        assert_eq!(merge(&merged, GENERATED).unwrap(), merged);
+    }
+
+    #[test]
+    fn test_merge_into_dotted_and_inline_tables() {
+        let existing = "env = { RUST_LOG = \"debug\" }\ntarget.x86_64-pc-windows-gnu.runner = \"wine\"\n";
+        let merged = merge(existing, GENERATED).unwrap();
+
+        let parsed: toml::Table = toml::from_str(&merged).unwrap();
+        assert_eq!(parsed["env"]["RUST_LOG"].as_str(), Some("debug"));
+        assert_eq!(parsed["env"]["CC_x86_64_pc_windows_gnu"].as_str(), Some("x86_64-w64-mingw32-gcc"));
+        assert_eq!(parsed["target"]["x86_64-pc-windows-gnu"]["runner"].as_str(), Some("wine"));
+        assert_eq!(parsed["target"]["x86_64-pc-windows-gnu"]["ar"].as_str(), Some("x86_64-w64-mingw32-ar"));
+
+        assert!(merge("build = 1\n", GENERATED).unwrap_err().contains("`build` is not a table"));
+        assert!(merge("[build\n", GENERATED).is_err());
+    }
+
+    #[test]
+    fn test_scope_paths() {
+        let project = Path::new("C:\\src\\app");
+        let home = cargo_home(&|name| (name == "USERPROFILE").then(|| "C:\\Users\\dev".to_string()));
+        assert_eq!(home, Path::new("C:\\Users\\dev").join(".cargo"));
+        assert_eq!(
+            cargo_home(&|name| (name == "CARGO_HOME").then(|| "D:\\cargo".to_string())),
+            PathBuf::from("D:\\cargo")
+        );
+
+        assert_eq!(Scope::Project.config_path(project, &home), Some(project.join(".cargo").join("config.toml")));
+        assert_eq!(Scope::User.config_path(project, &home), Some(home.join("config.toml")));
+        assert_eq!(Scope::None.config_path(project, &home), None);
+        assert_eq!(Scope::parse("user"), Some(Scope::User));
+        assert_eq!(Scope::parse("global"), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let changes = diff("a\nb\nc\n", "a\nB\nc\nd\n");
+        assert_eq!(changes, ["-b", "+B", "+d"]);
+        assert!(diff("same\n", "same\n").is_empty());
+    }
+
+    #[test]
+    fn test_backup_path_never_overwrites() {
+        let dir = scratch_dir("config-backup");
+        let config = dir.join("config.toml");
+        assert_eq!(backup_path(&config), dir.join("config.toml.bak"));
+        std::fs::write(dir.join("config.toml.bak"), "").unwrap();
+        assert_eq!(backup_path(&config), dir.join("config.toml.bak.1"));
+    }
+}
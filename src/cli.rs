@@ -0,0 +1,424 @@
+//! Command-line options.
+
+use std::path::PathBuf;
+
+use crate::cargo_config::Scope;
+use crate::msys2::Environment;
+use crate::progress::{Selection, Step};
+use crate::report::Format;
+use crate::verify::{self, SignatureTool, VerifyPolicy};
+
+pub const USAGE: &str = "\
+Usage: rs-easy-installer-windows [COMMAND] [OPTIONS]
+
+Commands:
+  install                   Install MSYS2, the GNU toolchain and Rust, and
+                            configure them (the default)
+  doctor                    Check an existing GNU Rust setup and explain how to
+                            fix what is missing; exits with 1 on failures
+  uninstall                 Undo the changes earlier installations recorded in
+                            the install journal
+  update                    Upgrade the MSYS2 packages and the Rust toolchain
+  status                    Show what is installed and configured
+  config                    Only write the cargo configuration, activation
+                            scripts and user PATH
+  bundle <DIR|FILE.zip>     Download everything an --offline install needs
+                            into a directory or zip file
+
+Global options:
+  -y, --yes                 Answer yes to confirmation prompts
+      --non-interactive     Never wait for input: fail with exit code 3
+                            where a prompt would be needed (the default when
+                            stdin is not a terminal)
+  -v, --verbose             Also print every command that is run
+  -q, --quiet               Print only errors
+      --msys2-root <PATH>   MSYS2 installation root (default: $MSYS2_ROOT, a
+                            detected install, or C:\\msys64)
+      --env <ENV>           MSYS2 environment: mingw64, ucrt64, clang64,
+                            mingw32 or clangarm64 (overrides the manifest)
+      --toolchain <CHANNEL> Rust channel: stable, beta, nightly,
+                            nightly-YYYY-MM-DD or a version like 1.81.0
+                            (overrides the manifest)
+      --manifest <PATH>     Install manifest (default: rs-easy-install.toml in
+                            the current directory, if present)
+      --format <text|json>  Print one JSON event per line (each step, doctor
+                            check or planned action, then a summary) instead
+                            of text
+  -h, --help                Print this help
+
+Install options:
+      --dry-run             Print the plan without changing anything (also
+                            for uninstall, update and config)
+      --download-base <URL> Download installers from this mirror instead of the
+                            official sites
+      --offline <DIR>       Install from a pre-fetched bundle directory without
+                            network access
+      --scope <project|user|none>
+                            Where cargo configuration goes: the project's
+                            .cargo/config.toml with a rustup override
+                            (default), %CARGO_HOME%\\config.toml with rustup
+                            default, or nowhere
+      --project-dir <DIR>   Project for --scope project (default: the current
+                            directory)
+      --no-modify-path      Print the PATH entries to add instead of adding
+                            them to the user PATH
+      --from-step <STEP>    Run this step and the ones after it, even if an
+                            earlier run completed them
+      --only-step <STEP>    Run only this step
+                            Steps: check, msys2, toolchain, rust, configure,
+                            verify. Without either flag an interrupted
+                            installation resumes at its first incomplete step
+
+Verification options:
+      --msys2-sha256 <HEX>  Expected SHA-256 of the MSYS2 installer (default:
+                            the published .sha256 file)
+      --rustup-sha256 <HEX> Expected SHA-256 of rustup-init.exe (default: the
+                            published .sha256 file)
+      --verify-signatures <gpg|minisign>
+                            Also check the installers' detached signatures
+      --minisign-key <KEY>  Public key for --verify-signatures minisign
+
+Exit codes:
+  0 success, 1 other failure, 2 invalid options or manifest, 3 input needed
+  while non-interactive, 4 declined confirmation, 5 download failed,
+  6 checksum or signature mismatch, 7 blocked by PowerShell execution policy,
+  8 pacman failed, 9 rustup failed, 10 verification failed, 11 MSYS2 missing
+  or its installation failed
+";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    #[default]
+    Install,
+    /// Build an offline bundle at this directory or `.zip` path.
+    Bundle { output: PathBuf },
+    /// Reverse what the install journal records.
+    Uninstall,
+    /// Diagnose the current setup without changing it.
+    Doctor,
+    /// Upgrade what is installed.
+    Update,
+    /// Report what is installed.
+    Status,
+    /// Run only the configuration step.
+    Config,
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Install => "install",
+            Command::Bundle { .. } => "bundle",
+            Command::Uninstall => "uninstall",
+            Command::Doctor => "doctor",
+            Command::Update => "update",
+            Command::Status => "status",
+            Command::Config => "config",
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    pub yes: bool,
+    pub non_interactive: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+    pub dry_run: bool,
+    pub msys2_root: Option<PathBuf>,
+    pub download_base: Option<String>,
+    pub offline: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub env: Option<Environment>,
+    pub toolchain: Option<String>,
+    pub scope: Scope,
+    pub project_dir: Option<PathBuf>,
+    pub no_modify_path: bool,
+    pub steps: Selection,
+    pub format: Format,
+    pub verify: VerifyPolicy,
+    pub help: bool,
+}
+
+impl Options {
+    /// Parse the arguments following the program name.
+    pub fn parse<I>(args: I) -> Result<Options, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options::default();
+        let mut command = None;
+        let mut args = args.into_iter();
+        let mut signature_tool = None;
+        let mut minisign_key = None;
+
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", flag))
+            };
+
+            match flag.as_str() {
+                "-y" | "--yes" => options.yes = true,
+                "--non-interactive" => options.non_interactive = true,
+                "-v" | "--verbose" => options.verbose = true,
+                "-q" | "--quiet" => options.quiet = true,
+                "--dry-run" => options.dry_run = true,
+                "--format" => {
+                    let format = value()?;
+                    options.format =
+                        Format::parse(&format).ok_or_else(|| format!("--format expects text or json, not '{}'", format))?;
+                }
+                "--msys2-root" => options.msys2_root = Some(PathBuf::from(value()?)),
+                "--download-base" => options.download_base = Some(value()?),
+                "--offline" => options.offline = Some(PathBuf::from(value()?)),
+                "--manifest" => options.manifest = Some(PathBuf::from(value()?)),
+                "--env" => {
+                    let env = value()?;
+                    options.env = Some(Environment::parse(&env).ok_or_else(|| {
+                        let names: Vec<&str> = Environment::ALL.iter().map(|env| env.name()).collect();
+                        format!("--env expects one of {}, not '{}'", names.join(", "), env)
+                    })?);
+                }
+                "--toolchain" => options.toolchain = Some(value()?),
+                "--scope" => {
+                    let scope = value()?;
+                    options.scope = Scope::parse(&scope)
+                        .ok_or_else(|| format!("--scope expects project, user or none, not '{}'", scope))?;
+                }
+                "--project-dir" => options.project_dir = Some(PathBuf::from(value()?)),
+                "--no-modify-path" => options.no_modify_path = true,
+                "--from-step" | "--only-step" => {
+                    if options.steps != Selection::Resume {
+                        return Err("--from-step and --only-step can only be given once".to_string());
+                    }
+                    let name = value()?;
+                    let step = Step::parse(&name)
+                        .ok_or_else(|| format!("{} expects one of {}, not '{}'", flag, Step::names(), name))?;
+                    options.steps = if flag == "--from-step" { Selection::From(step) } else { Selection::Only(step) };
+                }
+                "--msys2-sha256" => options.verify.msys2_sha256 = Some(sha256_value(&flag, value()?)?),
+                "--rustup-sha256" => options.verify.rustup_sha256 = Some(sha256_value(&flag, value()?)?),
+                "--verify-signatures" => match value()?.as_str() {
+                    tool @ ("gpg" | "minisign") => signature_tool = Some(tool.to_string()),
+                    other => return Err(format!("--verify-signatures expects gpg or minisign, not '{}'", other)),
+                },
+                "--minisign-key" => minisign_key = Some(value()?),
+                "install" | "bundle" | "uninstall" | "doctor" | "update" | "status" | "config" if command.is_none() => {
+                    command = Some(match flag.as_str() {
+                        "bundle" => {
+                            let output =
+                                value().map_err(|_| "bundle requires an output directory or .zip file".to_string())?;
+                            // Options go after the output, so a flag here means it is missing
+                            if output.starts_with('-') {
+                                return Err(format!(
+                                    "bundle expects the output directory or .zip file first, not '{}' \
+                                     (usage: bundle <DIR|FILE.zip> [OPTIONS])",
+                                    output
+                                ));
+                            }
+                            Command::Bundle {
+                                output: PathBuf::from(output),
+                            }
+                        }
+                        "uninstall" => Command::Uninstall,
+                        "doctor" => Command::Doctor,
+                        "update" => Command::Update,
+                        "status" => Command::Status,
+                        "config" => Command::Config,
+                        _ => Command::Install,
+                    });
+                }
+                "-h" | "--help" => options.help = true,
+                _ => return Err(format!("unrecognized argument '{}'", arg)),
+            }
+        }
+        options.command = command.unwrap_or_default();
+
+        options.verify.signature = match (signature_tool.as_deref(), minisign_key) {
+            (Some("gpg"), None) => Some(SignatureTool::Gpg),
+            (Some("gpg"), Some(_)) => return Err("--minisign-key cannot be used with --verify-signatures gpg".to_string()),
+            (None, Some(_)) => return Err("--minisign-key requires --verify-signatures minisign".to_string()),
+            (_, Some(public_key)) => Some(SignatureTool::Minisign { public_key }),
+            (Some(_), None) => return Err("--verify-signatures minisign requires --minisign-key".to_string()),
+            (None, None) => None,
+        };
+
+        if options.offline.is_some() && options.download_base.is_some() {
+            return Err("--offline cannot be combined with --download-base".to_string());
+        }
+        if options.quiet && options.verbose {
+            return Err("--quiet cannot be combined with --verbose".to_string());
+        }
+        if options.project_dir.is_some() && options.scope != Scope::Project {
+            return Err(format!("--project-dir cannot be used with --scope {}", options.scope));
+        }
+        let steps_flag = match options.steps {
+            Selection::From(_) => "--from-step",
+            _ => "--only-step",
+        };
+        // Flags that only mean something to some commands
+        let command = options.command.name();
+        for (set, flag, commands) in [
+            (options.dry_run, "--dry-run", &["install", "uninstall", "update", "config"][..]),
+            (options.offline.is_some(), "--offline", &["install"]),
+            (options.download_base.is_some(), "--download-base", &["install", "bundle"]),
+            (options.steps != Selection::Resume, steps_flag, &["install"]),
+        ] {
+            if set && !commands.contains(&command) {
+                return Err(format!("{} cannot be used with {}", flag, command));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn sha256_value(flag: &str, value: String) -> Result<String, String> {
+    if verify::is_sha256(&value) {
+        Ok(value.to_ascii_lowercase())
+    } else {
+        Err(format!("{} expects a 64-character hex SHA-256, not '{}'", flag, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+        assert!(parse(&["--dry-run"]).unwrap().dry_run);
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(parse(&["--no-modify-path"]).unwrap().no_modify_path);
+        let options = parse(&["-y", "--verbose", "--env", "ucrt64", "--toolchain=nightly"]).unwrap();
+        assert!(options.yes && options.verbose);
+        assert!(parse(&["uninstall", "--non-interactive"]).unwrap().non_interactive);
+        assert_eq!(options.env, Some(Environment::Ucrt64));
+        assert_eq!(options.toolchain.as_deref(), Some("nightly"));
+        assert!(parse(&["--env", "msvc"]).unwrap_err().contains("mingw64, ucrt64, clang64"));
+        assert!(parse(&["-q", "-v"]).is_err());
+        assert_eq!(parse(&["--format", "json"]).unwrap().format, Format::Json);
+        assert!(parse(&["--format=yaml"]).unwrap_err().contains("text or json"));
+        assert_eq!(parse(&["--manifest=team.toml"]).unwrap().manifest, Some(PathBuf::from("team.toml")));
+    }
+
+    #[test]
+    fn test_parse_msys2_root() {
+        let expected = Some(PathBuf::from("D:\\msys64"));
+        assert_eq!(parse(&["--msys2-root", "D:\\msys64"]).unwrap().msys2_root, expected);
+        assert_eq!(parse(&["--msys2-root=D:\\msys64"]).unwrap().msys2_root, expected);
+        assert_eq!(parse(&["--msys2-root"]).unwrap_err(), "--msys2-root requires a value");
+    }
+
+    #[test]
+    fn test_parse_scope() {
+        assert_eq!(parse(&[]).unwrap().scope, Scope::Project);
+        assert_eq!(parse(&["--scope=user"]).unwrap().scope, Scope::User);
+        assert_eq!(parse(&["--scope", "none"]).unwrap().scope, Scope::None);
+        assert!(parse(&["--scope", "global"]).unwrap_err().contains("project, user or none"));
+        assert_eq!(
+            parse(&["--project-dir", "C:\\src\\app"]).unwrap().project_dir,
+            Some(PathBuf::from("C:\\src\\app"))
+        );
+        assert!(parse(&["--scope", "user", "--project-dir", "app"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        assert_eq!(parse(&[]).unwrap().steps, Selection::Resume);
+        assert_eq!(parse(&["--from-step", "rust"]).unwrap().steps, Selection::From(Step::Rust));
+        assert_eq!(parse(&["--only-step=verify"]).unwrap().steps, Selection::Only(Step::Verify));
+        assert!(parse(&["--only-step", "pacman"]).unwrap_err().contains("check, msys2, toolchain"));
+        assert!(parse(&["--from-step", "rust", "--only-step", "verify"]).is_err());
+        assert!(parse(&["uninstall", "--only-step", "verify"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_offline() {
+        assert_eq!(parse(&["--offline", "E:\\bundle"]).unwrap().offline, Some(PathBuf::from("E:\\bundle")));
+        assert!(parse(&["--offline=E:\\bundle", "--download-base", "https://mirror"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_bundle() {
+        let options = parse(&["bundle", "E:\\bundle.zip", "--download-base", "http://mirror"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Bundle {
+                output: PathBuf::from("E:\\bundle.zip")
+            }
+        );
+        assert_eq!(options.download_base.as_deref(), Some("http://mirror"));
+        assert!(parse(&["bundle"]).unwrap_err().contains("output directory"));
+        assert!(parse(&["bundle", "--yes"]).unwrap_err().contains("not '--yes'"));
+        let err = parse(&["bundle", "--download-base", "http://mirror", "out"]).unwrap_err();
+        assert!(err.contains("bundle <DIR|FILE.zip> [OPTIONS]"), "{}", err);
+        assert_eq!(parse(&["uninstall", "--dry-run"]).unwrap().command, Command::Uninstall);
+        assert!(parse(&["uninstall", "--offline", "E:\\bundle"]).is_err());
+        assert!(parse(&["bundle", "out", "--dry-run"]).is_err());
+        assert_eq!(parse(&["doctor", "--msys2-root", "D:\\msys64"]).unwrap().command, Command::Doctor);
+        assert!(parse(&["doctor", "--dry-run"]).unwrap_err().contains("cannot be used with doctor"));
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        for (name, command) in [
+            ("install", Command::Install),
+            ("doctor", Command::Doctor),
+            ("uninstall", Command::Uninstall),
+            ("update", Command::Update),
+            ("status", Command::Status),
+            ("config", Command::Config),
+        ] {
+            // Global flags go before or after the command
+            let options = parse(&["--quiet", name, "--msys2-root", "D:\\msys64"]).unwrap();
+            assert_eq!(options.command, command);
+            assert!(options.quiet);
+            assert_eq!(command.name(), name);
+        }
+        assert_eq!(parse(&["update", "--dry-run"]).unwrap().command, Command::Update);
+        assert!(parse(&["status", "--dry-run"]).unwrap_err().contains("--dry-run cannot be used with status"));
+        assert!(parse(&["config", "--offline", "E:\\bundle"]).is_err());
+        assert!(parse(&["install", "status"]).unwrap_err().contains("unrecognized argument 'status'"));
+    }
+
+    #[test]
+    fn test_parse_verification() {
+        let digest = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let options = parse(&["--msys2-sha256", digest, "--verify-signatures", "gpg"]).unwrap();
+        assert_eq!(options.verify.msys2_sha256, Some(digest.to_ascii_lowercase()));
+        assert_eq!(options.verify.signature, Some(SignatureTool::Gpg));
+
+        let options = parse(&["--verify-signatures=minisign", "--minisign-key", "RWQkey"]).unwrap();
+        assert_eq!(
+            options.verify.signature,
+            Some(SignatureTool::Minisign {
+                public_key: "RWQkey".to_string()
+            })
+        );
+
+        assert!(parse(&["--rustup-sha256", "abc"]).unwrap_err().contains("64-character"));
+        assert!(parse(&["--verify-signatures", "minisign"]).unwrap_err().contains("--minisign-key"));
+        assert!(parse(&["--minisign-key", "RWQkey"]).unwrap_err().contains("--verify-signatures minisign"));
+        assert!(parse(&["--verify-signatures", "pgp"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_argument() {
+        assert_eq!(parse(&["--bogus"]).unwrap_err(), "unrecognized argument '--bogus'");
+    }
+}
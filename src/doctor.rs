@@ -0,0 +1,412 @@
+//! `doctor`: a checklist of what a working GNU Rust setup needs, with a hint
+//! for everything that is missing.
+//!
+//! Only reads: it runs `rustc`, `rustup` and `pkg-config` and looks through
+//! the PATH directories, but changes nothing.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::manifest::Manifest;
+use crate::runner::CommandRunner;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    /// Works, but something may bite later.
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn icon(self) -> &'static str {
+        match self {
+            Status::Pass => "✅",
+            Status::Warn => "⚠️ ",
+            Status::Fail => "❌",
+        }
+    }
+}
+
+/// One line of the checklist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: String) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Pass,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn problem(name: &str, status: Status, detail: String, hint: String) -> Check {
+        Check {
+            name: name.to_string(),
+            status,
+            detail,
+            hint: Some(hint),
+        }
+    }
+}
+
+/// What the checks look at.
+pub struct Doctor<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub manifest: &'a Manifest,
+    pub msys2_root: &'a Path,
+    /// The PATH directories, in order.
+    pub path: Vec<PathBuf>,
+}
+
+impl Doctor<'_> {
+    /// Run every check.
+    pub fn run(&self) -> Vec<Check> {
+        let mut checks = vec![self.host()];
+        checks.extend(self.targets());
+        checks.extend(self.linkers());
+        checks.extend(self.binutils());
+        checks.extend(self.runtime_dlls());
+        checks.push(self.pkg_config());
+        checks.extend(self.conflicts());
+        checks
+    }
+
+    /// First PATH directory containing `program`.
+    fn find(&self, program: &str) -> Option<PathBuf> {
+        self.path.iter().find(|dir| dir.join(program).is_file()).cloned()
+    }
+
+    fn bin_dir(&self) -> PathBuf {
+        self.manifest.msys2.env.bin_dir(self.msys2_root)
+    }
+
+    /// Build scripts and proc macros are built for the host, so an MSVC host
+    /// needs Visual Studio's linker even when the target is GNU.
+    fn host(&self) -> Check {
+        let toolchain = self.manifest.toolchain();
+        let output = match self.runner.run("rustc", &["--version", "--verbose"]) {
+            Ok(output) if output.success() => output,
+            _ => {
+                return Check::problem(
+                    "rustc host",
+                    Status::Fail,
+                    "rustc not found".to_string(),
+                    "Run the installer, then open a new terminal so %USERPROFILE%\\.cargo\\bin is on PATH".to_string(),
+                );
+            }
+        };
+        let host = output
+            .stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .unwrap_or("unknown")
+            .trim()
+            .to_string();
+        if host.ends_with("-windows-gnu") || host.ends_with("-windows-gnullvm") {
+            Check::pass("rustc host", host)
+        } else {
+            Check::problem(
+                "rustc host",
+                Status::Fail,
+                format!("{} (build scripts need the MSVC linker)", host),
+                format!("Run `rustup override set {0}` in the project, or `rustup default {0}`", toolchain),
+            )
+        }
+    }
+
+    fn targets(&self) -> Vec<Check> {
+        let mut targets = vec![self.manifest.msys2.env.rust_target().to_string()];
+        for target in &self.manifest.rust.targets {
+            if !targets.contains(target) {
+                targets.push(target.clone());
+            }
+        }
+
+        let installed = match self.runner.run("rustup", &["target", "list", "--installed"]) {
+            Ok(output) if output.success() => output.stdout,
+            _ => {
+                return vec![Check::problem(
+                    "GNU target",
+                    Status::Fail,
+                    "rustup not found".to_string(),
+                    "Run the installer to install rustup".to_string(),
+                )];
+            }
+        };
+        targets
+            .iter()
+            .map(|target| {
+                if installed.lines().any(|line| line.trim() == target) {
+                    Check::pass("GNU target", target.clone())
+                } else {
+                    Check::problem(
+                        "GNU target",
+                        Status::Fail,
+                        format!("{} is not installed", target),
+                        format!("Run `rustup target add {}`", target),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    /// The primary linker is found through PATH; cross linkers are configured
+    /// with absolute paths, so those only need to exist.
+    fn linkers(&self) -> Vec<Check> {
+        let env = self.manifest.msys2.env;
+        let linker = format!("{}.exe", env.linker());
+        let bin_dir = self.bin_dir();
+        let mut checks = vec![match self.find(&linker) {
+            Some(dir) if same_dir(&dir, &bin_dir) => Check::pass("linker", dir.join(&linker).display().to_string()),
+            Some(dir) => Check::problem(
+                "linker",
+                Status::Warn,
+                format!("{} comes from {}, not MSYS2", linker, dir.display()),
+                format!("Put {} before {} on PATH", bin_dir.display(), dir.display()),
+            ),
+            None => Check::problem(
+                "linker",
+                Status::Fail,
+                format!("{} not found on PATH", linker),
+                format!(
+                    "Add {} to PATH (rerun with --only-step configure) or use the activation scripts",
+                    bin_dir.display()
+                ),
+            ),
+        }];
+
+        for env in self.manifest.cross_environments() {
+            let path = env.bin_dir(self.msys2_root).join(format!("{}.exe", env.linker()));
+            checks.push(if path.is_file() {
+                Check::pass("linker", path.display().to_string())
+            } else {
+                Check::problem(
+                    "linker",
+                    Status::Fail,
+                    format!("{} does not exist", path.display()),
+                    format!("Run `pacman -S {}` in an MSYS2 shell", env.package("toolchain")),
+                )
+            });
+        }
+        checks
+    }
+
+    fn binutils(&self) -> Vec<Check> {
+        let env = self.manifest.msys2.env;
+        [env.ar(), env.dlltool()]
+            .into_iter()
+            .map(|tool| {
+                let program = format!("{}.exe", tool);
+                match self.find(&program) {
+                    Some(dir) => Check::pass(tool, dir.join(&program).display().to_string()),
+                    None => Check::problem(
+                        tool,
+                        Status::Fail,
+                        format!("{} not found on PATH", program),
+                        format!("Run `pacman -S {}` and add {} to PATH", env.package("toolchain"), self.bin_dir().display()),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    /// Rust links the GNU runtime statically, but C and C++ libraries built
+    /// by the `cc` crate may need these DLLs when the program starts.
+    fn runtime_dlls(&self) -> Vec<Check> {
+        self.manifest
+            .msys2
+            .env
+            .runtime_dlls()
+            .iter()
+            .map(|dll| match self.find(dll) {
+                Some(dir) => Check::pass(dll, dir.join(dll).display().to_string()),
+                None => Check::problem(
+                    dll,
+                    Status::Warn,
+                    "not found on PATH; programs using C/C++ libraries may fail to start".to_string(),
+                    format!("Add {} to PATH, or copy the DLL next to the program", self.bin_dir().display()),
+                ),
+            })
+            .collect()
+    }
+
+    fn pkg_config(&self) -> Check {
+        match self.runner.run("pkg-config", &["--version"]) {
+            Ok(output) if output.success() => Check::pass("pkg-config", output.stdout.trim().to_string()),
+            _ => Check::problem(
+                "pkg-config",
+                Status::Warn,
+                "pkg-config does not run; crates that find system libraries with it will fail".to_string(),
+                format!(
+                    "Run `pacman -S {}` and add {} to PATH",
+                    self.manifest.msys2.env.package("pkgconf"),
+                    self.bin_dir().display()
+                ),
+            ),
+        }
+    }
+
+    /// Visual Studio's compiler, and MinGW installs other than this MSYS2
+    /// (Strawberry Perl, standalone MinGW-w64, another MSYS2), on PATH.
+    fn conflicts(&self) -> Vec<Check> {
+        let bin_dir = self.bin_dir();
+        let ours = self.path.iter().position(|dir| same_dir(dir, &bin_dir));
+        let mut checks = Vec::new();
+
+        for (index, dir) in self.path.iter().enumerate() {
+            if dir.join("cl.exe").is_file() {
+                checks.push(Check::problem(
+                    "conflicting toolchains",
+                    Status::Warn,
+                    format!("MSVC compiler in {}", dir.display()),
+                    "Build GNU projects from a terminal that is not a Visual Studio developer prompt".to_string(),
+                ));
+            }
+            let is_msys2 = dir.starts_with(self.msys2_root) || same_dir(dir, &bin_dir);
+            if !is_msys2 && dir.join("gcc.exe").is_file() {
+                // A MinGW ahead of ours wins for `gcc` and its DLLs
+                let shadows = ours.is_none_or(|ours| index < ours);
+                checks.push(Check::problem(
+                    "conflicting toolchains",
+                    if shadows { Status::Fail } else { Status::Warn },
+                    format!("another MinGW in {}", dir.display()),
+                    format!("Remove {} from PATH or put {} before it", dir.display(), bin_dir.display()),
+                ));
+            }
+        }
+
+        if checks.is_empty() {
+            checks.push(Check::pass("conflicting toolchains", "none on PATH".to_string()));
+        }
+        checks
+    }
+}
+
+/// Whether two PATH entries are the same directory, ignoring case and a
+/// trailing separator as Windows does.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    let normalize = |path: &Path| path.to_string_lossy().replace('/', "\\").trim_end_matches('\\').to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+/// Print the checklist and a summary line (nothing with `--quiet`; the exit
+/// code still tells whether a check failed).
+pub fn print(checks: &[Check]) {
+    for check in checks {
+        say!("{} {}: {}", check.status.icon(), check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            say!("   → {}", hint);
+        }
+    }
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    say!(
+        "\n{} passed, {} warning(s), {} failed",
+        count(Status::Pass),
+        count(Status::Warn),
+        count(Status::Fail)
+    );
+}
+
+/// How many checks failed.
+pub fn failures(checks: &[Check]) -> usize {
+    checks.iter().filter(|check| check.status == Status::Fail).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{CommandOutput, FakeRunner};
+    use crate::testutil::scratch_dir;
+    use std::fs;
+
+    fn touch(dir: &Path, names: &[&str]) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        dir.to_path_buf()
+    }
+
+    fn check<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+        checks.iter().find(|check| check.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_healthy_setup_passes() {
+        let dir = scratch_dir("doctor-healthy");
+        let root = dir.join("msys64");
+        let bin = touch(
+            &root.join("mingw64").join("bin"),
+            &[
+                "x86_64-w64-mingw32-gcc.exe",
+                "x86_64-w64-mingw32-ar.exe",
+                "dlltool.exe",
+                "libgcc_s_seh-1.dll",
+                "libwinpthread-1.dll",
+            ],
+        );
+        let runner = FakeRunner::new()
+            .on("rustc --version --verbose", CommandOutput::ok("rustc 1.80.0\nhost: x86_64-pc-windows-gnu\n"))
+            .on("target list --installed", CommandOutput::ok("x86_64-pc-windows-gnu\n"))
+            .on("pkg-config --version", CommandOutput::ok("2.1.1\n"));
+        let manifest = Manifest::default();
+        let doctor = Doctor {
+            runner: &runner,
+            manifest: &manifest,
+            msys2_root: &root,
+            path: vec![touch(&dir.join("cargo-bin"), &[]), bin, root.join("usr").join("bin")],
+        };
+
+        let checks = doctor.run();
+        assert!(checks.iter().all(|check| check.status == Status::Pass), "{:#?}", checks);
+        assert_eq!(failures(&checks), 0);
+        assert_eq!(check(&checks, "rustc host").detail, "x86_64-pc-windows-gnu");
+    }
+
+    #[test]
+    fn test_broken_setup_explains_each_failure() {
+        let dir = scratch_dir("doctor-broken");
+        let root = dir.join("msys64");
+        let strawberry = touch(&dir.join("strawberry").join("c").join("bin"), &["gcc.exe", "x86_64-w64-mingw32-gcc.exe"]);
+        let vs = touch(&dir.join("vs").join("bin"), &["cl.exe", "link.exe"]);
+        let runner = FakeRunner::new()
+            .on("rustc --version --verbose", CommandOutput::ok("rustc 1.80.0\nhost: x86_64-pc-windows-msvc\n"))
+            .on("target list --installed", CommandOutput::ok("x86_64-pc-windows-msvc\n"))
+            .missing("pkg-config");
+        let manifest = Manifest::default();
+        let doctor = Doctor {
+            runner: &runner,
+            manifest: &manifest,
+            msys2_root: &root,
+            path: vec![strawberry, vs, root.join("mingw64").join("bin")],
+        };
+
+        let checks = doctor.run();
+        assert!(failures(&checks) > 0);
+        let host = check(&checks, "rustc host");
+        assert_eq!(host.status, Status::Fail);
+        assert!(host.hint.as_ref().unwrap().contains("rustup override set stable-x86_64-pc-windows-gnu"));
+        assert!(check(&checks, "GNU target").hint.as_ref().unwrap().contains("rustup target add x86_64-pc-windows-gnu"));
+        assert_eq!(check(&checks, "linker").status, Status::Warn);
+        assert_eq!(check(&checks, "dlltool").status, Status::Fail);
+        assert_eq!(check(&checks, "libgcc_s_seh-1.dll").status, Status::Warn);
+        assert_eq!(check(&checks, "pkg-config").status, Status::Warn);
+
+        let conflicts: Vec<_> = checks.iter().filter(|check| check.name == "conflicting toolchains").collect();
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts[0].detail.contains("another MinGW"));
+        assert_eq!(conflicts[0].status, Status::Fail);
+        assert!(conflicts[1].detail.contains("MSVC compiler"));
+    }
+}
@@ -0,0 +1,403 @@
+//! In-process HTTP downloads for the MSYS2 and rustup installers.
+//!
+//! Downloads go to `<dest>.part` first and are renamed into place once
+//! complete, so an interrupted transfer is resumed with a `Range` request on
+//! the next attempt instead of starting over. `file://` URLs are copied, which
+//! is how offline bundles are consumed.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ureq::{Agent, Proxy};
+
+use crate::msys2::Environment;
+
+/// Where the installers and packages are downloaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sources {
+    pub msys2_installer: String,
+    pub rustup_init: String,
+    /// Parent of the per-environment pacman repositories (`mingw64/`, `ucrt64/`, ...).
+    pub mingw_mirror: String,
+    /// rustup dist server (the directory containing `dist/`).
+    pub rust_dist: String,
+}
+
+impl Sources {
+    /// Upstream downloads, with the `rustup-init.exe` build that runs on
+    /// `env`'s host. MSYS2 itself only ships an x86_64 installer, which
+    /// ARM64 Windows runs under emulation.
+    pub fn official(env: Environment) -> Self {
+        Sources {
+            msys2_installer: "https://github.com/msys2/msys2-installer/releases/latest/download/msys2-x86_64-latest.exe"
+                .to_string(),
+            rustup_init: format!(
+                "https://static.rust-lang.org/rustup/dist/{}/rustup-init.exe",
+                env.rustup_init_host()
+            ),
+            mingw_mirror: "https://mirror.msys2.org/mingw".to_string(),
+            rust_dist: "https://static.rust-lang.org".to_string(),
+        }
+    }
+
+    /// Mirror layout: `<base>/msys2-x86_64-latest.exe` and `<base>/rustup-init.exe`,
+    /// each with a `.sha256` file next to it, the pacman repositories under
+    /// `<base>/mingw/<env>` and the rustup dist server under `<base>/rust`.
+    pub fn from_base(base: &str) -> Self {
+        let base = base.trim_end_matches('/');
+        Sources {
+            msys2_installer: format!("{}/msys2-x86_64-latest.exe", base),
+            rustup_init: format!("{}/rustup-init.exe", base),
+            mingw_mirror: format!("{}/mingw", base),
+            rust_dist: format!("{}/rust", base),
+        }
+    }
+}
+
+/// Why a single attempt failed.
+enum AttemptError {
+    /// Worth trying again (network trouble, 5xx, truncated body).
+    Retry(String),
+    /// Trying again will not help (404, local I/O failure).
+    Fatal(String),
+}
+
+pub struct Downloader {
+    agent: Agent,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Downloader {
+    /// Downloader using `proxy` for every request, or connecting directly.
+    pub fn new(proxy: Option<Proxy>) -> Self {
+        let agent = Agent::config_builder()
+            .proxy(proxy)
+            .timeout_connect(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+
+        Downloader {
+            agent,
+            retries: 3,
+            backoff: Duration::from_secs(2),
+        }
+    }
+
+    /// Downloader honouring `HTTPS_PROXY` (and `ALL_PROXY`, `HTTP_PROXY`, `NO_PROXY`).
+    pub fn from_env() -> Self {
+        Self::new(Proxy::try_from_env())
+    }
+
+    /// Retry up to `retries` times, doubling the delay from `backoff`.
+    #[cfg(test)]
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Download `url` to `dest`, printing progress. Returns the file size.
+    pub fn download(&self, url: &str, dest: &Path) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut progress = ConsoleProgress::default();
+        let result = self.download_with_progress(url, dest, &mut |done, total| progress.update(done, total));
+        progress.finish();
+        result
+    }
+
+    /// How long `url` takes to answer a HEAD request, going through the proxy
+    /// like any download; `None` without an answer within `timeout`. Any HTTP
+    /// status counts as an answer.
+    pub fn latency(&self, url: &str, timeout: Duration) -> Option<Duration> {
+        let started = Instant::now();
+        let request = self.agent.head(url).config().timeout_global(Some(timeout)).build();
+        match request.call() {
+            Ok(_) | Err(ureq::Error::StatusCode(_)) => Some(started.elapsed()),
+            Err(_) => None,
+        }
+    }
+
+    /// Fetch a small text file such as a `.sha256` sidecar, without retries.
+    pub fn fetch_string(&self, url: &str) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(source) = file_url_to_path(url) {
+            return fs::read_to_string(&source).map_err(|e| format!("Cannot read {}: {}", source.display(), e).into());
+        }
+        match self.agent.get(url).call() {
+            Ok(mut response) => Ok(response.body_mut().read_to_string()?),
+            Err(ureq::Error::StatusCode(code)) => Err(format!("HTTP {} from {}", code, url).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Download `url` to `dest`, calling `on_progress(bytes_done, total)` as data arrives.
+    pub fn download_with_progress(
+        &self,
+        url: &str,
+        dest: &Path,
+        on_progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        if let Some(source) = file_url_to_path(url) {
+            let size = fs::copy(&source, dest).map_err(|e| format!("Cannot copy {}: {}", source.display(), e))?;
+            on_progress(size, Some(size));
+            return Ok(size);
+        }
+
+        let part = part_path(dest);
+        let mut last_error = String::new();
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                let delay = self.backoff * 2u32.pow(attempt - 1);
+                say!("   ⚠️  {} — retrying in {:?} ({}/{})", last_error, delay, attempt, self.retries);
+                thread::sleep(delay);
+            }
+
+            match self.attempt(url, &part, on_progress) {
+                Ok(size) => {
+                    fs::rename(&part, dest)?;
+                    return Ok(size);
+                }
+                Err(AttemptError::Fatal(e)) => return Err(e.into()),
+                Err(AttemptError::Retry(e)) => last_error = e,
+            }
+        }
+
+        Err(format!(
+            "Failed to download {} after {} attempts: {}",
+            url,
+            self.retries + 1,
+            last_error
+        )
+        .into())
+    }
+
+    fn attempt(
+        &self,
+        url: &str,
+        part: &Path,
+        on_progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> Result<u64, AttemptError> {
+        let resume_from = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = self.agent.get(url);
+        if resume_from > 0 {
+            request = request.header("Range", &format!("bytes={}-", resume_from));
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::StatusCode(416)) => {
+                // The partial file does not match what the server has; start over
+                let _ = fs::remove_file(part);
+                return Err(AttemptError::Retry("server rejected resume".to_string()));
+            }
+            Err(ureq::Error::StatusCode(code)) if code >= 500 || code == 408 || code == 429 => {
+                return Err(AttemptError::Retry(format!("HTTP {} from {}", code, url)));
+            }
+            Err(ureq::Error::StatusCode(code)) => {
+                return Err(AttemptError::Fatal(format!("HTTP {} from {}", code, url)));
+            }
+            Err(e) => return Err(AttemptError::Retry(e.to_string())),
+        };
+
+        let resumed = response.status().as_u16() == 206;
+        let start = if resumed { resume_from } else { 0 };
+        let total = response
+            .headers()
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|len| len + start);
+
+        let file = if resumed {
+            OpenOptions::new().append(true).open(part)
+        } else {
+            File::create(part)
+        };
+        let mut file = file.map_err(|e| AttemptError::Fatal(format!("Cannot write {}: {}", part.display(), e)))?;
+
+        let mut reader = response.into_body().into_reader();
+        let mut done = start;
+        let mut buffer = vec![0u8; 64 * 1024];
+        on_progress(done, total);
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(AttemptError::Retry(format!("connection lost: {}", e))),
+            };
+            file.write_all(&buffer[..read])
+                .map_err(|e| AttemptError::Fatal(format!("Cannot write {}: {}", part.display(), e)))?;
+            done += read as u64;
+            on_progress(done, total);
+        }
+
+        match total {
+            Some(total) if done < total => Err(AttemptError::Retry(format!(
+                "connection closed after {} of {} bytes",
+                done, total
+            ))),
+            _ => Ok(done),
+        }
+    }
+}
+
+/// `file:///C:/bundle/x.exe` → `C:/bundle/x.exe`; `None` for other schemes.
+pub fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://")?;
+    // Drop the slash in front of a drive letter
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => path,
+    };
+    Some(PathBuf::from(path))
+}
+
+/// `file://` URL for a local path, with forward slashes.
+pub fn path_to_file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        format!("file:///{}", path)
+    }
+}
+
+/// `<dest>.part`, where an unfinished download is kept.
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+/// Single-line progress indicator, updated at most once per percent.
+#[derive(Default)]
+struct ConsoleProgress {
+    last_shown: Option<u64>,
+}
+
+impl ConsoleProgress {
+    fn update(&mut self, done: u64, total: Option<u64>) {
+        if crate::output::is_silent() {
+            return;
+        }
+        let mb = |bytes: u64| bytes as f64 / 1_000_000.0;
+        let (step, line) = match total {
+            Some(total) if total > 0 => (
+                done * 100 / total,
+                format!("   {:.1} / {:.1} MB ({}%)", mb(done), mb(total), done * 100 / total),
+            ),
+            _ => (done / 1_000_000, format!("   {:.1} MB", mb(done))),
+        };
+        if self.last_shown != Some(step) {
+            self.last_shown = Some(step);
+            print!("\r{}", line);
+            let _ = io::stdout().flush();
+        }
+    }
+
+    fn finish(&self) {
+        if self.last_shown.is_some() && !crate::output::is_silent() {
+            println!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{Faults, TestServer, scratch_dir};
+
+    fn payload() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn downloader() -> Downloader {
+        Downloader::new(None).with_retries(3, Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_download_reports_progress() {
+        let body = payload();
+        let server = TestServer::start(&[("/rustup-init.exe", &body)], Faults::default());
+        let dest = scratch_dir("download-ok").join("rustup-init.exe");
+
+        let mut last = (0, None);
+        let size = downloader()
+            .download_with_progress(&Sources::from_base(&server.base_url).rustup_init, &dest, &mut |done, total| {
+                last = (done, total)
+            })
+            .unwrap();
+
+        assert_eq!(size, body.len() as u64);
+        assert_eq!(last, (body.len() as u64, Some(body.len() as u64)));
+        assert_eq!(fs::read(&dest).unwrap(), body);
+        assert!(!part_path(&dest).exists());
+    }
+
+    #[test]
+    fn test_download_retries_and_resumes() {
+        let body = payload();
+        let server = TestServer::start(
+            &[("/msys2-x86_64-latest.exe", &body)],
+            Faults {
+                fail_first: 1,
+                cut_first_at: Some(50_000),
+            },
+        );
+        let dest = scratch_dir("download-resume").join("msys2-installer.exe");
+
+        downloader()
+            .download(&format!("{}/msys2-x86_64-latest.exe", server.base_url), &dest)
+            .unwrap();
+
+        assert_eq!(fs::read(&dest).unwrap(), body);
+        assert_eq!(
+            server.requests(),
+            vec![
+                "GET /msys2-x86_64-latest.exe",
+                "GET /msys2-x86_64-latest.exe",
+                "GET /msys2-x86_64-latest.exe range=50000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_urls() {
+        assert_eq!(file_url_to_path("file:///C:/bundle/rustup-init.exe"), Some(PathBuf::from("C:/bundle/rustup-init.exe")));
+        assert_eq!(file_url_to_path("file:///srv/bundle"), Some(PathBuf::from("/srv/bundle")));
+        assert_eq!(file_url_to_path("https://example.com/x"), None);
+        assert_eq!(path_to_file_url(Path::new("C:\\bundle\\rustup")), "file:///C:/bundle/rustup");
+        assert_eq!(path_to_file_url(Path::new("/srv/bundle")), "file:///srv/bundle");
+    }
+
+    #[test]
+    fn test_download_copies_file_urls() {
+        let dir = scratch_dir("download-file-url");
+        fs::write(dir.join("source.exe"), b"installer").unwrap();
+        let dest = dir.join("copy.exe");
+
+        let size = downloader().download(&path_to_file_url(&dir.join("source.exe")), &dest).unwrap();
+
+        assert_eq!(size, 9);
+        assert_eq!(fs::read(&dest).unwrap(), b"installer");
+    }
+
+    #[test]
+    fn test_download_does_not_retry_missing_file() {
+        let server = TestServer::start(&[], Faults::default());
+        let dest = scratch_dir("download-404").join("rustup-init.exe");
+
+        let err = downloader()
+            .download(&format!("{}/rustup-init.exe", server.base_url), &dest)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("HTTP 404"));
+        assert_eq!(server.requests().len(), 1);
+        assert!(!dest.exists());
+    }
+}
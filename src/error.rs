@@ -0,0 +1,171 @@
+//! Why an installation failed, and the process exit code for each reason.
+//!
+//! | Code | Meaning                                                        |
+//! |------|----------------------------------------------------------------|
+//! | 0    | Success                                                        |
+//! | 1    | Any other failure, including failed `doctor` checks            |
+//! | 2    | Invalid command line or manifest                               |
+//! | 3    | Input was needed but the run is non-interactive                |
+//! | 4    | The user declined a confirmation                               |
+//! | 5    | A download failed, including a published checksum or signature |
+//! | 6    | A download did not match its checksum or signature             |
+//! | 7    | PowerShell's execution policy blocked a command                |
+//! | 8    | A pacman command failed                                        |
+//! | 9    | A rustup command failed                                        |
+//! | 10   | The installed toolchain failed verification                    |
+//! | 11   | MSYS2 could not be installed, found or removed                 |
+//!
+//! Plain messages only become an error through an explicit
+//! [`InstallError::Other`], so a failure with its own exit code is not
+//! reported as a generic one by accident.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::pacman::Failure;
+
+/// Exit code for failures without a more specific one.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code for an invalid command line or manifest.
+pub const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug)]
+pub enum InstallError {
+    /// Downloading `url` failed.
+    Download { url: String, reason: String },
+    /// The file at `path` failed its SHA-256 or signature check.
+    Checksum { path: PathBuf, reason: String },
+    /// PowerShell refused to run `command` because of the execution policy.
+    ExecutionPolicy { command: String },
+    /// A pacman transaction failed; `package` is set when one package is to blame.
+    Pacman { package: Option<String>, reason: String },
+    /// `rustup <command>` (or rustup-init) failed.
+    Rustup { command: String, reason: String },
+    /// The toolchain was installed but cannot build a test program.
+    Verification { reason: String },
+    /// MSYS2 is missing, or installing or removing it failed.
+    Msys2 { reason: String },
+    /// The user answered no to `question`.
+    Aborted { question: String },
+    /// An answer was needed but the run is non-interactive.
+    PromptRequired(String),
+    Other(String),
+}
+
+impl InstallError {
+    /// The process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InstallError::Other(_) => EXIT_FAILURE,
+            InstallError::PromptRequired(_) => 3,
+            InstallError::Aborted { .. } => 4,
+            InstallError::Download { .. } => 5,
+            InstallError::Checksum { .. } => 6,
+            InstallError::ExecutionPolicy { .. } => 7,
+            InstallError::Pacman { .. } => 8,
+            InstallError::Rustup { .. } => 9,
+            InstallError::Verification { .. } => 10,
+            InstallError::Msys2 { .. } => 11,
+        }
+    }
+
+    /// A failed pacman transaction; without a `package` the ones pacman
+    /// blamed are named.
+    pub fn pacman(package: Option<&str>, failure: &Failure) -> InstallError {
+        let blamed = failure.packages().join(", ");
+        InstallError::Pacman {
+            package: package.map(str::to_string).or((!blamed.is_empty()).then_some(blamed)),
+            reason: failure.to_string(),
+        }
+    }
+
+    /// A failed `rustup <args>`, described by the first line of its stderr.
+    pub fn rustup(args: &[&str], stderr: &str) -> InstallError {
+        InstallError::Rustup {
+            command: args.join(" "),
+            reason: stderr.lines().next().unwrap_or("Unknown error").to_string(),
+        }
+    }
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallError::Download { url, reason } => write!(f, "Downloading {} failed: {}", url, reason),
+            InstallError::Checksum { path, reason } => write!(f, "Verifying {} failed: {}", path.display(), reason),
+            InstallError::ExecutionPolicy { command } => write!(
+                f,
+                "PowerShell's execution policy blocked `{}`. Allow it with \
+                 `Set-ExecutionPolicy -Scope CurrentUser RemoteSigned` or install by hand",
+                command
+            ),
+            InstallError::Pacman { package: Some(package), reason } => {
+                write!(f, "pacman failed to install {}: {}", package, reason)
+            }
+            InstallError::Pacman { package: None, reason } => write!(f, "pacman failed: {}", reason),
+            InstallError::Rustup { command, reason } => write!(f, "rustup {} failed: {}", command, reason),
+            InstallError::Verification { reason } => write!(f, "Verification failed: {}", reason),
+            InstallError::Msys2 { reason } => f.write_str(reason),
+            InstallError::Aborted { question } => write!(f, "Cancelled at \"{}\"; nothing was changed", question),
+            InstallError::PromptRequired(question) => write!(f, "{} (running non-interactively)", question),
+            InstallError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for InstallError {}
+
+impl From<io::Error> for InstallError {
+    fn from(e: io::Error) -> InstallError {
+        InstallError::Other(e.to_string())
+    }
+}
+
+/// Whether PowerShell's stderr says a script or command was blocked by the
+/// execution policy.
+pub fn is_execution_policy_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("running scripts is disabled")
+        || stderr.contains("executionpolicy")
+        || stderr.contains("execution policy")
+        || stderr.contains("pssecurityexception")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            InstallError::Other("x".into()),
+            InstallError::PromptRequired("Continue?".into()),
+            InstallError::Aborted { question: "Continue?".into() },
+            InstallError::Download { url: "u".into(), reason: "r".into() },
+            InstallError::Checksum { path: PathBuf::from("f"), reason: "r".into() },
+            InstallError::ExecutionPolicy { command: "c".into() },
+            InstallError::pacman(None, &Failure::MissingTarget(vec!["p".into()])),
+            InstallError::rustup(&["target", "add", "t"], ""),
+            InstallError::Verification { reason: "r".into() },
+            InstallError::Msys2 { reason: "r".into() },
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(InstallError::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0) && !codes.contains(&EXIT_USAGE));
+
+        assert_eq!(errors[6].to_string(), "pacman failed to install p: target not found: p");
+        assert_eq!(errors[7].to_string(), "rustup target add t failed: Unknown error");
+    }
+
+    #[test]
+    fn test_execution_policy_detection() {
+        assert!(is_execution_policy_error(
+            "File C:\\x.ps1 cannot be loaded because running scripts is disabled on this system."
+        ));
+        assert!(is_execution_policy_error("+ FullyQualifiedErrorId : UnauthorizedAccess,PSSecurityException"));
+        assert!(!is_execution_policy_error("The operation was canceled by the user."));
+    }
+}
@@ -0,0 +1,174 @@
+//! Install journal: every change an installation made, so `uninstall` can
+//! reverse exactly those and leave pre-existing installs alone.
+//!
+//! The journal is a TOML file of `[[entry]]` tables, appended to as each
+//! change is made so an interrupted installation is still covered.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// One change made by an installation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Entry {
+    /// MSYS2 was installed by this tool.
+    Msys2Installed { root: PathBuf },
+    /// MSYS2 was already there; never removed.
+    Msys2Found { root: PathBuf },
+    /// pacman packages that were not installed before, dependencies included.
+    PackagesInstalled { root: PathBuf, packages: Vec<String> },
+    /// rustup itself was installed by this tool.
+    RustupInstalled,
+    /// `toolchain` is unset for rustup's default toolchain.
+    TargetsAdded {
+        toolchain: Option<String>,
+        targets: Vec<String>,
+    },
+    ComponentsAdded { toolchain: String, components: Vec<String> },
+    /// `previous` is the override the directory had before, if any.
+    OverrideSet { path: PathBuf, previous: Option<String> },
+    DefaultSet { previous: Option<String> },
+    /// A file written by this tool; `backup` holds the original if it existed.
+    FileWritten { path: PathBuf, backup: Option<PathBuf> },
+    /// Entries added to the user PATH (moved ones are not listed) and the
+    /// whole value before; journals from older versions have no `previous`.
+    UserPathChanged {
+        added: Vec<String>,
+        #[serde(default)]
+        previous: Option<String>,
+    },
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Msys2Installed { root } => write!(f, "MSYS2 installed at {}", root.display()),
+            Entry::Msys2Found { root } => write!(f, "MSYS2 found at {}", root.display()),
+            Entry::PackagesInstalled { packages, .. } => write!(f, "pacman packages {}", packages.join(", ")),
+            Entry::RustupInstalled => write!(f, "rustup installed"),
+            Entry::TargetsAdded { targets, .. } => write!(f, "rustup targets {}", targets.join(", ")),
+            Entry::ComponentsAdded { components, .. } => write!(f, "rustup components {}", components.join(", ")),
+            Entry::OverrideSet { path, .. } => write!(f, "toolchain override for {}", path.display()),
+            Entry::DefaultSet { .. } => write!(f, "default toolchain"),
+            Entry::FileWritten { path, .. } => write!(f, "{}", path.display()),
+            Entry::UserPathChanged { added, .. } if added.is_empty() => write!(f, "user PATH order"),
+            Entry::UserPathChanged { added, .. } => write!(f, "user PATH entries {}", added.join(", ")),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct JournalFile {
+    #[serde(default)]
+    entry: Vec<Entry>,
+}
+
+/// The journal file.
+pub struct Journal {
+    pub path: PathBuf,
+}
+
+impl Journal {
+    pub fn at(path: PathBuf) -> Journal {
+        Journal { path }
+    }
+
+    /// Append `entry`.
+    pub fn record(&self, entry: &Entry) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(render(std::slice::from_ref(entry))?.as_bytes())
+    }
+
+    /// Everything recorded so far, oldest first; empty if there is no journal.
+    pub fn entries(&self) -> Result<Vec<Entry>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => toml::from_str::<JournalFile>(&text)
+                .map(|journal| journal.entry)
+                .map_err(|e| format!("Cannot read install journal {}: {}", self.path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(format!("Cannot read install journal {}: {}", self.path.display(), e)),
+        }
+    }
+
+    /// Replace the journal with `entries`, removing it when there are none.
+    pub fn replace(&self, entries: &[Entry]) -> io::Result<()> {
+        if entries.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        fs::write(&self.path, render(entries)?)
+    }
+}
+
+fn render(entries: &[Entry]) -> io::Result<String> {
+    let file = JournalFile {
+        entry: entries.to_vec(),
+    };
+    toml::to_string(&file).map_err(io::Error::other)
+}
+
+/// `%LOCALAPPDATA%\rs-easy-installer\journal.toml`, so it survives both
+/// `rustup self uninstall` and removing MSYS2.
+pub fn default_path(env: &dyn Fn(&str) -> Option<String>) -> PathBuf {
+    let base = env("LOCALAPPDATA")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".local").join("share")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("rs-easy-installer").join("journal.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::scratch_dir;
+
+    #[test]
+    fn test_round_trip() {
+        let journal = Journal::at(scratch_dir("journal").join("state").join("journal.toml"));
+        assert!(journal.entries().unwrap().is_empty());
+
+        let entries = [
+            Entry::Msys2Found {
+                root: PathBuf::from("C:\\msys64"),
+            },
+            Entry::TargetsAdded {
+                toolchain: None,
+                targets: vec!["x86_64-pc-windows-gnu".to_string()],
+            },
+            Entry::FileWritten {
+                path: PathBuf::from("C:\\app\\.cargo\\config.toml"),
+                backup: Some(PathBuf::from("C:\\app\\.cargo\\config.toml.bak")),
+            },
+            Entry::RustupInstalled,
+        ];
+        for entry in &entries {
+            journal.record(entry).unwrap();
+        }
+        assert_eq!(journal.entries().unwrap(), entries);
+        assert!(fs::read_to_string(&journal.path).unwrap().contains("action = \"targets-added\""));
+
+        journal.replace(&entries[..1]).unwrap();
+        assert_eq!(journal.entries().unwrap(), entries[..1]);
+        journal.replace(&[]).unwrap();
+        assert!(!journal.path.exists());
+    }
+
+    #[test]
+    fn test_default_path() {
+        let path = default_path(&|name| (name == "LOCALAPPDATA").then(|| "C:\\Users\\dev\\AppData\\Local".to_string()));
+        assert_eq!(
+            path,
+            Path::new("C:\\Users\\dev\\AppData\\Local").join("rs-easy-installer").join("journal.toml")
+        );
+    }
+}
@@ -1,271 +1,918 @@
+// The baseline test_windows_check asserts `true` on Windows; allowed here so
+// that test stays as written
+#![cfg_attr(test, allow(clippy::assertions_on_constants))]
+
+#[macro_use]
+mod output;
+mod activate;
+mod bundle;
+mod cargo_config;
+mod cli;
+mod doctor;
+mod download;
+mod error;
+mod journal;
+mod manifest;
+mod msys2;
+mod offline;
+mod pacman;
+mod pacman_config;
+mod plan;
+mod progress;
+mod report;
+mod runner;
+#[cfg(test)]
+mod testutil;
+mod user_env;
+mod verify;
+
+use std::collections::BTreeSet;
 use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
-fn main() {
-    println!("🦀 Rust GNU/MSYS Installation Helper for Windows");
-    println!("================================================\n");
+use sha2::{Digest, Sha256};
+
+use activate::Activation;
+use cargo_config::Scope;
+use cli::{Command, Options};
+use download::{Downloader, Sources};
+use error::InstallError;
+use journal::{Entry, Journal};
+use manifest::Manifest;
+use msys2::RootSource;
+use offline::Bundle;
+use pacman::{Failure, Transaction};
+use plan::{DryRunRunner, Plan};
+use progress::{Progress, Selection, Step};
+use report::Report;
+use runner::{CommandOutput, CommandRunner, SystemRunner};
+use user_env::{RegistryEnvironment, UserEnvironment};
+use verify::VerifyPolicy;
+
+/// Everything the install steps need from the outside world.
+struct Context<'a> {
+    runner: &'a dyn CommandRunner,
+    /// MSYS2 installation root, e.g. `C:\msys64`.
+    msys2_root: PathBuf,
+    msys2_root_source: RootSource,
+    downloader: Downloader,
+    sources: Sources,
+    verify: VerifyPolicy,
+    /// What to install; the built-in defaults unless a manifest was given.
+    manifest: Manifest,
+    /// Set for `--offline`; packages and toolchains come from the bundle.
+    offline: Option<Bundle>,
+    /// Directory for downloaded installers and the test program.
+    work_dir: PathBuf,
+    /// Where the cargo config goes and how the toolchain is selected.
+    scope: Scope,
+    /// Project for [`Scope::Project`].
+    project_dir: PathBuf,
+    /// Cargo's home, for [`Scope::User`] and its `bin` on the PATH.
+    cargo_home: PathBuf,
+    /// The persistent user PATH.
+    user_env: &'a dyn UserEnvironment,
+    /// Unset by `--no-modify-path`; the PATH change is then only printed.
+    modify_path: bool,
+    /// Where the changes made are recorded for `uninstall`.
+    journal: Journal,
+    /// Which steps have completed, for resuming after a failure.
+    progress: Progress,
+    /// Which steps this run goes through.
+    steps: Selection,
+    /// Events for `--format json`; disabled for text output.
+    report: Report,
+    /// Set by `--yes`; confirmation prompts are answered yes.
+    assume_yes: bool,
+    /// Unset by `--non-interactive` or when stdin is not a terminal; prompts
+    /// then fail with [`InstallError::PromptRequired`] instead of waiting.
+    interactive: bool,
+    /// Set for dry runs; `runner` is then a [`DryRunRunner`] feeding the same plan.
+    plan: Option<&'a Plan>,
+}
+
+impl Context<'_> {
+    fn msys2_bash(&self) -> PathBuf {
+        msys2::bash_path(&self.msys2_root)
+    }
+
+    /// Run `cmd` in an MSYS2 login shell.
+    fn msys2_shell(&self, cmd: &str) -> io::Result<CommandOutput> {
+        self.runner
+            .run(&self.msys2_bash().to_string_lossy(), &["-l", "-c", cmd])
+    }
+
+    /// Run `rustup` (or `rustup-init.exe`), pointed at the bundle's dist
+    /// server when installing offline.
+    fn rustup(&self, program: &str, args: &[&str]) -> io::Result<CommandOutput> {
+        match &self.offline {
+            Some(bundle) => {
+                let dist_server = bundle.rustup_dist_server();
+                self.runner
+                    .run_with_env(program, args, &[("RUSTUP_DIST_SERVER", &dist_server)])
+            }
+            None => self.runner.run(program, args),
+        }
+    }
+
+    /// Rust target of the GNU toolchain being installed.
+    fn rust_target(&self) -> &'static str {
+        self.manifest.msys2.env.rust_target()
+    }
+
+    fn dry_run(&self) -> bool {
+        self.plan.is_some()
+    }
+
+    /// Identifies the settings step state was saved with, so a rerun with a
+    /// different manifest, MSYS2 root or scope does not skip anything.
+    fn fingerprint(&self) -> String {
+        let settings = format!(
+            "{:?}\n{}\n{}\n{}\n{}",
+            self.manifest,
+            self.msys2_root.display(),
+            self.scope,
+            self.project_dir.display(),
+            self.offline.is_some()
+        );
+        verify::hex(&Sha256::digest(settings.as_bytes()))
+    }
+
+    /// Ask a yes/no question on the terminal; `--yes` and dry runs answer yes.
+    /// The question goes to stderr so it never mixes with `--format json`.
+    fn confirm(&self, question: &str) -> Result<bool, InstallError> {
+        if self.assume_yes || self.dry_run() {
+            return Ok(true);
+        }
+        if !self.interactive {
+            return Err(InstallError::PromptRequired(format!("{} Pass --yes to confirm", question)));
+        }
+        eprint!("{} [y/N] ", question);
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+
+    /// Print a warning and add it to the report.
+    fn warn(&self, message: &str) {
+        say!("⚠️  {}", message);
+        self.report.warn(message);
+    }
+
+    /// Add the version in a tool's `--version` output to the report.
+    fn detected(&self, tool: &str, version_output: &str) {
+        if let Some(version) = report::version_number(version_output) {
+            self.report.version(tool, version);
+        }
+    }
+
+    /// Explain why the actions that follow are needed (recorded in dry runs).
+    fn because(&self, why: &str) {
+        if let Some(plan) = self.plan {
+            plan.because(why);
+        }
+    }
+
+    /// Note an action the current decision logic skips (recorded in dry runs).
+    fn skip(&self, what: &str, why: &str) {
+        if let Some(plan) = self.plan {
+            plan.skip(what, why);
+        }
+    }
+
+    /// Download `url` to `dest`, or record the download in dry runs.
+    fn download(&self, url: &str, dest: &Path) -> Result<(), InstallError> {
+        match self.plan {
+            Some(plan) => plan.record(&format!("download {} → {}", url, dest.display())),
+            None => {
+                self.downloader.download(url, dest).map_err(|e| InstallError::Download {
+                    url: url.to_string(),
+                    reason: e.to_string(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a downloaded installer's SHA-256 (pinned, or `<url>.sha256`) and,
+    /// if requested, its detached signature. A file that fails is deleted.
+    fn verify_installer(
+        &self,
+        url: &str,
+        path: &Path,
+        pinned: Option<&str>,
+        pin_flag: &str,
+    ) -> Result<(), InstallError> {
+        let sidecar_url = format!("{}.sha256", url);
+        let signature_url = self
+            .verify
+            .signature
+            .as_ref()
+            .map(|tool| format!("{}.{}", url, tool.extension()));
+
+        if let Some(plan) = self.plan {
+            let source = if pinned.is_some() { "pinned checksum" } else { sidecar_url.as_str() };
+            plan.record(&format!("verify SHA-256 of {} against {}", path.display(), source));
+            if let Some(signature_url) = &signature_url {
+                plan.record(&format!("verify signature of {} with {}", path.display(), signature_url));
+            }
+            return Ok(());
+        }
+
+        let signature = verify::verify_download(
+            &self.downloader,
+            self.runner,
+            url,
+            path,
+            pinned,
+            pin_flag,
+            self.verify.signature.as_ref(),
+        )?;
+        if let Some(signature) = signature {
+            let _ = fs::remove_file(signature);
+        }
+        Ok(())
+    }
+
+    /// Copy `path` to a fresh backup next to it, or record the copy in dry runs.
+    fn backup_file(&self, path: &Path) -> io::Result<PathBuf> {
+        let backup = cargo_config::backup_path(path);
+        match self.plan {
+            Some(plan) => plan.record(&format!("back up {} to {}", path.display(), backup.display())),
+            None => {
+                fs::copy(path, &backup)?;
+            }
+        }
+        Ok(backup)
+    }
+
+    /// [`Context::record`], unless an equal entry is already journaled.
+    fn record_once(&self, entry: Entry) -> Result<(), InstallError> {
+        if self.dry_run() || self.journal.entries().map_err(InstallError::Other)?.contains(&entry) {
+            return Ok(());
+        }
+        Ok(self.record(entry)?)
+    }
+
+    /// Note a change in the install journal; dry runs change nothing.
+    fn record(&self, entry: Entry) -> io::Result<()> {
+        if self.dry_run() {
+            return Ok(());
+        }
+        self.journal.record(&entry)
+    }
+
+    /// Remove a file, or record the removal in dry runs.
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.plan {
+            Some(plan) => {
+                plan.record(&format!("remove {}", path.display()));
+                Ok(())
+            }
+            None => fs::remove_file(path),
+        }
+    }
+
+    /// Store a new user PATH, or record the change in dry runs.
+    fn set_user_path(&self, path: &str) -> io::Result<()> {
+        match self.plan {
+            Some(plan) => {
+                plan.record(&format!("set user PATH to {}", path));
+                Ok(())
+            }
+            None => self.user_env.set_user_path(path),
+        }
+    }
 
-    match run_installation_process() {
-        Ok(_) => println!("\n✅ Installation process completed successfully!"),
-        Err(e) => eprintln!("\n❌ Error during installation: {}", e),
+    /// Write a file, or record the write in dry runs.
+    fn write_file(&self, path: &Path, contents: &str) -> io::Result<()> {
+        match self.plan {
+            Some(plan) => {
+                plan.record(&format!("write {}", path.display()));
+                Ok(())
+            }
+            None => fs::write(path, contents),
+        }
     }
 }
 
-fn run_installation_process() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(error::EXIT_USAGE);
+        }
+    };
+    if options.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+
+    // With --format json stdout carries only the events
+    let report = Report::new(options.format);
+    let silent = report.enabled() || options.quiet;
+    output::set_silent(silent);
+    output::set_verbose(options.verbose);
+    let command = options.command.name();
+
+    say!("🦀 Rust GNU/MSYS Installation Helper for Windows");
+    say!("================================================\n");
+
+    let manifest_path = options
+        .manifest
+        .clone()
+        .or_else(|| Some(PathBuf::from(manifest::FILE_NAME)).filter(|path| path.exists()));
+    let manifest = match &manifest_path {
+        Some(path) => Manifest::load(path).inspect(|_| say!("📄 Using manifest {}\n", path.display())),
+        None => Ok(Manifest::default()),
+    };
+    let manifest = match manifest.and_then(|manifest| manifest.with_overrides(options.env, options.toolchain.as_deref())) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            report.summary(command, options.dry_run, Some(e));
+            std::process::exit(error::EXIT_USAGE);
+        }
+    };
+
+    // Bundles are built on any connected machine, not just Windows
+    if let Command::Bundle { output } = &options.command {
+        let downloader = Downloader::from_env();
+        let sources = match &options.download_base {
+            Some(base) => Sources::from_base(base),
+            None => Sources::official(manifest.msys2.env),
+        };
+        let fetcher = bundle::Fetcher {
+            downloader: &downloader,
+            runner: &SystemRunner,
+            sources: &sources,
+            verify: &options.verify,
+            install: &manifest,
+        };
+        say!("📦 Building offline bundle at {}\n", output.display());
+        let result = bundle::create(&fetcher, output);
+        if let Err(e) = &result {
+            eprintln!("\n❌ Error while building bundle: {}", e);
+        }
+        let exit_code = result.as_ref().err().map(InstallError::exit_code);
+        report.summary(command, false, result.err().map(|e| e.to_string()));
+        if let Some(code) = exit_code {
+            std::process::exit(code);
+        }
+        return;
+    }
+
     // Check if we're on Windows
     if !cfg!(target_os = "windows") {
-        return Err("This installer is designed for Windows systems only.".into());
+        let error = "This installer is designed for Windows systems only.";
+        eprintln!("\n❌ Error during installation: {}", error);
+        report.summary(command, options.dry_run, Some(error.to_string()));
+        std::process::exit(error::EXIT_FAILURE);
     }
 
-    println!("This program will help you install Rust with GNU/MSYS toolchain.");
-    println!("The GNU toolchain provides better compatibility with Unix-like tools.\n");
+    if options.command == Command::Doctor {
+        let (msys2_root, _) = msys2::resolve_root(options.msys2_root.as_deref(), &|name| std::env::var(name).ok());
+        let doctor = doctor::Doctor {
+            runner: &SystemRunner,
+            manifest: &manifest,
+            msys2_root: &msys2_root,
+            path: std::env::var_os("PATH")
+                .map(|path| std::env::split_paths(&path).collect())
+                .unwrap_or_default(),
+        };
+        say!("🩺 Checking the GNU Rust setup (MSYS2 root {})\n", msys2_root.display());
+        let checks = doctor.run();
+        if report.enabled() {
+            for check in &checks {
+                report.check(check);
+            }
+        } else {
+            doctor::print(&checks);
+        }
+        let failed = doctor::failures(&checks);
+        report.summary(command, false, (failed > 0).then(|| format!("{} check(s) failed", failed)));
+        if failed > 0 {
+            std::process::exit(error::EXIT_FAILURE);
+        }
+        return;
+    }
+
+    // Refuse to start with an incomplete bundle rather than fail halfway
+    let offline = match &options.offline {
+        Some(dir) => match Bundle::open(dir, &manifest) {
+            Ok(bundle) => {
+                say!("📦 Installing offline from {}\n", dir.display());
+                Some(bundle)
+            }
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                report.summary(command, options.dry_run, Some(e.to_string()));
+                std::process::exit(error::EXIT_FAILURE);
+            }
+        },
+        None => None,
+    };
 
-    // Step 1: Check for existing installations
-    check_existing_installations()?;
+    let plan = Plan::new();
+    let dry_runner = DryRunRunner {
+        inner: &SystemRunner,
+        plan: &plan,
+    };
+    let (msys2_root, msys2_root_source) =
+        msys2::resolve_root(options.msys2_root.as_deref(), &|name| std::env::var(name).ok());
+    let journal_path = journal::default_path(&|name| std::env::var(name).ok());
+    // CI runners and pipes cannot answer prompts; waiting would hang forever
+    let interactive = !options.non_interactive && io::stdin().is_terminal();
+    if !interactive && !options.non_interactive {
+        say!("ℹ️  stdin is not a terminal; running non-interactively\n");
+    }
+    let ctx = Context {
+        runner: if options.dry_run { &dry_runner } else { &SystemRunner },
+        msys2_root,
+        msys2_root_source,
+        downloader: Downloader::from_env(),
+        sources: match (&offline, &options.download_base) {
+            (Some(bundle), _) => bundle.sources(),
+            (None, Some(base)) => Sources::from_base(base),
+            (None, None) => Sources::official(manifest.msys2.env),
+        },
+        verify: match &offline {
+            Some(bundle) => bundle.pin(&options.verify),
+            None => options.verify.clone(),
+        },
+        manifest,
+        offline,
+        work_dir: PathBuf::from("."),
+        scope: options.scope,
+        project_dir: options
+            .project_dir
+            .clone()
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_else(|| PathBuf::from(".")),
+        cargo_home: cargo_config::cargo_home(&|name| std::env::var(name).ok()),
+        user_env: &RegistryEnvironment,
+        modify_path: !options.no_modify_path,
+        journal: Journal::at(journal_path.clone()),
+        progress: Progress::at(progress::path_beside(&journal_path)),
+        steps: options.steps,
+        report,
+        assume_yes: options.yes,
+        interactive,
+        plan: options.dry_run.then_some(&plan),
+    };
+
+    type Process = fn(&Context) -> Result<(), InstallError>;
+    let (process, name, done): (Process, &str, &str) = match options.command {
+        Command::Uninstall => (uninstall, "uninstall", "Uninstall completed successfully!"),
+        Command::Update => (update, "update", "Update completed successfully!"),
+        Command::Status => (status, "status", ""),
+        Command::Config => (configure_environment, "configuration", "Configuration completed successfully!"),
+        _ => (run_installation_process, "installation", "Installation process completed successfully!"),
+    };
 
-    // Step 2: Guide MSYS2 installation
-    guide_msys2_installation()?;
+    if options.dry_run {
+        say!("🧪 Dry run: nothing will be downloaded, installed or written.\n");
+        output::set_silent(true);
+        let result = process(&ctx);
+        output::set_silent(silent);
+
+        match &result {
+            Ok(_) if ctx.report.enabled() => {
+                for action in plan.actions() {
+                    ctx.report.planned(&action);
+                }
+            }
+            Ok(_) => plan.print(),
+            Err(e) => eprintln!("\n❌ Error while planning {}: {}", name, e),
+        }
+        let exit_code = result.as_ref().err().map(InstallError::exit_code);
+        ctx.report.summary(command, true, result.err().map(|e| e.to_string()));
+        if let Some(code) = exit_code {
+            std::process::exit(code);
+        }
+        return;
+    }
 
-    // Step 3: Install GNU toolchain
-    install_gnu_toolchain()?;
+    let result = process(&ctx);
+    match &result {
+        Ok(_) if done.is_empty() => {}
+        Ok(_) => say!("\n✅ {}", done),
+        Err(e) => eprintln!("\n❌ Error during {}: {}", name, e),
+    }
+    let exit_code = result.as_ref().err().map(InstallError::exit_code);
+    ctx.report.summary(command, false, result.err().map(|e| e.to_string()));
+    if let Some(code) = exit_code {
+        std::process::exit(code);
+    }
+}
 
-    // Step 4: Install Rust with GNU target
-    install_rust_gnu()?;
+fn run_installation_process(ctx: &Context) -> Result<(), InstallError> {
+    say!("This program will help you install Rust with GNU/MSYS toolchain.");
+    say!("The GNU toolchain provides better compatibility with Unix-like tools.\n");
 
-    // Step 5: Configure environment
-    configure_environment()?;
+    let fingerprint = ctx.fingerprint();
+    let mut completed = ctx.progress.completed(&fingerprint).map_err(InstallError::Other)?;
+    let steps = ctx.steps.steps(&completed);
+    if ctx.steps == Selection::Resume && !completed.is_empty() {
+        let done: Vec<&str> = completed.iter().map(|step| step.name()).collect();
+        say!("⏩ Resuming an interrupted installation (already completed: {})\n", done.join(", "));
+    }
 
-    // Step 6: Verify installation
-    verify_installation()?;
+    for step in Step::ALL {
+        if !steps.contains(&step) {
+            let why = match ctx.steps {
+                Selection::Resume => "completed by an earlier run",
+                Selection::From(_) => "before --from-step",
+                Selection::Only(_) => "not selected by --only-step",
+            };
+            ctx.skip(&format!("{} step", step), why);
+            ctx.report.step_skipped(step, why);
+            continue;
+        }
+        ctx.report.step_started(step);
+        if let Err(e) = run_step(ctx, step) {
+            ctx.report.step_finished(step, Some(e.to_string()));
+            if !ctx.dry_run() {
+                say!("\nRerun the installer to resume at the {} step.", step);
+            }
+            return Err(e);
+        }
+        ctx.report.step_finished(step, None);
+        if !ctx.dry_run() {
+            ctx.progress.complete(&fingerprint, &completed, step)?;
+            completed.push(step);
+        }
+    }
 
+    if !ctx.dry_run() && Step::ALL.iter().all(|step| completed.contains(step)) {
+        ctx.progress.clear()?;
+    }
     Ok(())
 }
 
-fn check_existing_installations() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔍 Checking for existing installations...\n");
+fn run_step(ctx: &Context, step: Step) -> Result<(), InstallError> {
+    match step {
+        Step::Check => check_existing_installations(ctx),
+        Step::Msys2 => guide_msys2_installation(ctx),
+        Step::Toolchain => install_gnu_toolchain(ctx),
+        Step::Rust => install_rust_gnu(ctx),
+        Step::Configure => configure_environment(ctx),
+        Step::Verify => verify_installation(ctx),
+    }
+}
+
+fn check_existing_installations(ctx: &Context) -> Result<(), InstallError> {
+    say!("🔍 Checking for existing installations...\n");
 
     // Check for rustc
-    match Command::new("rustc").arg("--version").output() {
+    match ctx.runner.run("rustc", &["--version"]) {
         Ok(output) => {
-            let version = String::from_utf8_lossy(&output.stdout);
-            println!("Found existing Rust installation: {}", version.trim());
+            let version = output.stdout;
+            say!("Found existing Rust installation: {}", version.trim());
+            ctx.detected("rustc", &version);
             
             if version.contains("msvc") {
-                println!("⚠️  Current installation uses MSVC toolchain.");
-                println!("   We'll configure GNU toolchain as an additional target.");
+                ctx.warn("Current installation uses MSVC toolchain.");
+                say!("   We'll configure GNU toolchain as an additional target.");
             }
         }
-        Err(_) => println!("No existing Rust installation found."),
+        Err(_) => say!("No existing Rust installation found."),
     }
 
     // Check for MSYS2
-    let msys2_paths = [
-        "C:\\msys64\\usr\\bin\\bash.exe",
-        "C:\\msys32\\usr\\bin\\bash.exe",
-    ];
-
-    let mut msys2_found = false;
-    for path in &msys2_paths {
-        if Path::new(path).exists() {
-            println!("✅ Found MSYS2 installation at: {}", path);
-            msys2_found = true;
-            break;
-        }
-    }
-
-    if !msys2_found {
-        println!("❌ MSYS2 not found. Installation will be required.");
+    say!(
+        "Using MSYS2 root: {} ({})",
+        ctx.msys2_root.display(),
+        ctx.msys2_root_source
+    );
+    if ctx.msys2_bash().exists() {
+        say!("✅ Found MSYS2 installation at: {}", ctx.msys2_bash().display());
+    } else {
+        say!("❌ MSYS2 not found. Installation will be required.");
     }
 
-    println!();
+    say!();
     Ok(())
 }
 
-fn guide_msys2_installation() -> Result<(), Box<dyn std::error::Error>> {
-    println!("📦 MSYS2 Installation");
-    println!("--------------------");
+fn guide_msys2_installation(ctx: &Context) -> Result<(), InstallError> {
+    say!("📦 MSYS2 Installation");
+    say!("--------------------");
 
     // Check if MSYS2 is already installed
-    if Path::new("C:\\msys64\\usr\\bin\\bash.exe").exists() {
-        println!("✅ MSYS2 is already installed.");
+    if ctx.msys2_bash().exists() {
+        say!("✅ MSYS2 is already installed.");
+        ctx.record_once(Entry::Msys2Found {
+            root: ctx.msys2_root.clone(),
+        })?;
+        ctx.skip(
+            "download, install and initialize MSYS2",
+            &format!("MSYS2 is already installed at {}", ctx.msys2_root.display()),
+        );
+        configure_pacman(ctx)?;
         return Ok(());
     }
 
-    println!("MSYS2 not found. Installing automatically...");
+    say!("MSYS2 not found. Installing automatically...");
+    ctx.because(&format!("MSYS2 not found at {}", ctx.msys2_bash().display()));
     
     // Download and install MSYS2
-    download_and_install_msys2()?;
+    download_and_install_msys2(ctx)?;
+    ctx.record(Entry::Msys2Installed {
+        root: ctx.msys2_root.clone(),
+    })?;
+
+    // Mirrors have to be right before the first pacman -Sy
+    configure_pacman(ctx)?;
     
     // Initialize MSYS2
-    initialize_msys2()?;
+    initialize_msys2(ctx)?;
 
     // Verify MSYS2 installation
-    if !Path::new("C:\\msys64\\usr\\bin\\bash.exe").exists() {
-        return Err("MSYS2 installation failed. Please try manual installation from https://www.msys2.org/".into());
+    if !ctx.dry_run() && !ctx.msys2_bash().exists() {
+        return Err(InstallError::Msys2 {
+            reason: "MSYS2 installation failed. Please try manual installation from https://www.msys2.org/".to_string(),
+        });
     }
 
-    println!("✅ MSYS2 installation completed successfully!");
-    println!();
+    say!("✅ MSYS2 installation completed successfully!");
+    say!();
     Ok(())
 }
 
-fn download_and_install_msys2() -> Result<(), Box<dyn std::error::Error>> {
-    println!("📥 Downloading MSYS2 installer...");
+fn download_and_install_msys2(ctx: &Context) -> Result<(), InstallError> {
+    say!("📥 Downloading MSYS2 installer...");
     
     // Download MSYS2 installer
-    let installer_url = "https://github.com/msys2/msys2-installer/releases/latest/download/msys2-x86_64-latest.exe";
-    let installer_path = "msys2-installer.exe";
-    
-    // Use PowerShell to download the file (available on all Windows systems)
-    let download_cmd = format!(
-        "Invoke-WebRequest -Uri '{}' -OutFile '{}' -UseBasicParsing",
-        installer_url, installer_path
-    );
+    let installer_url = &ctx.sources.msys2_installer;
+    let installer_path = ctx.work_dir.join("msys2-installer.exe");
     
-    println!("Downloading from: {}", installer_url);
-    let download_output = Command::new("powershell")
-        .args(&["-Command", &download_cmd])
-        .output()?;
+    say!("Downloading from: {}", installer_url);
+    ctx.download(installer_url, &installer_path)?;
     
-    if !download_output.status.success() {
-        let error_msg = String::from_utf8_lossy(&download_output.stderr);
-        if error_msg.contains("cannot be loaded because running scripts is disabled") {
-            return Err("PowerShell execution policy blocks downloads. Please run as administrator or enable PowerShell scripts.".into());
+    if !ctx.dry_run() {
+        if !installer_path.exists() {
+            return Err(InstallError::Download {
+                url: installer_url.to_string(),
+                reason: format!("{} not found after downloading", installer_path.display()),
+            });
         }
-        return Err(format!(
-            "Failed to download MSYS2 installer: {}",
-            error_msg
-        ).into());
-    }
-    
-    if !Path::new(installer_path).exists() {
-        return Err("MSYS2 installer download failed - file not found".into());
+
+        say!("✅ Download completed successfully ({} MB)", 
+                 fs::metadata(&installer_path)?.len() / 1_000_000);
     }
-    
-    println!("✅ Download completed successfully ({} MB)", 
-             fs::metadata(installer_path)?.len() / 1_000_000);
+    ctx.verify_installer(
+        installer_url,
+        &installer_path,
+        ctx.verify.msys2_sha256.as_deref(),
+        "--msys2-sha256",
+    )?;
     
     // Run the installer silently
-    println!("🚀 Running MSYS2 installer...");
-    println!("   Installing to C:\\msys64...");
-    println!("   This may take several minutes, please wait...");
+    say!("🚀 Running MSYS2 installer...");
+    say!("   Installing to {}...", ctx.msys2_root.display());
+    say!("   This may take several minutes, please wait...");
     
     // Try silent installation first
-    let install_output = Command::new(installer_path)
-        .args(&[
+    let install_output = ctx.runner.run(
+        &installer_path.to_string_lossy(),
+        &[
             "install",
             "--confirm-command",
             "--accept-messages", 
-            "--root", "C:\\msys64"
-        ])
-        .output()?;
+            "--root", &ctx.msys2_root.to_string_lossy(),
+        ],
+    )?;
     
-    if !install_output.status.success() {
-        println!("⚠️  Silent installation failed, trying alternative method...");
+    if !install_output.success() && !ctx.interactive {
+        return Err(InstallError::PromptRequired(format!(
+            "The silent MSYS2 installation failed ({}); retrying elevated or installing by hand needs a user",
+            install_output.stderr.lines().next().unwrap_or("no error output")
+        )));
+    }
+    if !install_output.success() {
+        ctx.warn("Silent installation failed, trying alternative method...");
         
         // Try running with elevated permissions request
         let powershell_cmd = format!(
-            "Start-Process -FilePath '{}' -ArgumentList 'install --confirm-command --accept-messages --root C:\\msys64' -Verb RunAs -Wait",
-            installer_path
+            "Start-Process -FilePath '{}' -ArgumentList 'install --confirm-command --accept-messages --root \"{}\"' -Verb RunAs -Wait",
+            installer_path.display(),
+            ctx.msys2_root.display()
         );
         
-        let elevated_output = Command::new("powershell")
-            .args(&["-Command", &powershell_cmd])
-            .output()?;
+        let elevated_output = ctx.runner.run("powershell", &["-Command", &powershell_cmd])?;
+        if !elevated_output.success() && error::is_execution_policy_error(&elevated_output.stderr) {
+            return Err(InstallError::ExecutionPolicy {
+                command: format!("powershell -Command {}", powershell_cmd),
+            });
+        }
         
-        if !elevated_output.status.success() {
-            println!("❌ Automated installation failed.");
-            println!("📝 Please install MSYS2 manually:");
-            println!("   1. Double-click the downloaded installer: {}", installer_path);
-            println!("   2. Follow the installation wizard");
-            println!("   3. Install to C:\\msys64 (default location)");
-            println!("   4. Complete the installation");
-            println!();
+        if !elevated_output.success() {
+            say!("❌ Automated installation failed.");
+            say!("📝 Please install MSYS2 manually:");
+            say!("   1. Double-click the downloaded installer: {}", installer_path.display());
+            say!("   2. Follow the installation wizard");
+            say!("   3. Install to {}", ctx.msys2_root.display());
+            say!("   4. Complete the installation");
+            say!();
             
-            print!("Press Enter when manual installation is complete...");
-            io::stdout().flush()?;
+            // On stderr, like confirm(), so it never mixes with --format json
+            eprint!("Press Enter when manual installation is complete...");
             let mut input = String::new();
             io::stdin().read_line(&mut input)?;
         }
     }
     
     // Clean up installer file
-    let _ = fs::remove_file(installer_path);
+    let _ = ctx.remove_file(&installer_path);
     
     // Verify installation
-    if !Path::new("C:\\msys64").exists() {
-        return Err("MSYS2 installation directory not found. Installation may have failed.".into());
+    if !ctx.dry_run() && !ctx.msys2_root.exists() {
+        return Err(InstallError::Msys2 {
+            reason: "MSYS2 installation directory not found. Installation may have failed.".to_string(),
+        });
     }
     
-    println!("✅ MSYS2 installation completed");
+    say!("✅ MSYS2 installation completed");
+    Ok(())
+}
+
+/// Apply the manifest's pacman settings: point the mirrorlists at
+/// `msys2.mirror` or rank them by latency, and add `msys2.repos` to
+/// `pacman.conf`. Changed files are backed up and journaled.
+fn configure_pacman(ctx: &Context) -> Result<(), InstallError> {
+    let msys2 = &ctx.manifest.msys2;
+    if msys2.mirror.is_none() && !msys2.rank_mirrors && msys2.repos.is_empty() {
+        return Ok(());
+    }
+    say!("🪞 Configuring pacman...");
+
+    let mirrorlists = pacman_config::mirrorlists(&ctx.msys2_root)?;
+    if (msys2.mirror.is_some() || msys2.rank_mirrors) && mirrorlists.is_empty() {
+        // Dry runs before MSYS2 is installed have nothing to read yet
+        let pending = ctx.msys2_root.join("etc").join("pacman.d").join("mirrorlist.*");
+        if ctx.dry_run() {
+            ctx.because("msys2.mirror / msys2.rank-mirrors rewrite the mirrorlists MSYS2 installs");
+            ctx.write_file(&pending, "")?;
+        } else {
+            ctx.warn(&format!("No mirrorlists found at {}; mirror settings not applied", pending.display()));
+        }
+    } else if let Some(mirror) = &msys2.mirror {
+        for path in &mirrorlists {
+            let existing = fs::read_to_string(path)?;
+            match pacman_config::use_mirror(&existing, mirror) {
+                Some(rewritten) => {
+                    ctx.because(&format!("msys2.mirror points pacman at {}", mirror));
+                    replace_pacman_file(ctx, path, &existing, &rewritten)?;
+                }
+                None => ctx.warn(&format!("{} lists no server; left unchanged", path.display())),
+            }
+        }
+    } else if msys2.rank_mirrors && ctx.offline.is_some() {
+        say!("   Ranking mirrors: skipped (offline)");
+        ctx.skip("rank pacman mirrors", "offline install; no mirror is used");
+    } else if msys2.rank_mirrors && ctx.dry_run() {
+        ctx.because("msys2.rank-mirrors orders the mirrors by latency, measured when the install runs");
+        for path in &mirrorlists {
+            ctx.backup_file(path)?;
+            ctx.write_file(path, "")?;
+        }
+    } else if msys2.rank_mirrors {
+        let lists = mirrorlists
+            .iter()
+            .map(|path| Ok((path, fs::read_to_string(path)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut probes: Vec<String> = lists
+            .iter()
+            .flat_map(|(_, text)| pacman_config::servers(text))
+            .map(|server| pacman_config::probe_url(server).to_string())
+            .collect();
+        probes.sort();
+        probes.dedup();
+        say!("   Measuring {} mirror(s)...", probes.len());
+        let latencies = pacman_config::measure(&ctx.downloader, &probes);
+        let latency = |url: &str| latencies.get(pacman_config::probe_url(url)).copied().flatten();
+        if latencies.values().all(Option::is_none) {
+            ctx.warn("No mirror answered (check the proxy settings); the mirrorlists keep their order");
+        } else {
+            for (path, existing) in &lists {
+                let ranked = pacman_config::rank(existing, &latency);
+                let fastest = pacman_config::servers(&ranked).into_iter().find_map(|server| Some((server, latency(server)?)));
+                match fastest {
+                    Some((server, time)) => say!("   {}: fastest is {} ({} ms)", path.display(), server, time.as_millis()),
+                    None => say!("   {}: no mirror answered; order unchanged", path.display()),
+                }
+                replace_pacman_file(ctx, path, existing, &ranked)?;
+            }
+        }
+    }
+
+    if !msys2.repos.is_empty() {
+        let conf_path = pacman_config::conf_path(&ctx.msys2_root);
+        let names: Vec<&str> = msys2.repos.iter().map(|repo| repo.name.as_str()).collect();
+        ctx.because(&format!("msys2.repos adds [{}] to pacman.conf", names.join("], [")));
+        match fs::read_to_string(&conf_path) {
+            Ok(existing) => {
+                let updated = msys2.repos.iter().fold(existing.clone(), |conf, repo| pacman_config::with_repo(&conf, repo));
+                replace_pacman_file(ctx, &conf_path, &existing, &updated)?;
+            }
+            Err(_) if ctx.dry_run() => ctx.write_file(&conf_path, "")?,
+            Err(e) => return Err(InstallError::Other(format!("Cannot read {}: {}", conf_path.display(), e))),
+        }
+    }
+    Ok(())
+}
+
+/// Write `updated` over `path`, which holds `existing`, keeping a backup.
+fn replace_pacman_file(ctx: &Context, path: &Path, existing: &str, updated: &str) -> Result<(), InstallError> {
+    if updated == existing {
+        say!("   ✅ {} is already set up", path.display());
+        ctx.skip(&format!("update {}", path.display()), "it already has these settings");
+        return Ok(());
+    }
+    let backup = ctx.backup_file(path)?;
+    ctx.write_file(path, updated)?;
+    ctx.record(Entry::FileWritten {
+        path: path.to_path_buf(),
+        backup: Some(backup.clone()),
+    })?;
+    say!("   ✅ Updated {} (original saved as {})", path.display(), backup.display());
     Ok(())
 }
 
-fn initialize_msys2() -> Result<(), Box<dyn std::error::Error>> {
-    println!("⚙️  Initializing MSYS2...");
+fn initialize_msys2(ctx: &Context) -> Result<(), InstallError> {
+    say!("⚙️  Initializing MSYS2...");
     
-    let msys2_bash = "C:\\msys64\\usr\\bin\\bash.exe";
+    let msys2_bash = ctx.msys2_bash();
     
     // Wait for installation to settle and files to be ready
-    print!("   Waiting for MSYS2 to be ready");
-    for _ in 0..10 {
-        print!(".");
-        io::stdout().flush()?;
-        thread::sleep(Duration::from_secs(1));
-        
-        if Path::new(msys2_bash).exists() {
-            break;
+    if !ctx.dry_run() {
+        // The dots share one line, which say! cannot do; gate them like it instead
+        let show = !output::is_silent();
+        if show {
+            print!("   Waiting for MSYS2 to be ready");
+        }
+        for _ in 0..10 {
+            if show {
+                print!(".");
+                io::stdout().flush()?;
+            }
+            if msys2_bash.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        say!(" ✅");
+
+        if !msys2_bash.exists() {
+            return Err(InstallError::Msys2 {
+                reason: "MSYS2 bash not found after installation. Installation may be incomplete.".to_string(),
+            });
         }
     }
-    println!(" ✅");
-    
-    if !Path::new(msys2_bash).exists() {
-        return Err("MSYS2 bash not found after installation. Installation may be incomplete.".into());
-    }
+    ctx.because("a fresh MSYS2 needs its keyring initialized and packages updated");
     
     // Initialize MSYS2 keyring and update packages
     let init_commands = [
         ("Initializing keyring", "pacman-key --init"),
         ("Populating keyring", "pacman-key --populate msys2"),
         ("Updating package database", "pacman -Sy --noconfirm"),
-        ("Updating system packages", "pacman -Syu --noconfirm --disable-download-timeout"),
+        ("Updating system packages", SYSTEM_UPGRADE),
     ];
     
     for (description, cmd) in &init_commands {
-        println!("   {}: {}", description, cmd);
-        
-        let output = Command::new(msys2_bash)
-            .args(&["-l", "-c", cmd])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()?;
+        if ctx.offline.is_some() && cmd.starts_with("pacman -Sy") {
+            say!("   {}: skipped (offline)", description);
+            ctx.skip(cmd, "offline install; packages come from the bundle");
+            continue;
+        }
+        say!("   {}: {}", description, cmd);
+        if *cmd == SYSTEM_UPGRADE {
+            upgrade_system(ctx)?;
+            say!("     ✅ Completed successfully");
+            continue;
+        }
         
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            
-            // Check for common non-critical messages
-            if stderr.contains("nothing to do") 
-                || stderr.contains("there is nothing to do") 
-                || stdout.contains("there is nothing to do") {
-                println!("     ✅ No updates needed");
-                continue;
-            }
-            
+        let output = ctx.msys2_shell(cmd)?;
+        let transaction = Transaction::parse(&output);
+        match &transaction.failure {
+            None if transaction.nothing_to_do => say!("     ✅ No updates needed"),
+            None => say!("     ✅ Completed successfully"),
             // Some warnings during first-time setup are normal
-            if stderr.contains("warning") && !stderr.contains("error") {
-                println!("     ⚠️  Warning (continuing): {}", stderr.lines().next().unwrap_or(""));
-                continue;
+            Some(_) if transaction.errors.is_empty() && !transaction.warnings.is_empty() => {
+                say!("     ⚠️  Warning (continuing): {}", transaction.warnings[0]);
+            }
+            Some(failure) => {
+                eprintln!("     ❌ Failed: {}", output.stderr);
+                return Err(InstallError::Pacman {
+                    package: failure.packages().first().cloned(),
+                    reason: format!("{}: {}", description, failure),
+                });
             }
-            
-            eprintln!("     ❌ Failed: {}", stderr);
-            return Err(format!("MSYS2 initialization step failed: {}", description).into());
-        } else {
-            println!("     ✅ Completed successfully");
         }
     }
     
@@ -275,330 +922,970 @@ fn initialize_msys2() -> Result<(), Box<dyn std::error::Error>> {
         "gcc --version",
     ];
     
-    println!("   Verifying installation...");
+    say!("   Verifying installation...");
     for cmd in &verification_commands {
-        let output = Command::new(msys2_bash)
-            .args(&["-l", "-c", cmd])
-            .output();
+        let output = ctx.msys2_shell(cmd);
             
         match output {
-            Ok(out) if out.status.success() => {
+            Ok(out) if out.success() => {
                 // Extract first line of output for verification
-                let stdout_str = String::from_utf8_lossy(&out.stdout);
-                let first_line = stdout_str
+                let first_line = out
+                    .stdout
                     .lines()
                     .next()
                     .unwrap_or("")
                     .trim();
                 if !first_line.is_empty() {
-                    println!("     ✅ {}: {}", cmd.split_whitespace().next().unwrap(), first_line);
+                    say!("     ✅ {}: {}", cmd.split_whitespace().next().unwrap(), first_line);
                 }
             }
             _ => {
-                println!("     ⚠️  {} not yet available (will install with toolchain)", 
+                say!("     ⚠️  {} not yet available (will install with toolchain)", 
                          cmd.split_whitespace().next().unwrap());
             }
         }
     }
     
-    println!("✅ MSYS2 initialization completed successfully");
+    say!("✅ MSYS2 initialization completed successfully");
     Ok(())
 }
 
-fn install_gnu_toolchain() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔧 Installing GNU Toolchain");
-    println!("---------------------------");
+const SYSTEM_UPGRADE: &str = "pacman -Syu --noconfirm --disable-download-timeout";
 
-    let msys2_bash = "C:\\msys64\\usr\\bin\\bash.exe";
-    
-    if !Path::new(msys2_bash).exists() {
-        return Err("MSYS2 bash not found. Please install MSYS2 first.".into());
+/// How many `pacman -Syu` runs to allow before giving up; a fresh MSYS2 needs
+/// two (the core system, then everything else), an old one sometimes three.
+const MAX_SYSTEM_UPGRADES: usize = 5;
+
+/// Run `pacman -Syu` until it finishes without a core system upgrade. After
+/// a core upgrade pacman closes every MSYS2 process; the ones it could not
+/// close (a gpg-agent started by `pacman-key`, say) are stopped before the
+/// next run, which needs the new runtime.
+fn upgrade_system(ctx: &Context) -> Result<(), InstallError> {
+    for pass in 1..=MAX_SYSTEM_UPGRADES {
+        let output = ctx.msys2_shell(SYSTEM_UPGRADE)?;
+        let transaction = Transaction::parse(&output);
+        // pacman kills its own shell, so the exit code says nothing here
+        if transaction.core_upgrade && transaction.errors.is_empty() {
+            say!(
+                "     🔁 Core system upgraded; restarting MSYS2 (pass {} of {})",
+                pass,
+                MAX_SYSTEM_UPGRADES
+            );
+            stop_msys2_processes(ctx);
+            continue;
+        }
+        if let Some(failure) = transaction.failure {
+            return Err(InstallError::pacman(None, &failure));
+        }
+        return Ok(());
+    }
+    Err(InstallError::Pacman {
+        package: None,
+        reason: format!(
+            "MSYS2 still had core updates after {} runs of `{}`; run it by hand in an MSYS2 shell",
+            MAX_SYSTEM_UPGRADES, SYSTEM_UPGRADE
+        ),
+    })
+}
+
+/// Stop every process that has the MSYS2 runtime loaded. Often none is left,
+/// so a failure is only worth a detail line.
+fn stop_msys2_processes(ctx: &Context) {
+    match ctx.runner.run("taskkill", &["/F", "/FI", "MODULES eq msys-2.0.dll"]) {
+        // Give Windows a moment to release the runtime DLL
+        Ok(output) if output.success() && !ctx.dry_run() => thread::sleep(Duration::from_secs(1)),
+        Ok(output) => detail!("     taskkill: {}", output.stdout.trim()),
+        Err(e) => detail!("     taskkill: {}", e),
     }
+}
 
-    println!("Installing GNU toolchain packages via MSYS2...");
+fn install_gnu_toolchain(ctx: &Context) -> Result<(), InstallError> {
+    say!("🔧 Installing GNU Toolchain");
+    say!("---------------------------");
 
-    // Install mingw-w64 toolchain
-    let install_commands = [
-        ("Core toolchain", "pacman -S --noconfirm mingw-w64-x86_64-toolchain"),
-        ("CMake", "pacman -S --noconfirm mingw-w64-x86_64-cmake"),
-        ("pkg-config", "pacman -S --noconfirm mingw-w64-x86_64-pkgconf"), // Updated package name
-        ("OpenSSL", "pacman -S --noconfirm mingw-w64-x86_64-openssl"),
-        ("Additional tools", "pacman -S --noconfirm mingw-w64-x86_64-make"),
-    ];
+    if !ctx.dry_run() && !ctx.msys2_bash().exists() {
+        return Err(InstallError::Msys2 {
+            reason: "MSYS2 bash not found. Please install MSYS2 first.".to_string(),
+        });
+    }
+    let installed_before = installed_packages(ctx)?;
 
-    let mut failed_packages = Vec::new();
+    if let Some(bundle) = &ctx.offline {
+        let packages = msys2::to_msys_path(&bundle.packages_dir());
+        let cmd = format!(
+            "shopt -s nullglob; pacman -U --noconfirm --needed '{0}'/*.pkg.tar.zst '{0}'/*.pkg.tar.xz",
+            packages
+        );
+        say!("Installing bundled packages from {}...", bundle.packages_dir().display());
+        ctx.because("offline install; the mingw-w64 packages come from the bundle");
+        let output = ctx.msys2_shell(&cmd)?;
+        if let Some(failure) = Transaction::parse(&output).failure {
+            return Err(InstallError::pacman(None, &failure));
+        }
+        record_new_packages(ctx, &installed_before)?;
+        say!("✅ GNU toolchain installation completed!");
+        say!();
+        return Ok(());
+    }
 
-    for (description, cmd) in &install_commands {
-        println!("Installing {}: {}", description, cmd);
-        let output = Command::new(msys2_bash)
-            .args(&["-l", "-c", cmd])
-            .output()?;
+    say!("Installing GNU toolchain packages via MSYS2...");
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            
-            // Check if it's already installed or just a warning
-            if stderr.contains("nothing to do") || stderr.contains("up to date") {
-                println!("✅ {} - already up to date", description);
-            } else if stderr.contains("could not satisfy dependencies") || stderr.contains("target not found") {
-                println!("⚠️  {} - skipped (dependency issue or already installed)", description);
-                failed_packages.push(description);
+    // The mingw-w64 toolchain for each environment, then any packages the manifest adds
+    let mut wanted: Vec<(String, String)> = Vec::new();
+    for env in ctx.manifest.environments() {
+        for (description, package) in env.toolchain_packages() {
+            let description = if env == ctx.manifest.msys2.env {
+                description.to_string()
             } else {
-                println!("❌ {} - failed: {}", description, stderr.lines().next().unwrap_or("Unknown error"));
-                failed_packages.push(description);
+                format!("{} ({})", description, env)
+            };
+            wanted.push((description, package));
+        }
+    }
+    for package in &ctx.manifest.msys2.packages {
+        if !wanted.iter().any(|(_, name)| name == package) {
+            wanted.push(("Extra package".to_string(), package.clone()));
+        }
+    }
+    let description = |package: &str| {
+        wanted
+            .iter()
+            .find(|(_, name)| name == package)
+            .map_or(package.to_string(), |(description, _)| description.clone())
+    };
+
+    let mut packages = missing_packages(ctx, &wanted, &installed_before)?;
+    for (description, package) in &wanted {
+        if !packages.contains(package) {
+            say!("✅ {} - already installed", description);
+        }
+    }
+    if packages.is_empty() {
+        say!("✅ All {} packages are already installed", wanted.len());
+        ctx.skip("pacman -S", "every toolchain package is already installed");
+        say!();
+        return Ok(());
+    }
+    ctx.because("install the missing mingw-w64 packages in one transaction");
+
+    let mut skipped: Vec<String> = Vec::new();
+    loop {
+        let cmd = format!("pacman -S --needed --noconfirm {}", packages.join(" "));
+        say!("Installing {} package(s): {}", packages.len(), cmd);
+        let transaction = Transaction::parse(&ctx.msys2_shell(&cmd)?);
+        match transaction.failure {
+            None => {
+                say!(
+                    "✅ {} installed, {} upgraded",
+                    transaction.installed.len(),
+                    transaction.upgraded.len()
+                );
+                break;
+            }
+            // pacman refuses the whole transaction over one unknown name;
+            // leave those out once and install the rest
+            Some(Failure::MissingTarget(targets))
+                if skipped.is_empty()
+                    && !targets.is_empty()
+                    && targets.iter().all(|target| packages.contains(target))
+                    && targets.len() < packages.len() =>
+            {
+                for target in &targets {
+                    ctx.warn(&format!("{} - skipped (target not found: {})", description(target), target));
+                }
+                packages.retain(|package| !targets.contains(package));
+                skipped = targets;
+            }
+            Some(failure) => {
+                say!("❌ Installing the toolchain packages failed: {}", failure);
+                return Err(InstallError::pacman(None, &failure));
             }
-        } else {
-            println!("✅ {} - installed successfully", description);
         }
     }
 
-    if !failed_packages.is_empty() {
-        println!("\n⚠️  Some packages had issues but core toolchain should still work:");
-        for pkg in failed_packages {
-            println!("   - {}", pkg);
+    if !skipped.is_empty() {
+        say!("\n⚠️  Some packages were not found but core toolchain should still work:");
+        for package in &skipped {
+            say!("   - {}", description(package));
         }
-        println!("   This is usually not a problem for basic Rust development.");
+        say!("   This is usually not a problem for basic Rust development.");
     }
 
-    println!("✅ GNU toolchain installation completed!");
-    println!();
+    record_new_packages(ctx, &installed_before)?;
+    say!("✅ GNU toolchain installation completed!");
+    say!();
     Ok(())
 }
 
-fn install_rustup_automatically() -> Result<(), Box<dyn std::error::Error>> {
-    println!("📥 Downloading rustup installer...");
+/// Names of the installed pacman packages; empty in dry runs, which record
+/// nothing in the journal.
+fn installed_packages(ctx: &Context) -> io::Result<BTreeSet<String>> {
+    if ctx.dry_run() {
+        return Ok(BTreeSet::new());
+    }
+    let output = ctx.msys2_shell("pacman -Qq")?;
+    Ok(output.stdout.split_whitespace().map(str::to_string).collect())
+}
+
+/// The `wanted` packages that are not installed yet. Groups such as
+/// `mingw-w64-x86_64-toolchain` are expanded with `pacman -Sg` so a group
+/// whose members are all present is not reinstalled. Dry runs, which do not
+/// query pacman, treat everything as missing.
+fn missing_packages(ctx: &Context, wanted: &[(String, String)], installed: &BTreeSet<String>) -> io::Result<Vec<String>> {
+    let names: Vec<String> = wanted.iter().map(|(_, package)| package.clone()).collect();
+    if ctx.dry_run() {
+        return Ok(names);
+    }
+    let candidates: Vec<String> = names.iter().filter(|name| !installed.contains(*name)).cloned().collect();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+    let output = ctx.msys2_shell(&format!("pacman -Sg {}", candidates.join(" ")))?;
+    Ok(pacman::missing(&names, installed, &pacman::groups(&output.stdout)))
+}
+
+/// Journal the packages (dependencies included) installed since `before`.
+fn record_new_packages(ctx: &Context, before: &BTreeSet<String>) -> io::Result<()> {
+    let packages: Vec<String> = installed_packages(ctx)?.difference(before).cloned().collect();
+    if packages.is_empty() {
+        return Ok(());
+    }
+    ctx.record(Entry::PackagesInstalled {
+        root: ctx.msys2_root.clone(),
+        packages,
+    })
+}
+
+/// Installed rustup targets or components (`kind`), with the host suffix of
+/// component names stripped; empty in dry runs.
+fn installed_rust_parts(ctx: &Context, kind: &str, toolchain: Option<&str>) -> io::Result<BTreeSet<String>> {
+    if ctx.dry_run() {
+        return Ok(BTreeSet::new());
+    }
+    let mut args = vec![kind, "list", "--installed"];
+    if let Some(toolchain) = toolchain {
+        args.extend(["--toolchain", toolchain]);
+    }
+    let output = ctx.rustup("rustup", &args)?;
+    let suffix = format!("-{}", ctx.rust_target());
+    Ok(output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .flat_map(|line| [line.to_string(), line.trim_end_matches(suffix.as_str()).to_string()])
+        .collect())
+}
+
+/// First word of a rustup line like `stable-x86_64-pc-windows-msvc (default)`.
+fn toolchain_name(line: &str) -> Option<String> {
+    line.split_whitespace()
+        .next()
+        .filter(|name| !name.is_empty() && *name != "no")
+        .map(str::to_string)
+}
+
+/// What `rustup override set` and `rustup default` replace, for the journal.
+fn previous_toolchain(ctx: &Context) -> io::Result<Option<String>> {
+    if ctx.dry_run() {
+        return Ok(None);
+    }
+    if ctx.scope == Scope::User {
+        let output = ctx.rustup("rustup", &["default"])?;
+        return Ok(output.success().then(|| toolchain_name(&output.stdout)).flatten());
+    }
+    // `rustup override list` prints `<path>\t<toolchain>` per override
+    let output = ctx.rustup("rustup", &["override", "list"])?;
+    let project = ctx.project_dir.to_string_lossy().trim_end_matches(['\\', '/']).to_lowercase();
+    Ok(output.stdout.lines().find_map(|line| {
+        let (path, toolchain) = line.trim_end().rsplit_once(char::is_whitespace)?;
+        (path.trim().trim_end_matches(['\\', '/']).to_lowercase() == project).then(|| toolchain.to_string())
+    }))
+}
+
+fn install_rustup_automatically(ctx: &Context) -> Result<(), InstallError> {
+    say!("📥 Downloading rustup installer...");
     
-    let rustup_url = "https://win.rustup.rs/x86_64";
-    let installer_path = "rustup-init.exe";
+    let rustup_url = &ctx.sources.rustup_init;
+    let installer_path = ctx.work_dir.join("rustup-init.exe");
     
     // Download rustup-init.exe
-    let download_cmd = format!(
-        "Invoke-WebRequest -Uri '{}' -OutFile '{}' -UseBasicParsing",
-        rustup_url, installer_path
-    );
-    
-    println!("Downloading from: {}", rustup_url);
-    let download_output = Command::new("powershell")
-        .args(&["-Command", &download_cmd])
-        .output()?;
-    
-    if !download_output.status.success() {
-        let error_msg = String::from_utf8_lossy(&download_output.stderr);
-        return Err(format!("Failed to download rustup installer: {}", error_msg).into());
-    }
+    say!("Downloading from: {}", rustup_url);
+    ctx.download(rustup_url, &installer_path)?;
     
-    if !Path::new(installer_path).exists() {
-        return Err("rustup installer download failed - file not found".into());
+    if !ctx.dry_run() && !installer_path.exists() {
+        return Err(InstallError::Download {
+            url: rustup_url.to_string(),
+            reason: format!("{} not found after downloading", installer_path.display()),
+        });
     }
     
-    println!("✅ rustup installer downloaded successfully");
+    say!("✅ rustup installer downloaded successfully");
+    ctx.verify_installer(
+        rustup_url,
+        &installer_path,
+        ctx.verify.rustup_sha256.as_deref(),
+        "--rustup-sha256",
+    )?;
     
     // Install rustup with GNU as default target
-    println!("🚀 Installing rustup with GNU toolchain...");
-    println!("   This will install Rust with x86_64-pc-windows-gnu as default");
-    
-    let install_output = Command::new(installer_path)
-        .args(&[
-            "--default-host", "x86_64-pc-windows-gnu",
-            "--default-toolchain", "stable",
-            "--profile", "default",
-            "-y"  // Accept all defaults
-        ])
-        .output()?;
+    say!("🚀 Installing rustup with GNU toolchain...");
+    say!("   This will install Rust with {} as default", ctx.rust_target());
+
+    let rust = &ctx.manifest.rust;
+    let mut args = vec![
+        "--default-host", ctx.rust_target(),
+        "--default-toolchain", &rust.channel,
+        "--profile", &rust.profile,
+    ];
+    for component in &rust.components {
+        args.extend(["--component", component]);
+    }
+    for target in &rust.targets {
+        args.extend(["--target", target]);
+    }
+    args.push("-y"); // Accept all defaults
+    let install_output = ctx.rustup(&installer_path.to_string_lossy(), &args)?;
     
     // Clean up installer
-    let _ = fs::remove_file(installer_path);
+    let _ = ctx.remove_file(&installer_path);
     
-    if !install_output.status.success() {
-        let stderr = String::from_utf8_lossy(&install_output.stderr);
-        return Err(format!("rustup installation failed: {}", stderr).into());
+    if !install_output.success() {
+        return Err(InstallError::Rustup {
+            command: "installation (rustup-init.exe)".to_string(),
+            reason: install_output.stderr.lines().next().unwrap_or("Unknown error").to_string(),
+        });
     }
+    ctx.record(Entry::RustupInstalled)?;
     
-    println!("✅ rustup installation completed successfully!");
+    say!("✅ rustup installation completed successfully!");
     
     // Verify installation
-    match Command::new("rustup").arg("--version").output() {
+    match ctx.runner.run("rustup", &["--version"]) {
         Ok(output) => {
-            let version = String::from_utf8_lossy(&output.stdout);
-            println!("✅ Verified rustup installation: {}", version.trim());
+            let version = output.stdout;
+            say!("✅ Verified rustup installation: {}", version.trim());
+            ctx.detected("rustup", &version);
         }
         Err(_) => {
-            println!("⚠️  rustup installed but not immediately available in PATH");
-            println!("   You may need to restart your terminal or run:");
-            println!("   source ~/.cargo/env");
+            ctx.warn("rustup installed but not immediately available in PATH");
+            say!("   You may need to restart your terminal or run:");
+            say!("   source ~/.cargo/env");
         }
     }
     
     Ok(())
 }
 
-fn install_rust_gnu() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🦀 Installing Rust with GNU Target");
-    println!("----------------------------------");
+fn install_rust_gnu(ctx: &Context) -> Result<(), InstallError> {
+    say!("🦀 Installing Rust with GNU Target");
+    say!("----------------------------------");
 
     // Check if rustup is available
-    match Command::new("rustup").arg("--version").output() {
-        Ok(_) => {
-            println!("✅ rustup found. Adding GNU target...");
+    match ctx.runner.run("rustup", &["--version"]) {
+        Ok(output) => {
+            ctx.detected("rustup", &output.stdout);
+            say!("✅ rustup found. Adding GNU target...");
+            ctx.because("rustup is installed; the GNU target is added if missing");
+            ctx.skip("download and run rustup-init.exe", "rustup is already installed");
             
             // Add the GNU target
-            let output = Command::new("rustup")
-                .args(&["target", "add", "x86_64-pc-windows-gnu"])
-                .output()?;
+            let had_target = installed_rust_parts(ctx, "target", None)?.contains(ctx.rust_target());
+            let output = ctx.rustup("rustup", &["target", "add", ctx.rust_target()])?;
 
-            if output.status.success() {
-                println!("✅ x86_64-pc-windows-gnu target added successfully!");
+            if output.success() {
+                if !had_target {
+                    ctx.record(Entry::TargetsAdded {
+                        toolchain: None,
+                        targets: vec![ctx.rust_target().to_string()],
+                    })?;
+                }
+                say!("✅ {} target added successfully!", ctx.rust_target());
             } else {
-                eprintln!("❌ Failed to add GNU target: {}", String::from_utf8_lossy(&output.stderr));
+                eprintln!("❌ Failed to add GNU target: {}", output.stderr);
+                return Err(InstallError::rustup(&["target", "add", ctx.rust_target()], &output.stderr));
             }
+
+            add_manifest_extras(ctx)?;
         }
         Err(_) => {
-            println!("rustup not found. Installing Rust automatically...");
-            install_rustup_automatically()?;
+            say!("rustup not found. Installing Rust automatically...");
+            ctx.because("rustup not found on PATH");
+            install_rustup_automatically(ctx)?;
         }
     }
 
-    // Set GNU as default target for current directory
-    let output = Command::new("rustup")
-        .args(&["override", "set", "stable-x86_64-pc-windows-gnu"])
-        .output();
+    // Select the GNU toolchain for the project or for the user
+    let toolchain = ctx.manifest.toolchain();
+    let project_dir = ctx.project_dir.to_string_lossy();
+    let (args, selected_for) = match ctx.scope {
+        Scope::Project => (
+            vec!["override", "set", &toolchain, "--path", &project_dir],
+            ctx.project_dir.display().to_string(),
+        ),
+        Scope::User => (vec!["default", &toolchain], "this user".to_string()),
+        Scope::None => {
+            say!("ℹ️  Not selecting {} (--scope none)", toolchain);
+            ctx.skip(&format!("select {}", toolchain), "--scope none");
+            say!();
+            return Ok(());
+        }
+    };
+    ctx.because(&format!("make the GNU toolchain the default for {}", selected_for));
+    let previous = previous_toolchain(ctx)?;
+    let output = ctx.rustup("rustup", &args);
 
     match output {
-        Ok(out) if out.status.success() => {
-            println!("✅ Set GNU toolchain as default for current directory");
+        Ok(out) if out.success() => {
+            if previous.as_deref() != Some(toolchain.as_str()) {
+                ctx.record(match ctx.scope {
+                    Scope::User => Entry::DefaultSet { previous },
+                    _ => Entry::OverrideSet {
+                        path: ctx.project_dir.clone(),
+                        previous,
+                    },
+                })?;
+            }
+            say!("✅ Set GNU toolchain as default for {}", selected_for);
         }
         _ => {
-            println!("ℹ️  You can manually set GNU toolchain with:");
-            println!("   rustup override set stable-x86_64-pc-windows-gnu");
+            say!("ℹ️  You can manually set GNU toolchain with:");
+            say!("   rustup {}", args.join(" "));
         }
     }
 
-    println!();
+    say!();
     Ok(())
 }
 
-fn configure_environment() -> Result<(), Box<dyn std::error::Error>> {
-    println!("⚙️  Configuring Environment");
-    println!("--------------------------");
-
-    // Create .cargo/config.toml for GNU toolchain
-    let cargo_dir = Path::new(".cargo");
-    if !cargo_dir.exists() {
-        fs::create_dir(cargo_dir)?;
-    }
-
-    let config_content = r#"[target.x86_64-pc-windows-gnu]
-linker = "x86_64-w64-mingw32-gcc"
-ar = "x86_64-w64-mingw32-ar"
-
-[build]
-target = "x86_64-pc-windows-gnu"
-
-[env]
-CC_x86_64_pc_windows_gnu = "x86_64-w64-mingw32-gcc"
-CXX_x86_64_pc_windows_gnu = "x86_64-w64-mingw32-g++"
-"#;
-
-    let config_path = cargo_dir.join("config.toml");
-    fs::write(&config_path, config_content)?;
-    println!("✅ Created .cargo/config.toml with GNU toolchain settings");
-
-    // Add MSYS2 to PATH suggestion
-    println!();
-    println!("📝 Environment Setup Recommendation:");
-    println!("Add the following to your PATH environment variable:");
-    println!("   C:\\msys64\\mingw64\\bin");
-    println!("   C:\\msys64\\usr\\bin");
-    println!();
-    println!("You can do this by:");
-    println!("1. Open System Properties → Advanced → Environment Variables");
-    println!("2. Edit the PATH variable");
-    println!("3. Add the paths above");
-    println!();
+/// Add the manifest's extra components and targets to an existing rustup.
+fn add_manifest_extras(ctx: &Context) -> Result<(), InstallError> {
+    let rust = &ctx.manifest.rust;
+    let toolchain = ctx.manifest.toolchain();
+    ctx.because("requested in the install manifest");
 
+    for (kind, names) in [("component", &rust.components), ("target", &rust.targets)] {
+        if names.is_empty() {
+            continue;
+        }
+        let installed = installed_rust_parts(ctx, kind, Some(&toolchain))?;
+        let mut args = vec![kind, "add", "--toolchain", &toolchain];
+        args.extend(names.iter().map(String::as_str));
+        let output = ctx.rustup("rustup", &args)?;
+        if !output.success() {
+            return Err(InstallError::rustup(&args, &output.stderr));
+        }
+        let added: Vec<String> = names.iter().filter(|name| !installed.contains(*name)).cloned().collect();
+        if !added.is_empty() {
+            ctx.record(match kind {
+                "component" => Entry::ComponentsAdded {
+                    toolchain: toolchain.clone(),
+                    components: added,
+                },
+                _ => Entry::TargetsAdded {
+                    toolchain: Some(toolchain.clone()),
+                    targets: added,
+                },
+            })?;
+        }
+        say!("✅ Added {}s: {}", kind, names.join(", "));
+    }
     Ok(())
 }
 
-fn verify_installation() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔍 Verifying Installation");
-    println!("-------------------------");
+fn configure_environment(ctx: &Context) -> Result<(), InstallError> {
+    say!("⚙️  Configuring Environment");
+    say!("--------------------------");
 
-    // Check rustc version and target
-    match Command::new("rustc").args(&["--version", "--verbose"]).output() {
-        Ok(output) => {
-            println!("Rust compiler info:");
-            println!("{}", String::from_utf8_lossy(&output.stdout));
+    // Create or update the cargo config for the chosen scope
+    let config_path = ctx.scope.config_path(&ctx.project_dir, &ctx.cargo_home);
+    if let (Some(config_path), true) = (&config_path, ctx.manifest.cargo.config) {
+        if let Some(cargo_dir) = config_path.parent().filter(|dir| !ctx.dry_run() && !dir.exists()) {
+            fs::create_dir_all(cargo_dir)?;
         }
-        Err(_) => println!("❌ Could not run rustc"),
-    }
 
-    // Check available targets
-    match Command::new("rustup").args(&["target", "list", "--installed"]).output() {
-        Ok(output) => {
-            let targets = String::from_utf8_lossy(&output.stdout);
-            println!("Installed targets:");
-            for line in targets.lines() {
-                if line.contains("windows-gnu") {
-                    println!("✅ {}", line);
-                } else {
-                    println!("   {}", line);
+        let generated = ctx.manifest.cargo_config(&ctx.msys2_root);
+        if config_path.exists() {
+            // Only the installer's own keys change; registries, aliases and
+            // comments already in the file survive
+            let existing = fs::read_to_string(config_path)?;
+            let merged = cargo_config::merge(&existing, &generated)
+                .map_err(|e| InstallError::Other(format!("Cannot update {}: {}", config_path.display(), e)))?;
+            if merged == existing {
+                say!("✅ {} already has the GNU toolchain settings", config_path.display());
+                ctx.skip(&format!("update {}", config_path.display()), "it already has the GNU toolchain settings");
+            } else {
+                ctx.because("keep a copy of the existing config before merging into it");
+                let backup = ctx.backup_file(config_path)?;
+                ctx.because("point cargo at the MinGW linker and default to the GNU target");
+                ctx.write_file(config_path, &merged)?;
+                ctx.record(Entry::FileWritten {
+                    path: config_path.clone(),
+                    backup: Some(backup.clone()),
+                })?;
+                say!("✅ Updated {} (original saved as {}):", config_path.display(), backup.display());
+                for line in cargo_config::diff(&existing, &merged) {
+                    say!("   {}", line);
                 }
             }
+        } else {
+            ctx.because("point cargo at the MinGW linker and default to the GNU target");
+            ctx.write_file(config_path, &generated)?;
+            ctx.record(Entry::FileWritten {
+                path: config_path.clone(),
+                backup: None,
+            })?;
+            say!("✅ Created {} with GNU toolchain settings", config_path.display());
         }
-        Err(_) => println!("❌ Could not list targets"),
+    } else if let Some(config_path) = &config_path {
+        say!("ℹ️  Not writing {} (cargo.config = false)", config_path.display());
+        ctx.skip(&format!("write {}", config_path.display()), "cargo.config = false in the manifest");
+    } else {
+        say!("ℹ️  Not writing any cargo config (--scope none)");
+        ctx.skip("write cargo config", "--scope none");
     }
 
-    // Test compilation with a simple program
-    println!("\n🧪 Testing compilation...");
-    let test_code = r#"fn main() {
-    println!("Hello from Rust with GNU toolchain!");
-    println!("Target: {}", std::env::consts::ARCH);
-    println!("OS: {}", std::env::consts::OS);
-    
-    #[cfg(target_env = "gnu")]
-    println!("✅ Successfully using GNU environment!");
-    
+    // Per-session activation scripts next to the cargo config
+    let scripts_dir = config_path.as_deref().and_then(Path::parent);
+    if let (Some(dir), true) = (scripts_dir, ctx.manifest.cargo.config) {
+        ctx.because("let shells opt into the toolchain per session");
+        let activation = Activation::new(&ctx.manifest, &ctx.msys2_root, &ctx.cargo_home);
+        for (name, contents) in activation.scripts() {
+            let path = dir.join(name);
+            let created = !path.exists();
+            ctx.write_file(&path, &contents)?;
+            if created {
+                ctx.record(Entry::FileWritten { path, backup: None })?;
+            }
+        }
+        say!("✅ Wrote activation scripts to {}", dir.display());
+        say!("   cmd:        call \"{}\"", dir.join("activate.bat").display());
+        say!("   PowerShell: . \"{}\"", dir.join("activate.ps1").display());
+        say!("   MSYS2 bash: add `{}` to ~/.bashrc", activate::bashrc_line(dir));
+        say!("   Each has a matching deactivate script.");
+    }
+
+    update_user_path(ctx)?;
+    Ok(())
+}
+
+/// Put the MSYS2 directories on the persistent user PATH, or say how to.
+fn update_user_path(ctx: &Context) -> Result<(), InstallError> {
+    let toolchain_dirs: Vec<PathBuf> = ctx
+        .manifest
+        .environments()
+        .into_iter()
+        .map(|env| env.bin_dir(&ctx.msys2_root))
+        .collect();
+    let usr_bin = ctx.msys2_root.join("usr").join("bin");
+
+    let current = if ctx.modify_path {
+        match ctx.user_env.user_path() {
+            Ok(current) => Some(current.unwrap_or_default()),
+            Err(e) => {
+                ctx.warn(&format!("Cannot read the user PATH: {}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    say!();
+    let Some(current) = current else {
+        say!("📝 Environment Setup Recommendation:");
+        say!("Add the following to your PATH environment variable, after {}:", ctx.cargo_home.join("bin").display());
+        for dir in toolchain_dirs.iter().chain([&usr_bin]) {
+            say!("   {}", dir.display());
+        }
+        say!();
+        say!("You can do this by:");
+        say!("1. Open System Properties → Advanced → Environment Variables");
+        say!("2. Edit the PATH variable");
+        say!("3. Add the paths above");
+        say!();
+        ctx.skip("update the user PATH", "--no-modify-path or the user PATH is unavailable");
+        return Ok(());
+    };
+
+    let env = |name: &str| std::env::var(name).ok();
+    let updated = user_env::updated_path(&current, &ctx.cargo_home.join("bin"), &toolchain_dirs, &usr_bin, &env);
+    match updated {
+        Some(path) => {
+            ctx.because("make the MinGW tools available in new terminals");
+            ctx.set_user_path(&path)?;
+            let added: Vec<String> = toolchain_dirs
+                .iter()
+                .chain([&usr_bin])
+                .filter(|dir| !user_env::contains(&current, dir, &env))
+                .map(|dir| dir.display().to_string())
+                .collect();
+            ctx.record(Entry::UserPathChanged {
+                added,
+                previous: Some(current.clone()),
+            })?;
+            say!("✅ Updated the user PATH:");
+            for dir in toolchain_dirs.iter().chain([&usr_bin]) {
+                say!("   {}", dir.display());
+            }
+            say!("   Open a new terminal to pick it up.");
+        }
+        None => {
+            say!("✅ The MSYS2 directories are already on the user PATH");
+            ctx.skip("update the user PATH", "the MSYS2 directories are already on it in the right order");
+        }
+    }
+    say!();
+    Ok(())
+}
+
+/// Sample program `verify_installation` compiles with the new toolchain.
+const TEST_PROGRAM: &str = r#"fn main() {
+    println!("Hello from Rust with GNU toolchain!");
+    println!("Target: {}", std::env::consts::ARCH);
+    println!("OS: {}", std::env::consts::OS);
+    
+    #[cfg(target_env = "gnu")]
+    println!("✅ Successfully using GNU environment!");
+    
     #[cfg(not(target_env = "gnu"))]
     println!("⚠️  Not using GNU environment");
 }"#;
 
-    fs::write("test_gnu.rs", test_code)?;
+fn verify_installation(ctx: &Context) -> Result<(), InstallError> {
+    say!("🔍 Verifying Installation");
+    say!("-------------------------");
+
+    // Check rustc version and target
+    match ctx.runner.run("rustc", &["--version", "--verbose"]) {
+        Ok(output) => {
+            say!("Rust compiler info:");
+            say!("{}", output.stdout);
+            ctx.detected("rustc", &output.stdout);
+        }
+        Err(_) => say!("❌ Could not run rustc"),
+    }
+
+    // Check available targets
+    match ctx.runner.run("rustup", &["target", "list", "--installed"]) {
+        Ok(output) => {
+            say!("Installed targets:");
+            for line in output.stdout.lines() {
+                if line.contains("windows-gnu") {
+                    say!("✅ {}", line);
+                } else {
+                    say!("   {}", line);
+                }
+            }
+        }
+        Err(_) => say!("❌ Could not list targets"),
+    }
+
+    // Test compilation with a simple program
+    say!("\n🧪 Testing compilation...");
+    let source_path = ctx.work_dir.join("test_gnu.rs");
+    let exe_path = ctx.work_dir.join("test_gnu.exe");
+    ctx.because("smoke-test the GNU toolchain with a sample program");
+    ctx.write_file(&source_path, TEST_PROGRAM)?;
 
-    let compile_output = Command::new("rustc")
-        .args(&["test_gnu.rs", "--target", "x86_64-pc-windows-gnu"])
-        .output()?;
+    let compile_output = ctx.runner.run(
+        "rustc",
+        &[
+            &source_path.to_string_lossy(),
+            "--target",
+            ctx.rust_target(),
+            "-o",
+            &exe_path.to_string_lossy(),
+        ],
+    )?;
 
-    if compile_output.status.success() {
-        println!("✅ Test compilation successful!");
+    if compile_output.success() {
+        say!("✅ Test compilation successful!");
         
         // Try to run the compiled program
-        match Command::new("./test_gnu.exe").output() {
+        match ctx.runner.run(&exe_path.to_string_lossy(), &[]) {
             Ok(run_output) => {
-                println!("✅ Test program executed successfully:");
-                let output_str = String::from_utf8_lossy(&run_output.stdout);
+                say!("✅ Test program executed successfully:");
+                let output_str = run_output.stdout;
                 for line in output_str.lines() {
-                    println!("   {}", line);
+                    say!("   {}", line);
                 }
                 
                 // Check if GNU environment was detected
                 if output_str.contains("Successfully using GNU environment") {
-                    println!("🎉 GNU toolchain is working correctly!");
+                    say!("🎉 GNU toolchain is working correctly!");
                 } else {
-                    println!("⚠️  GNU environment may not be active");
+                    ctx.warn("GNU environment may not be active");
                 }
             }
-            Err(_) => println!("⚠️  Compiled successfully but couldn't run (may need MSYS2 DLLs in PATH)"),
+            Err(_) => ctx.warn("Compiled successfully but couldn't run (may need MSYS2 DLLs in PATH)"),
         }
 
         // Clean up
-        let _ = fs::remove_file("test_gnu.rs");
-        let _ = fs::remove_file("test_gnu.exe");
+        let _ = ctx.remove_file(&source_path);
+        let _ = ctx.remove_file(&exe_path);
     } else {
-        println!("❌ Test compilation failed:");
-        println!("{}", String::from_utf8_lossy(&compile_output.stderr));
+        say!("❌ Test compilation failed:");
+        say!("{}", compile_output.stderr);
+        return Err(InstallError::Verification {
+            reason: format!(
+                "the test program does not compile for {}: {}",
+                ctx.rust_target(),
+                compile_output.stderr.lines().find(|line| line.starts_with("error")).unwrap_or("Unknown error")
+            ),
+        });
+    }
+
+    say!();
+    Ok(())
+}
+
+/// `update`: upgrade the MSYS2 packages and the Rust toolchain in place.
+fn update(ctx: &Context) -> Result<(), InstallError> {
+    say!("🔄 Updating");
+    say!("-----------");
+
+    if !ctx.dry_run() && !ctx.msys2_bash().exists() {
+        return Err(InstallError::Msys2 {
+            reason: format!("MSYS2 not found at {}; run install first", ctx.msys2_root.display()),
+        });
+    }
+    ctx.because("update upgrades what is already installed");
+
+    say!("Upgrading MSYS2 packages...");
+    upgrade_system(ctx)?;
+    say!("✅ MSYS2 packages are up to date");
+
+    let toolchain = ctx.manifest.toolchain();
+    say!("Updating {}...", toolchain);
+    let output = ctx.rustup("rustup", &["update", &toolchain])?;
+    if !output.success() {
+        return Err(InstallError::rustup(&["update", &toolchain], &output.stderr));
+    }
+    say!("✅ {} is up to date", toolchain);
+    say!();
+    Ok(())
+}
+
+/// `status`: what is installed and configured, changing nothing.
+fn status(ctx: &Context) -> Result<(), InstallError> {
+    say!("📊 Installation Status");
+    say!("----------------------");
+    let mark = |ok: bool| if ok { "✅" } else { "❌" };
+
+    say!(
+        "{} MSYS2: {} ({})",
+        mark(ctx.msys2_bash().exists()),
+        ctx.msys2_root.display(),
+        ctx.msys2_root_source
+    );
+    say!("   Environment: {} for {}", ctx.manifest.msys2.env, ctx.rust_target());
+
+    match ctx.runner.run("rustc", &["--version"]) {
+        Ok(output) if output.success() => {
+            say!("✅ rustc: {}", output.stdout.trim());
+            ctx.detected("rustc", &output.stdout);
+        }
+        _ => say!("❌ rustc: not found"),
+    }
+    match ctx.runner.run("rustup", &["show", "active-toolchain"]) {
+        Ok(output) if output.success() => {
+            say!("   Active toolchain: {}", output.stdout.lines().next().unwrap_or("").trim());
+        }
+        _ => say!("❌ rustup: not found"),
+    }
+    let targets = installed_rust_parts(ctx, "target", None).unwrap_or_default();
+    say!("{} Target {}", mark(targets.contains(ctx.rust_target())), ctx.rust_target());
+
+    match ctx.scope.config_path(&ctx.project_dir, &ctx.cargo_home) {
+        Some(path) => say!("{} Cargo config ({} scope): {}", mark(path.exists()), ctx.scope, path.display()),
+        None => say!("   Cargo config: not written (scope none)"),
+    }
+
+    let completed = ctx.progress.completed(&ctx.fingerprint()).map_err(InstallError::Other)?;
+    if !completed.is_empty() {
+        let remaining: Vec<&str> = Selection::Resume.steps(&completed).into_iter().map(Step::name).collect();
+        say!("⏸️  Interrupted installation; install resumes with: {}", remaining.join(", "));
     }
 
-    println!();
+    let entries = ctx.journal.entries().map_err(InstallError::Other)?;
+    say!("   Install journal: {} change(s) recorded in {}", entries.len(), ctx.journal.path.display());
+    for entry in &entries {
+        say!("   - {}", entry);
+    }
+    say!();
+    Ok(())
+}
+
+/// Undo what earlier installations recorded in the journal, newest first.
+/// Pre-existing installs are left alone; changes that fail to undo stay in
+/// the journal for another attempt.
+fn uninstall(ctx: &Context) -> Result<(), InstallError> {
+    say!("🗑️  Uninstalling");
+    say!("---------------");
+
+    let entries = ctx.journal.entries().map_err(InstallError::Other)?;
+    if entries.is_empty() {
+        say!("Nothing to undo: no changes are recorded in {}", ctx.journal.path.display());
+        return Ok(());
+    }
+    for entry in &entries {
+        say!("   - {}", entry);
+    }
+    let question = format!("Undo these {} change(s)?", entries.len());
+    if !ctx.confirm(&question)? {
+        return Err(InstallError::Aborted { question });
+    }
+
+    // Removing rustup or MSYS2 takes everything added to them along
+    let rustup_removed = entries.contains(&Entry::RustupInstalled);
+    let msys2_removed: Vec<&PathBuf> = entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Msys2Installed { root } => Some(root),
+            _ => None,
+        })
+        .collect();
+
+    let mut failed = Vec::new();
+    for entry in entries.iter().rev() {
+        let covered_by = match entry {
+            Entry::TargetsAdded { .. }
+            | Entry::ComponentsAdded { .. }
+            | Entry::OverrideSet { .. }
+            | Entry::DefaultSet { .. }
+                if rustup_removed =>
+            {
+                Some("rustup")
+            }
+            Entry::PackagesInstalled { root, .. } if msys2_removed.contains(&root) => Some("MSYS2"),
+            _ => None,
+        };
+        if let Some(what) = covered_by {
+            ctx.skip(&format!("undo: {}", entry), &format!("removed along with {}", what));
+            continue;
+        }
+        if let Err(e) = undo(ctx, entry) {
+            say!("❌ {}", e);
+            failed.push(entry.clone());
+        }
+    }
+
+    if !ctx.dry_run() {
+        failed.reverse();
+        ctx.journal.replace(&failed)?;
+    }
+    if !failed.is_empty() {
+        return Err(InstallError::Other(format!(
+            "{} change(s) could not be undone; they are kept in {} for the next uninstall",
+            failed.len(),
+            ctx.journal.path.display()
+        )));
+    }
+    say!();
+    Ok(())
+}
+
+/// Reverse a single journal entry.
+fn undo(ctx: &Context, entry: &Entry) -> Result<(), InstallError> {
+    let rustup = |args: &[&str]| -> Result<(), InstallError> {
+        let output = ctx.rustup("rustup", args)?;
+        if !output.success() {
+            return Err(InstallError::rustup(args, &output.stderr));
+        }
+        Ok(())
+    };
+
+    match entry {
+        Entry::Msys2Found { root } => {
+            say!("ℹ️  Leaving the pre-existing MSYS2 at {} in place", root.display());
+            ctx.skip(&format!("remove MSYS2 at {}", root.display()), "it was installed before");
+        }
+        Entry::Msys2Installed { root } => {
+            say!("Removing MSYS2 from {}...", root.display());
+            ctx.because("MSYS2 was installed by this tool");
+            let uninstaller = root.join("uninstall.exe");
+            let output = ctx.runner.run(&uninstaller.to_string_lossy(), &["pr", "--confirm-command"])?;
+            if !output.success() {
+                return Err(InstallError::Msys2 {
+                    reason: format!("Removing MSYS2 failed: {}", output.stderr.lines().next().unwrap_or("Unknown error")),
+                });
+            }
+        }
+        Entry::PackagesInstalled { root, packages } => {
+            say!("Removing {} pacman package(s) from {}...", packages.len(), root.display());
+            ctx.because("these packages were installed by this tool");
+            let cmd = format!("pacman -R --noconfirm {}", packages.join(" "));
+            let output = ctx.runner.run(&msys2::bash_path(root).to_string_lossy(), &["-l", "-c", &cmd])?;
+            if let Some(failure) = Transaction::parse(&output).failure {
+                return Err(InstallError::pacman(None, &failure));
+            }
+        }
+        Entry::RustupInstalled => {
+            say!("Removing rustup and its toolchains...");
+            ctx.because("rustup was installed by this tool");
+            rustup(&["self", "uninstall", "-y"])?;
+        }
+        Entry::TargetsAdded { toolchain, targets } => {
+            say!("Removing targets: {}", targets.join(", "));
+            ctx.because("these targets were added by this tool");
+            let mut args = vec!["target", "remove"];
+            if let Some(toolchain) = toolchain {
+                args.extend(["--toolchain", toolchain]);
+            }
+            args.extend(targets.iter().map(String::as_str));
+            rustup(&args)?;
+        }
+        Entry::ComponentsAdded { toolchain, components } => {
+            say!("Removing components: {}", components.join(", "));
+            ctx.because("these components were added by this tool");
+            let mut args = vec!["component", "remove", "--toolchain", toolchain];
+            args.extend(components.iter().map(String::as_str));
+            rustup(&args)?;
+        }
+        Entry::OverrideSet { path, previous } => {
+            let path_arg = path.to_string_lossy();
+            ctx.because("the toolchain override was set by this tool");
+            match previous {
+                Some(previous) => rustup(&["override", "set", previous, "--path", &path_arg])?,
+                None => rustup(&["override", "unset", "--path", &path_arg])?,
+            }
+            say!("✅ Restored the toolchain override for {}", path.display());
+        }
+        Entry::DefaultSet { previous } => {
+            ctx.because("the default toolchain was set by this tool");
+            rustup(&["default", previous.as_deref().unwrap_or("none")])?;
+            say!("✅ Restored the default toolchain");
+        }
+        Entry::FileWritten { path, backup } => match backup {
+            Some(backup) => {
+                ctx.because("the file was changed by this tool; the backup holds the original");
+                let original = fs::read_to_string(backup)?;
+                ctx.write_file(path, &original)?;
+                ctx.remove_file(backup)?;
+                say!("✅ Restored {} from {}", path.display(), backup.display());
+            }
+            None if path.exists() => {
+                ctx.because("the file was created by this tool");
+                ctx.remove_file(path)?;
+                say!("✅ Removed {}", path.display());
+            }
+            None => {}
+        },
+        Entry::UserPathChanged { added, previous } => {
+            let current = ctx.user_env.user_path()?.unwrap_or_default();
+            let env = |name: &str| std::env::var(name).ok();
+            let restored = match previous {
+                Some(previous) => user_env::restored(&current, previous, added, &env),
+                None => user_env::without(&current, added, &env),
+            };
+            if restored != current {
+                ctx.because("the user PATH was changed by this tool");
+                ctx.set_user_path(&restored)?;
+                say!("✅ Restored the user PATH");
+            }
+        }
+    }
     Ok(())
 }
 
@@ -606,6 +1893,49 @@ fn verify_installation() -> Result<(), Box<dyn std::error::Error>> {
 mod tests {
     use super::*;
 
+    use runner::FakeRunner;
+    use user_env::FakeEnvironment;
+    use testutil::{Faults, TestServer, fake_bundle, fake_msys2_root, scratch_dir, silenced};
+
+    /// The single `pacman -S --needed` transaction `runner` saw, if any.
+    fn pacman_install(runner: &FakeRunner) -> Option<String> {
+        let installs: Vec<String> = runner.calls().into_iter().filter(|call| call.contains("pacman -S --needed")).collect();
+        assert!(installs.len() <= 1, "{:?}", installs);
+        installs.into_iter().next()
+    }
+
+    fn sha256_of(bytes: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        verify::hex(&Sha256::digest(bytes))
+    }
+
+    /// Context for a real (non dry-run) installation against `runner`.
+    fn test_context<'a>(runner: &'a dyn CommandRunner, msys2_root: PathBuf, work_dir: &Path) -> Context<'a> {
+        Context {
+            runner,
+            msys2_root,
+            msys2_root_source: RootSource::Flag,
+            downloader: Downloader::new(None).with_retries(0, Duration::ZERO),
+            sources: Sources::official(msys2::Environment::default()),
+            verify: VerifyPolicy::default(),
+            manifest: Manifest::default(),
+            offline: None,
+            work_dir: work_dir.to_path_buf(),
+            scope: Scope::Project,
+            project_dir: work_dir.to_path_buf(),
+            cargo_home: work_dir.join("cargo-home"),
+            user_env: &RegistryEnvironment,
+            modify_path: false,
+            journal: Journal::at(work_dir.join("journal.toml")),
+            progress: Progress::at(work_dir.join("steps.toml")),
+            steps: Selection::Resume,
+            report: Report::collecting(),
+            assume_yes: true,
+            interactive: false,
+            plan: None,
+        }
+    }
+
     #[test]
     fn test_windows_check() {
         // This test will only pass on Windows
@@ -623,5 +1953,853 @@ mod tests {
         assert!(current_dir.exists());
     }
 
-}
+    #[test]
+    fn test_full_flow_with_existing_msys2() {
+        let dir = scratch_dir("full-flow");
+        let runner = FakeRunner::new()
+            .on("rustc --version --verbose", CommandOutput::ok("rustc 1.80.0\nhost: x86_64-pc-windows-gnu\n"))
+            .on("rustc --version", CommandOutput::ok("rustc 1.80.0 (x86_64-pc-windows-msvc)"))
+            .on("rustup --version", CommandOutput::ok("rustup 1.27.1"))
+            .on("target list --installed", CommandOutput::ok("x86_64-pc-windows-gnu\nx86_64-pc-windows-msvc\n"))
+            .on("test_gnu.exe", CommandOutput::ok("✅ Successfully using GNU environment!\n"));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+
+        run_installation_process(&ctx).unwrap();
+
+        let calls = runner.calls();
+        let position = |pattern: &str| calls.iter().position(|c| c.contains(pattern)).unwrap();
+        assert!(position("pacman -S --needed --noconfirm mingw-w64-x86_64-toolchain") < position("rustup target add x86_64-pc-windows-gnu"));
+        assert!(position("rustup override set stable-x86_64-pc-windows-gnu") < position("test_gnu.rs --target x86_64-pc-windows-gnu"));
+        // MSYS2 was already present, so nothing was downloaded or initialized
+        assert!(!runner.ran("msys2-installer.exe"));
+        assert!(!runner.ran("pacman-key"));
 
+        let config = fs::read_to_string(dir.join(".cargo").join("config.toml")).unwrap();
+        assert!(config.contains("target = \"x86_64-pc-windows-gnu\""));
+        assert!(!dir.join("test_gnu.rs").exists());
+
+        // A started and a finished event for every step
+        let events = ctx.report.events();
+        assert_eq!(events.len(), 2 * Step::ALL.len());
+        match &events[1] {
+            report::Event::StepFinished {
+                step: Step::Check,
+                status: report::StepStatus::Completed,
+                versions,
+                warnings,
+                ..
+            } => {
+                assert_eq!(versions["rustc"], "1.80.0");
+                assert_eq!(warnings, &["Current installation uses MSVC toolchain."]);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rerun_resumes_at_failed_step() {
+        let dir = scratch_dir("resume");
+        let manifest = Manifest::parse("[rust]\ncomponents = [\"rust-src\"]\n").unwrap();
+        let probes = || {
+            FakeRunner::new()
+                .on("rustc --version", CommandOutput::ok("rustc 1.80.0 (x86_64-pc-windows-gnu)"))
+                .on("rustup --version", CommandOutput::ok("rustup 1.27.1"))
+                .on("test_gnu.exe", CommandOutput::ok("✅ Successfully using GNU environment!\n"))
+        };
+
+        let failing = probes().on("rustup component add", CommandOutput::failed("error: network failure"));
+        let mut ctx = test_context(&failing, fake_msys2_root(&dir), &dir);
+        ctx.manifest = manifest.clone();
+        assert!(run_installation_process(&ctx).is_err());
+        assert_eq!(
+            ctx.progress.completed(&ctx.fingerprint()).unwrap(),
+            [Step::Check, Step::Msys2, Step::Toolchain]
+        );
+
+        // The rerun starts at the rust step and finishes
+        let runner = probes();
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest = manifest.clone();
+        run_installation_process(&ctx).unwrap();
+        assert!(!runner.ran("pacman -S"));
+        assert!(runner.ran("rustup component add --toolchain stable-x86_64-pc-windows-gnu rust-src"));
+        assert!(runner.ran("test_gnu.rs"));
+        assert!(!ctx.progress.path.exists());
+
+        // --only-step runs just that step
+        let runner = probes();
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest = manifest;
+        ctx.steps = Selection::Only(Step::Toolchain);
+        run_installation_process(&ctx).unwrap();
+        assert!(pacman_install(&runner).unwrap().contains("mingw-w64-x86_64-toolchain"));
+        assert!(!runner.ran("rustup"));
+        assert_eq!(ctx.progress.completed(&ctx.fingerprint()).unwrap(), [Step::Toolchain]);
+    }
+
+    #[test]
+    fn test_dry_run_plans_fresh_install_without_side_effects() {
+        let dir = scratch_dir("dry-run");
+        let system = FakeRunner::new()
+            .on("rustc --version", CommandOutput::ok("rustc 1.80.0 (x86_64-pc-windows-msvc)"))
+            .on("rustup --version", CommandOutput::ok("rustup 1.27.1"));
+        let plan = Plan::new();
+        let runner = DryRunRunner {
+            inner: &system,
+            plan: &plan,
+        };
+        let user_env = FakeEnvironment::with_path("C:\\Tools");
+        let ctx = Context {
+            runner: &runner,
+            msys2_root: dir.join("msys64"),
+            msys2_root_source: RootSource::Default,
+            downloader: Downloader::new(None),
+            sources: Sources::official(msys2::Environment::default()),
+            verify: VerifyPolicy::default(),
+            manifest: Manifest::default(),
+            offline: None,
+            work_dir: dir.clone(),
+            scope: Scope::Project,
+            project_dir: dir.clone(),
+            cargo_home: dir.join("cargo-home"),
+            user_env: &user_env,
+            modify_path: true,
+            journal: Journal::at(dir.join("journal.toml")),
+            progress: Progress::at(dir.join("steps.toml")),
+            steps: Selection::Resume,
+            report: Report::new(report::Format::Text),
+            assume_yes: false,
+            interactive: false,
+            plan: Some(&plan),
+        };
+
+        silenced(|| run_installation_process(&ctx)).unwrap();
+
+        // Only read-only probes reached the system
+        assert!(system.calls().iter().all(|call| call.contains("--version") || call.contains("target list")));
+
+        let actions = plan.actions();
+        let position = |pattern: &str| actions.iter().position(|a| a.what.contains(pattern)).unwrap();
+        assert!(position("msys2-x86_64-latest.exe") < position("pacman-key --init"));
+        assert!(position("pacman-key --init") < position("mingw-w64-x86_64-toolchain"));
+        assert!(position("mingw-w64-x86_64-toolchain") < position("rustup target add"));
+        assert!(position("rustup target add") < position("config.toml"));
+        assert!(position("config.toml") < position("set user PATH to C:\\Tools;"));
+        assert!(actions[position("download ")].why.contains("MSYS2 not found"));
+        let root_arg = format!("--root {}", dir.join("msys64").display());
+        assert!(position("verify SHA-256 of") < position(&root_arg));
+
+        let skipped: Vec<_> = actions.iter().filter(|a| a.skipped).collect();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].why, "rustup is already installed");
+
+        assert!(!dir.join(".cargo").exists());
+        assert!(!dir.join("test_gnu.rs").exists());
+        assert_eq!(user_env.path.borrow().as_deref(), Some("C:\\Tools"));
+    }
+
+    #[test]
+    fn test_dry_run_removes_no_files() {
+        let dir = scratch_dir("dry-run-files");
+        let system = FakeRunner::new().missing("rustup --version");
+        let plan = Plan::new();
+        let runner = DryRunRunner {
+            inner: &system,
+            plan: &plan,
+        };
+        let mut ctx = test_context(&runner, dir.join("msys64"), &dir);
+        ctx.plan = Some(&plan);
+        let existing = ["msys2-installer.exe", "rustup-init.exe", "test_gnu.rs", "test_gnu.exe"];
+        for name in existing {
+            fs::write(dir.join(name), "already here").unwrap();
+        }
+
+        silenced(|| run_installation_process(&ctx)).unwrap();
+
+        for name in existing {
+            assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), "already here", "{}", name);
+            let removal = format!("remove {}", dir.join(name).display());
+            assert!(plan.actions().iter().any(|a| a.what == removal), "{}", removal);
+        }
+    }
+
+    #[test]
+    fn test_initialize_msys2_tolerates_nothing_to_do() {
+        let dir = scratch_dir("init-nothing-to-do");
+        let runner = FakeRunner::new().on(
+            "pacman -Syu",
+            CommandOutput::failed(" there is nothing to do"),
+        );
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+
+        initialize_msys2(&ctx).unwrap();
+        assert!(runner.ran("pacman-key --populate msys2"));
+        assert!(runner.ran("gcc --version"));
+    }
+
+    #[test]
+    fn test_initialize_msys2_stops_on_error() {
+        let dir = scratch_dir("init-error");
+        let runner = FakeRunner::new().on(
+            "pacman -Sy --noconfirm",
+            CommandOutput::failed("error: failed to synchronize all databases"),
+        );
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+
+        let err = initialize_msys2(&ctx).unwrap_err();
+        assert!(err.to_string().contains("Updating package database"));
+        assert!(!runner.ran("pacman -Syu"));
+    }
+
+    #[test]
+    fn test_install_rust_gnu_without_rustup_installs_from_mirror() {
+        let dir = scratch_dir("no-rustup");
+        let digest = sha256_of(b"rustup-init");
+        let server = TestServer::start(
+            &[
+                ("/rustup-init.exe", b"rustup-init"),
+                ("/rustup-init.exe.sha256", format!("{}  rustup-init.exe\n", digest).as_bytes()),
+            ],
+            Faults::default(),
+        );
+        let runner = FakeRunner::new().missing("rustup --version");
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.sources = Sources::from_base(&server.base_url);
+
+        install_rust_gnu(&ctx).unwrap();
+
+        assert_eq!(
+            server.requests(),
+            vec!["GET /rustup-init.exe", "GET /rustup-init.exe.sha256"]
+        );
+        assert!(runner.ran("rustup-init.exe --default-host x86_64-pc-windows-gnu --default-toolchain stable --profile default -y"));
+        assert!(!runner.ran("rustup target add"));
+        assert!(!dir.join("rustup-init.exe").exists());
+    }
+
+    #[test]
+    fn test_download_and_install_msys2_uses_resolved_root() {
+        let dir = scratch_dir("msys2-install");
+        let server = TestServer::start(&[("/msys2-x86_64-latest.exe", b"installer")], Faults::default());
+        let runner = FakeRunner::new();
+        let root = dir.join("custom-msys2");
+        // The fake installer does nothing, so pretend it created the root
+        fs::create_dir_all(&root).unwrap();
+        let mut ctx = test_context(&runner, root.clone(), &dir);
+        ctx.sources = Sources::from_base(&server.base_url);
+        ctx.verify.msys2_sha256 = Some(sha256_of(b"installer"));
+
+        download_and_install_msys2(&ctx).unwrap();
+
+        let install = format!("install --confirm-command --accept-messages --root {}", root.display());
+        assert!(runner.ran(&install));
+        assert!(!runner.ran("RunAs"));
+        assert!(!dir.join("msys2-installer.exe").exists());
+    }
+
+    #[test]
+    fn test_manifest_drives_packages_toolchain_and_config() {
+        let dir = scratch_dir("manifest-flow");
+        let runner = FakeRunner::new().on("rustup --version", CommandOutput::ok("rustup 1.27.1"));
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest = Manifest::parse(
+            r#"
+[msys2]
+packages = ["mingw-w64-x86_64-gdb"]
+
+[rust]
+channel = "1.81.0"
+components = ["rust-src", "llvm-tools"]
+targets = ["i686-pc-windows-gnu"]
+
+[cargo]
+config = false
+"#,
+        )
+        .unwrap();
+
+        install_gnu_toolchain(&ctx).unwrap();
+        install_rust_gnu(&ctx).unwrap();
+        configure_environment(&ctx).unwrap();
+
+        assert!(pacman_install(&runner).unwrap().ends_with(" mingw-w64-x86_64-gdb"));
+        assert!(runner.ran("rustup component add --toolchain 1.81.0-x86_64-pc-windows-gnu rust-src llvm-tools"));
+        assert!(runner.ran("rustup target add --toolchain 1.81.0-x86_64-pc-windows-gnu i686-pc-windows-gnu"));
+        assert!(runner.ran("rustup override set 1.81.0-x86_64-pc-windows-gnu"));
+        assert!(!dir.join(".cargo").exists());
+    }
+
+    #[test]
+    fn test_ucrt64_environment() {
+        let dir = scratch_dir("ucrt64");
+        let runner = FakeRunner::new().on("rustup --version", CommandOutput::ok("rustup 1.27.1"));
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest.msys2.env = msys2::Environment::Ucrt64;
+
+        install_gnu_toolchain(&ctx).unwrap();
+        install_rust_gnu(&ctx).unwrap();
+
+        let install = pacman_install(&runner).unwrap();
+        assert!(install.contains("mingw-w64-ucrt-x86_64-toolchain mingw-w64-ucrt-x86_64-cmake"));
+        assert!(install.contains("mingw-w64-ucrt-x86_64-openssl"));
+        assert!(!install.contains("mingw-w64-x86_64-"));
+        assert!(runner.ran("rustup override set stable-x86_64-pc-windows-gnu"));
+    }
+
+    #[test]
+    fn test_existing_cargo_config_is_merged_and_backed_up() {
+        let dir = scratch_dir("config-merge");
+        let runner = FakeRunner::new();
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        let config_path = dir.join(".cargo").join("config.toml");
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        let original = "[alias]\nb = \"build\" # keep me\n\n[registries.internal]\nindex = \"sparse+https://example.com/\"\n";
+        fs::write(&config_path, original).unwrap();
+
+        configure_environment(&ctx).unwrap();
+
+        let merged = fs::read_to_string(&config_path).unwrap();
+        assert!(merged.starts_with(original), "{}", merged);
+        assert!(merged.contains("[target.x86_64-pc-windows-gnu]\nlinker = \"x86_64-w64-mingw32-gcc\""));
+        assert_eq!(fs::read_to_string(dir.join(".cargo").join("config.toml.bak")).unwrap(), original);
+
+        // A second run has nothing left to change and makes no new backup
+        configure_environment(&ctx).unwrap();
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), merged);
+        assert!(!dir.join(".cargo").join("config.toml.bak.1").exists());
+    }
+
+    #[test]
+    fn test_user_scope_writes_cargo_home_and_sets_default() {
+        let dir = scratch_dir("scope-user");
+        let runner = FakeRunner::new().on("rustup --version", CommandOutput::ok("rustup 1.27.1"));
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.scope = Scope::User;
+
+        install_rust_gnu(&ctx).unwrap();
+        configure_environment(&ctx).unwrap();
+
+        assert!(runner.ran("rustup default stable-x86_64-pc-windows-gnu"));
+        assert!(!runner.ran("rustup override"));
+        assert!(dir.join("cargo-home").join("config.toml").exists());
+        assert!(!dir.join(".cargo").exists());
+    }
+
+    #[test]
+    fn test_no_scope_selects_and_writes_nothing() {
+        let dir = scratch_dir("scope-none");
+        let runner = FakeRunner::new().on("rustup --version", CommandOutput::ok("rustup 1.27.1"));
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.scope = Scope::None;
+
+        install_rust_gnu(&ctx).unwrap();
+        configure_environment(&ctx).unwrap();
+
+        assert!(runner.ran("rustup target add x86_64-pc-windows-gnu"));
+        assert!(!runner.ran("rustup override") && !runner.ran("rustup default"));
+        assert!(!dir.join(".cargo").exists() && !dir.join("cargo-home").exists());
+    }
+
+    #[test]
+    fn test_user_path_is_updated_once() {
+        let dir = scratch_dir("user-path");
+        let runner = FakeRunner::new();
+        let cargo_bin = dir.join("cargo-home").join("bin");
+        let user_env = FakeEnvironment::with_path(&format!("{};C:\\Tools", cargo_bin.display()));
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.user_env = &user_env;
+        ctx.modify_path = true;
+
+        configure_environment(&ctx).unwrap();
+
+        let path = user_env.path.borrow().clone().unwrap();
+        let entries: Vec<&str> = path.split(';').collect();
+        let mingw_bin = ctx.msys2_root.join("mingw64").join("bin");
+        let usr_bin = ctx.msys2_root.join("usr").join("bin");
+        assert_eq!(
+            entries,
+            [
+                cargo_bin.to_str().unwrap(),
+                mingw_bin.to_str().unwrap(),
+                "C:\\Tools",
+                usr_bin.to_str().unwrap()
+            ]
+        );
+
+        configure_environment(&ctx).unwrap();
+        assert_eq!(user_env.path.borrow().as_deref(), Some(path.as_str()));
+    }
+
+    #[test]
+    fn test_uninstall_reverses_only_journaled_changes() {
+        let dir = scratch_dir("uninstall");
+        let runner = FakeRunner::new()
+            .on("rustup --version", CommandOutput::ok("rustup 1.27.1"))
+            .on("rustup target list --installed", CommandOutput::ok("x86_64-pc-windows-msvc\n"));
+        let msys2_root = fake_msys2_root(&dir);
+        // usr\bin is already on the PATH, but first; the install moves it last
+        let original_path = format!("{};C:\\Tools", msys2_root.join("usr").join("bin").display());
+        let user_env = FakeEnvironment::with_path(&original_path);
+        let mut ctx = test_context(&runner, msys2_root, &dir);
+        ctx.user_env = &user_env;
+        ctx.modify_path = true;
+        let config_path = dir.join(".cargo").join("config.toml");
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(&config_path, "[alias]\nb = \"build\"\n").unwrap();
+
+        guide_msys2_installation(&ctx).unwrap();
+        install_rust_gnu(&ctx).unwrap();
+        configure_environment(&ctx).unwrap();
+        // A rerun finds the same MSYS2 without journaling it again
+        guide_msys2_installation(&ctx).unwrap();
+
+        let entries = ctx.journal.entries().unwrap();
+        assert_eq!(entries[0], Entry::Msys2Found { root: ctx.msys2_root.clone() });
+        assert_eq!(entries.iter().filter(|entry| matches!(entry, Entry::Msys2Found { .. })).count(), 1);
+        for expected in [
+            Entry::TargetsAdded {
+                toolchain: None,
+                targets: vec!["x86_64-pc-windows-gnu".to_string()],
+            },
+            Entry::OverrideSet {
+                path: dir.clone(),
+                previous: None,
+            },
+            Entry::FileWritten {
+                path: config_path.clone(),
+                backup: Some(dir.join(".cargo").join("config.toml.bak")),
+            },
+        ] {
+            assert!(entries.contains(&expected), "{:?}", entries);
+        }
+
+        uninstall(&ctx).unwrap();
+
+        assert!(runner.ran("rustup target remove x86_64-pc-windows-gnu"));
+        assert!(runner.ran(&format!("rustup override unset --path {}", dir.display())));
+        assert!(!runner.ran("uninstall.exe") && !runner.ran("pacman -R"));
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), "[alias]\nb = \"build\"\n");
+        assert!(!dir.join(".cargo").join("config.toml.bak").exists());
+        assert!(!dir.join(".cargo").join("activate.bat").exists());
+        assert_eq!(user_env.path.borrow().as_deref(), Some(original_path.as_str()));
+        assert!(!ctx.journal.path.exists());
+    }
+
+    #[test]
+    fn test_non_interactive_uninstall_needs_yes() {
+        let dir = scratch_dir("uninstall-prompt");
+        let runner = FakeRunner::new();
+        let mut ctx = test_context(&runner, dir.join("msys64"), &dir);
+        ctx.assume_yes = false;
+        ctx.journal.record(&Entry::RustupInstalled).unwrap();
+
+        let err = uninstall(&ctx).unwrap_err();
+
+        assert_eq!(err.exit_code(), 3);
+        assert!(err.to_string().contains("Pass --yes"), "{}", err);
+        assert!(runner.calls().is_empty());
+        assert_eq!(ctx.journal.entries().unwrap(), [Entry::RustupInstalled]);
+    }
+
+    #[test]
+    fn test_uninstall_removes_what_this_tool_installed() {
+        let dir = scratch_dir("uninstall-all");
+        let runner = FakeRunner::new().on("rustup self uninstall", CommandOutput::failed("rustup is busy"));
+        let ctx = test_context(&runner, dir.join("msys64"), &dir);
+        let entries = [
+            Entry::Msys2Installed { root: ctx.msys2_root.clone() },
+            Entry::PackagesInstalled {
+                root: ctx.msys2_root.clone(),
+                packages: vec!["mingw-w64-x86_64-gcc".to_string()],
+            },
+            Entry::RustupInstalled,
+            Entry::DefaultSet { previous: None },
+        ];
+        for entry in &entries {
+            ctx.journal.record(entry).unwrap();
+        }
+
+        let err = uninstall(&ctx).unwrap_err().to_string();
+
+        assert!(runner.ran("uninstall.exe pr --confirm-command"));
+        assert!(!runner.ran("pacman -R") && !runner.ran("rustup default"));
+        // The failed rustup removal is kept for the next attempt
+        assert!(err.contains("1 change(s) could not be undone"), "{}", err);
+        assert_eq!(ctx.journal.entries().unwrap(), [Entry::RustupInstalled]);
+    }
+
+    #[test]
+    fn test_failures_carry_their_exit_code() {
+        let dir = scratch_dir("exit-codes");
+        let runner = FakeRunner::new().on(
+            "pacman -S --needed",
+            CommandOutput::failed(include_str!("../testdata/pacman/conflicting_files.txt")),
+        );
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        let err = install_gnu_toolchain(&ctx).unwrap_err();
+        assert_eq!(err.exit_code(), 8);
+        assert!(err.to_string().contains("mingw-w64-x86_64-pkgconf"), "{}", err);
+
+        let runner = FakeRunner::new().on("rustup target add", CommandOutput::failed("error: toolchain not installed"));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        assert_eq!(install_rust_gnu(&ctx).unwrap_err().exit_code(), 9);
+
+        let runner = FakeRunner::new().on("test_gnu.rs", CommandOutput::failed("error: linker `x86_64-w64-mingw32-gcc` not found"));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        let err = verify_installation(&ctx).unwrap_err();
+        assert_eq!(err.exit_code(), 10);
+        assert!(err.to_string().contains("linker"), "{}", err);
+
+        let ctx = test_context(&runner, dir.join("no-msys64"), &dir);
+        assert_eq!(update(&ctx).unwrap_err().exit_code(), 11);
+    }
+
+    #[test]
+    fn test_missing_toolchain_package_is_skipped() {
+        let dir = scratch_dir("missing-package");
+        let runner = FakeRunner::new()
+            .on("pacman -S --needed --noconfirm mingw-w64-x86_64-toolchain mingw-w64-x86_64-cmake mingw-w64-x86_64-pkgconf mingw-w64-x86_64-openssl", CommandOutput::failed("error: target not found: mingw-w64-x86_64-openssl"))
+            .on("pacman -S --needed", CommandOutput::failed(include_str!("../testdata/pacman/db_lock.txt")));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+
+        let err = install_gnu_toolchain(&ctx).unwrap_err();
+
+        // openssl is left out of the retry, which the locked database stops
+        let calls = runner.calls();
+        let retry = calls.iter().rfind(|call| call.contains("pacman -S --needed")).unwrap();
+        assert!(retry.contains("mingw-w64-x86_64-pkgconf mingw-w64-x86_64-make") && !retry.contains("openssl"));
+        assert!(err.to_string().contains("database is locked"), "{}", err);
+        assert_eq!(err.exit_code(), 8);
+    }
+
+    #[test]
+    fn test_toolchain_rerun_installs_only_what_is_missing() {
+        let dir = scratch_dir("toolchain-needed");
+        let groups = "mingw-w64-x86_64-toolchain mingw-w64-x86_64-gcc\nmingw-w64-x86_64-toolchain mingw-w64-x86_64-gdb\n";
+        let installed = "mingw-w64-x86_64-gcc\nmingw-w64-x86_64-gdb\nmingw-w64-x86_64-cmake\nmingw-w64-x86_64-pkgconf\n";
+        let runner = FakeRunner::new()
+            .on("pacman -Qq", CommandOutput::ok(installed))
+            .on("pacman -Sg", CommandOutput::ok(groups));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+
+        install_gnu_toolchain(&ctx).unwrap();
+        assert_eq!(
+            pacman_install(&runner).unwrap(),
+            format!(
+                "{} -l -c pacman -S --needed --noconfirm mingw-w64-x86_64-openssl mingw-w64-x86_64-make",
+                msys2::bash_path(&ctx.msys2_root).display()
+            )
+        );
+
+        // Once everything is there, pacman -S is not run at all
+        let installed = format!("{}mingw-w64-x86_64-openssl\nmingw-w64-x86_64-make\n", installed);
+        let runner = FakeRunner::new()
+            .on("pacman -Qq", CommandOutput::ok(&installed))
+            .on("pacman -Sg", CommandOutput::ok(groups));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        install_gnu_toolchain(&ctx).unwrap();
+        assert!(pacman_install(&runner).is_none());
+    }
+
+    /// `pacman -Syu` answering with the recorded `outputs` in turn.
+    struct UpgradeRunner {
+        outputs: std::cell::RefCell<Vec<CommandOutput>>,
+        inner: FakeRunner,
+    }
+
+    impl CommandRunner for UpgradeRunner {
+        fn run_with_env(&self, program: &str, args: &[&str], env: &[(&str, &str)]) -> io::Result<CommandOutput> {
+            let output = self.inner.run_with_env(program, args, env)?;
+            if args.iter().any(|arg| arg.starts_with("pacman -Syu")) && !self.outputs.borrow().is_empty() {
+                return Ok(self.outputs.borrow_mut().remove(0));
+            }
+            Ok(output)
+        }
+    }
+
+    #[test]
+    fn test_core_system_upgrade_restarts_msys2() {
+        let dir = scratch_dir("core-upgrade");
+        let core = CommandOutput {
+            code: Some(1),
+            stdout: include_str!("../testdata/pacman/core_upgrade.txt").to_string(),
+            stderr: String::new(),
+        };
+        let no_tasks = CommandOutput::failed("INFO: No tasks are running which match the specified criteria.");
+        let runner = UpgradeRunner {
+            outputs: std::cell::RefCell::new(vec![
+                core.clone(),
+                CommandOutput::ok(include_str!("../testdata/pacman/full_upgrade.txt")),
+            ]),
+            inner: FakeRunner::new().on("taskkill", no_tasks.clone()),
+        };
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+
+        initialize_msys2(&ctx).unwrap();
+
+        let calls = runner.inner.calls();
+        let upgrades: Vec<usize> = (0..calls.len()).filter(|&i| calls[i].contains("pacman -Syu")).collect();
+        assert_eq!(upgrades.len(), 2);
+        assert!(calls[upgrades[0]..upgrades[1]].iter().any(|call| call.contains("taskkill /F /FI MODULES eq msys-2.0.dll")));
+
+        // A core upgrade that never settles gives up after the retry limit
+        let runner = UpgradeRunner {
+            outputs: std::cell::RefCell::new(vec![core; MAX_SYSTEM_UPGRADES + 1]),
+            inner: FakeRunner::new().on("taskkill", no_tasks),
+        };
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        let err = update(&ctx).unwrap_err();
+        assert_eq!(runner.inner.calls().iter().filter(|call| call.contains("pacman -Syu")).count(), MAX_SYSTEM_UPGRADES);
+        assert!(err.to_string().contains("still had core updates"), "{}", err);
+        assert!(!runner.inner.ran("rustup update"));
+    }
+
+    #[test]
+    fn test_pacman_mirror_and_repo_are_configured_with_backups() {
+        let dir = scratch_dir("pacman-config");
+        let runner = FakeRunner::new();
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest = Manifest::parse(
+            r#"
+[msys2]
+mirror = "https://pkgs.corp.example/msys2"
+
+[[msys2.repos]]
+name = "corp"
+server = "https://pkgs.corp.example/repo/$arch"
+"#,
+        )
+        .unwrap();
+        let etc = ctx.msys2_root.join("etc");
+        fs::create_dir_all(etc.join("pacman.d")).unwrap();
+        let mirrorlist = etc.join("pacman.d").join("mirrorlist.mingw");
+        let original = "## Primary\nServer = https://mirror.msys2.org/mingw/$repo/\n";
+        fs::write(&mirrorlist, original).unwrap();
+        let conf = "[options]\nArchitecture = auto\n";
+        fs::write(etc.join("pacman.conf"), conf).unwrap();
+
+        configure_pacman(&ctx).unwrap();
+
+        let rewritten = fs::read_to_string(&mirrorlist).unwrap();
+        assert_eq!(pacman_config::servers(&rewritten), ["https://pkgs.corp.example/msys2/mingw/$repo/"]);
+        assert!(rewritten.contains("#Server = https://mirror.msys2.org/mingw/$repo/"));
+        assert_eq!(fs::read_to_string(etc.join("pacman.d").join("mirrorlist.mingw.bak")).unwrap(), original);
+        let updated = fs::read_to_string(etc.join("pacman.conf")).unwrap();
+        assert!(updated.starts_with(conf) && updated.contains("[corp]\n"), "{}", updated);
+        assert!(updated.ends_with("Server = https://pkgs.corp.example/repo/$arch\n"));
+        assert_eq!(fs::read_to_string(etc.join("pacman.conf.bak")).unwrap(), conf);
+        assert_eq!(
+            ctx.journal.entries().unwrap(),
+            [
+                Entry::FileWritten { path: mirrorlist.clone(), backup: Some(etc.join("pacman.d").join("mirrorlist.mingw.bak")) },
+                Entry::FileWritten { path: etc.join("pacman.conf"), backup: Some(etc.join("pacman.conf.bak")) },
+            ]
+        );
+
+        // Rerunning finds both files already set up
+        configure_pacman(&ctx).unwrap();
+        assert_eq!(fs::read_to_string(&mirrorlist).unwrap(), rewritten);
+        assert!(!etc.join("pacman.conf.bak.1").exists());
+        assert_eq!(ctx.journal.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_unreachable_mirrors_are_not_ranked() {
+        let dir = scratch_dir("rank-unreachable");
+        let runner = FakeRunner::new();
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest.msys2.rank_mirrors = true;
+        let lists = ctx.msys2_root.join("etc").join("pacman.d");
+        fs::create_dir_all(&lists).unwrap();
+        // Nothing listens on port 1
+        let original = "Server = http://127.0.0.1:1/mingw/$repo/\nServer = http://127.0.0.1:1/msys2/mingw/$repo/\n";
+        fs::write(lists.join("mirrorlist.mingw"), original).unwrap();
+
+        configure_pacman(&ctx).unwrap();
+
+        assert_eq!(fs::read_to_string(lists.join("mirrorlist.mingw")).unwrap(), original);
+        assert!(!lists.join("mirrorlist.mingw.bak").exists());
+        assert!(ctx.journal.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verification_program_compiles() {
+        let dir = scratch_dir("test-program");
+        let source = dir.join("test_gnu.rs");
+        fs::write(&source, TEST_PROGRAM).unwrap();
+        // Only std: the crate's own macros do not exist in the program
+        assert!(!TEST_PROGRAM.contains("say!") && !TEST_PROGRAM.contains("detail!"));
+
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let output = std::process::Command::new(rustc)
+            .arg(&source)
+            .arg("-o")
+            .arg(dir.join("test_gnu"))
+            .output()
+            .expect("rustc runs");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn test_update_upgrades_msys2_then_the_toolchain() {
+        let dir = scratch_dir("update");
+        let runner = FakeRunner::new();
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest.rust.channel = "nightly".to_string();
+
+        update(&ctx).unwrap();
+
+        let calls = runner.calls();
+        let position = |pattern: &str| calls.iter().position(|c| c.contains(pattern)).unwrap();
+        assert!(position("pacman -Syu --noconfirm") < position("rustup update nightly-x86_64-pc-windows-gnu"));
+
+        let runner = FakeRunner::new().on("pacman -Syu", CommandOutput::failed("error: failed to synchronize all databases"));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        assert!(update(&ctx).unwrap_err().to_string().contains("failed to synchronize"));
+        assert!(!runner.ran("rustup update"));
+    }
+
+    #[test]
+    fn test_status_only_inspects() {
+        let dir = scratch_dir("status");
+        let runner = FakeRunner::new()
+            .on("rustc --version", CommandOutput::ok("rustc 1.80.0 (x86_64-pc-windows-gnu)"))
+            .on("target list --installed", CommandOutput::ok("x86_64-pc-windows-gnu\n"));
+        let ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.progress.complete(&ctx.fingerprint(), &[], Step::Check).unwrap();
+        ctx.journal.record(&Entry::RustupInstalled).unwrap();
+
+        status(&ctx).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            ["rustc --version", "rustup show active-toolchain", "rustup target list --installed"]
+        );
+        assert!(ctx.progress.path.exists());
+        assert_eq!(ctx.journal.entries().unwrap(), [Entry::RustupInstalled]);
+    }
+
+    #[test]
+    fn test_cross_target_installs_its_toolchain() {
+        let dir = scratch_dir("cross-i686");
+        let runner = FakeRunner::new();
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.manifest.rust.targets = vec!["i686-pc-windows-gnu".to_string()];
+
+        install_gnu_toolchain(&ctx).unwrap();
+        configure_environment(&ctx).unwrap();
+
+        let install = pacman_install(&runner).unwrap();
+        assert!(install.contains("mingw-w64-x86_64-toolchain") && install.contains("mingw-w64-i686-toolchain"));
+        let config = fs::read_to_string(dir.join(".cargo").join("config.toml")).unwrap();
+        assert!(config.contains("[target.i686-pc-windows-gnu]"));
+        let activate = fs::read_to_string(dir.join(".cargo").join("activate.bat")).unwrap();
+        assert!(activate.contains("mingw32"));
+        assert!(dir.join(".cargo").join("deactivate.sh").exists());
+        assert!(config.contains("i686-w64-mingw32-gcc.exe"));
+    }
+
+    /// Context installing from a complete fake bundle under `dir`.
+    fn offline_context<'a>(runner: &'a dyn CommandRunner, msys2_root: PathBuf, dir: &Path) -> Context<'a> {
+        let bundle_dir = dir.join("bundle");
+        fs::create_dir_all(&bundle_dir).unwrap();
+        fake_bundle(&bundle_dir);
+        let bundle = Bundle::open(&bundle_dir, &Manifest::default()).unwrap();
+
+        let mut ctx = test_context(runner, msys2_root, dir);
+        ctx.sources = bundle.sources();
+        ctx.verify = bundle.pin(&VerifyPolicy::default());
+        ctx.offline = Some(bundle);
+        ctx
+    }
+
+    #[test]
+    fn test_offline_install_uses_bundle() {
+        let dir = scratch_dir("offline-flow");
+        let runner = FakeRunner::new().missing("rustup --version");
+        let ctx = offline_context(&runner, fake_msys2_root(&dir), &dir);
+
+        install_gnu_toolchain(&ctx).unwrap();
+        install_rust_gnu(&ctx).unwrap();
+
+        let packages = msys2::to_msys_path(&dir.join("bundle").join("pacman"));
+        assert!(runner.ran(&format!("pacman -U --noconfirm --needed '{}'/*.pkg.tar.zst", packages)));
+        assert!(!runner.ran("pacman -S "));
+        let dist_server = format!("RUSTUP_DIST_SERVER={}", download::path_to_file_url(&dir.join("bundle").join("rustup")));
+        assert!(runner.ran(&format!("{} {}", dist_server, dir.join("rustup-init.exe").display())));
+        assert!(runner.ran(&format!("{} rustup override set", dist_server)));
+    }
+
+    #[test]
+    fn test_initialize_msys2_offline_skips_sync() {
+        let dir = scratch_dir("offline-init");
+        let runner = FakeRunner::new();
+        let ctx = offline_context(&runner, fake_msys2_root(&dir), &dir);
+
+        initialize_msys2(&ctx).unwrap();
+
+        assert!(runner.ran("pacman-key --populate msys2"));
+        assert!(!runner.ran("pacman -Sy"));
+    }
+
+    #[test]
+    fn test_installer_with_bad_checksum_is_never_run() {
+        let dir = scratch_dir("bad-checksum");
+        let server = TestServer::start(
+            &[
+                ("/rustup-init.exe", b"tampered"),
+                ("/rustup-init.exe.sha256", sha256_of(b"rustup-init").as_bytes()),
+            ],
+            Faults::default(),
+        );
+        let runner = FakeRunner::new().missing("rustup --version");
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.sources = Sources::from_base(&server.base_url);
+
+        let err = install_rust_gnu(&ctx).unwrap_err();
+
+        assert!(matches!(err, InstallError::Checksum { .. }));
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert!(!runner.ran("rustup-init.exe"));
+        assert!(!dir.join("rustup-init.exe").exists());
+    }
+
+    #[test]
+    fn test_unreachable_checksum_is_a_download_failure() {
+        let dir = scratch_dir("missing-sidecar");
+        let server = TestServer::start(&[("/rustup-init.exe", b"rustup-init")], Faults::default());
+        let runner = FakeRunner::new().missing("rustup --version");
+        let mut ctx = test_context(&runner, fake_msys2_root(&dir), &dir);
+        ctx.sources = Sources::from_base(&server.base_url);
+
+        let err = install_rust_gnu(&ctx).unwrap_err();
+
+        assert!(matches!(&err, InstallError::Download { url, .. } if url.ends_with("/rustup-init.exe.sha256")), "{:?}", err);
+        assert_eq!(err.exit_code(), 5);
+        assert!(err.to_string().contains("--rustup-sha256"));
+        assert!(!runner.ran("rustup-init.exe"));
+    }
+
+    #[test]
+    fn test_installer_signature_is_checked_before_running() {
+        let dir = scratch_dir("bad-signature");
+        let server = TestServer::start(
+            &[
+                ("/msys2-x86_64-latest.exe", b"installer"),
+                ("/msys2-x86_64-latest.exe.sig", b"signature"),
+            ],
+            Faults::default(),
+        );
+        let runner = FakeRunner::new().on("gpg --verify", CommandOutput::failed("gpg: BAD signature"));
+        let mut ctx = test_context(&runner, dir.join("msys64"), &dir);
+        ctx.sources = Sources::from_base(&server.base_url);
+        ctx.verify.msys2_sha256 = Some(sha256_of(b"installer"));
+        ctx.verify.signature = Some(verify::SignatureTool::Gpg);
+
+        let err = download_and_install_msys2(&ctx).unwrap_err();
+
+        assert!(err.to_string().contains("BAD signature"));
+        assert!(!runner.ran("install --confirm-command"));
+        assert!(!dir.join("msys2-installer.exe").exists());
+    }
+}
@@ -0,0 +1,542 @@
+//! `rs-easy-install.toml`: a team-shareable description of what gets
+//! installed. Every field is optional; an empty file (or no file at all)
+//! gives the built-in defaults.
+//!
+//! ```toml
+//! [msys2]
+//! env = "mingw64"
+//! packages = ["mingw-w64-x86_64-gdb"]
+//! mirror = "https://pkgs.corp.example/msys2"
+//!
+//! [[msys2.repos]]
+//! name = "corp"
+//! server = "https://pkgs.corp.example/repo/$arch"
+//! sig-level = "Optional TrustAll"
+//!
+//! [rust]
+//! channel = "1.81.0"
+//! profile = "default"
+//! components = ["rust-src"]
+//! targets = ["wasm32-unknown-unknown", "i686-pc-windows-gnu"]
+//!
+//! [cargo]
+//! config = true
+//! default-target = true
+//! env = { PKG_CONFIG_ALLOW_CROSS = "1" }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::msys2::Environment;
+
+/// Looked up in the current directory when `--manifest` is not given.
+pub const FILE_NAME: &str = "rs-easy-install.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub msys2: Msys2Section,
+    pub rust: RustSection,
+    pub cargo: CargoSection,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Msys2Section {
+    pub env: Environment,
+    /// Installed on top of the GNU toolchain packages.
+    pub packages: Vec<String>,
+    /// Base URL every `mirrorlist.*` is pointed at instead of the public
+    /// mirrors, e.g. an internal one.
+    pub mirror: Option<String>,
+    /// Order the public mirrors by how fast they answer.
+    pub rank_mirrors: bool,
+    /// Extra repositories for `pacman.conf`.
+    pub repos: Vec<Repo>,
+}
+
+/// A `[name]` section added to `pacman.conf`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Repo {
+    pub name: String,
+    pub server: String,
+    /// pacman's `SigLevel`, e.g. `Optional TrustAll`; pacman's default if unset.
+    pub sig_level: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RustSection {
+    /// `stable`, `beta`, `nightly`, a dated `nightly-YYYY-MM-DD` or a version like `1.81.0`.
+    pub channel: String,
+    /// rustup profile: `minimal`, `default` or `complete`.
+    pub profile: String,
+    /// Installed on top of the profile, e.g. `rust-src`.
+    pub components: Vec<String>,
+    /// Extra targets besides the GNU host. Windows GNU targets of another
+    /// MSYS2 environment also get that environment's toolchain.
+    pub targets: Vec<String>,
+}
+
+impl Default for RustSection {
+    fn default() -> Self {
+        RustSection {
+            channel: "stable".to_string(),
+            profile: "default".to_string(),
+            components: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CargoSection {
+    /// Write `.cargo/config.toml` at all.
+    pub config: bool,
+    /// Make the GNU target the `[build] target`.
+    pub default_target: bool,
+    /// Extra `[env]` entries.
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for CargoSection {
+    fn default() -> Self {
+        CargoSection {
+            config: true,
+            default_target: true,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Read and validate the manifest at `path`.
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Manifest::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let manifest: Manifest = toml::from_str(text).map_err(|e| e.to_string())?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Apply `--env` and `--toolchain`, which win over the manifest.
+    pub fn with_overrides(mut self, env: Option<Environment>, channel: Option<&str>) -> Result<Manifest, String> {
+        if let Some(env) = env {
+            self.msys2.env = env;
+        }
+        if let Some(channel) = channel {
+            self.rust.channel = channel.to_string();
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check the values serde cannot, reporting every problem at once.
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+
+        if !is_channel(&self.rust.channel) {
+            problems.push(format!(
+                "rust.channel '{}' is not stable, beta, nightly, nightly-YYYY-MM-DD or a version like 1.81.0",
+                self.rust.channel
+            ));
+        }
+        if !["minimal", "default", "complete"].contains(&self.rust.profile.as_str()) {
+            problems.push(format!(
+                "rust.profile '{}' must be minimal, default or complete",
+                self.rust.profile
+            ));
+        }
+        // Package names end up in a shell command line
+        let env = self.msys2.env;
+        for package in &self.msys2.packages {
+            let valid = !package.is_empty()
+                && package
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c));
+            if !valid {
+                problems.push(format!("msys2.packages: '{}' is not a valid package name", package));
+            } else if package.starts_with("mingw-w64-") && self.package_environment(package).is_none() {
+                problems.push(format!(
+                    "msys2.packages: '{}' is not a {} package (they start with {}-)",
+                    package,
+                    env,
+                    env.package_prefix()
+                ));
+            }
+        }
+        let msys2 = &self.msys2;
+        if let Some(mirror) = &msys2.mirror {
+            if !is_url(mirror) {
+                problems.push(format!("msys2.mirror '{}' is not an http(s) or file URL", mirror));
+            }
+            if msys2.rank_mirrors {
+                problems.push("msys2.rank-mirrors has nothing to rank when msys2.mirror replaces the mirrors".to_string());
+            }
+        }
+        for (i, repo) in msys2.repos.iter().enumerate() {
+            let valid = !repo.name.is_empty()
+                && repo.name != "options"
+                && repo.name.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c));
+            // with_repo replaces a section of the same name, Include line and all
+            let official = repo.name.eq_ignore_ascii_case("msys")
+                || Environment::ALL.iter().any(|env| repo.name.eq_ignore_ascii_case(env.name()));
+            if !valid {
+                problems.push(format!("msys2.repos: '{}' is not a valid repository name", repo.name));
+            } else if official {
+                problems.push(format!(
+                    "msys2.repos: '{}' is one of MSYS2's own repositories; give the extra repository another name",
+                    repo.name
+                ));
+            } else if msys2.repos[..i].iter().any(|other| other.name == repo.name) {
+                problems.push(format!("msys2.repos: '{}' is listed twice", repo.name));
+            }
+            if !is_url(&repo.server) {
+                problems.push(format!("msys2.repos: server '{}' is not an http(s) or file URL", repo.server));
+            }
+            if repo.sig_level.as_ref().is_some_and(|level| level.contains(['\n', '\r', '[', ']'])) {
+                problems.push(format!("msys2.repos: sig-level of '{}' must be a single line", repo.name));
+            }
+        }
+        for component in &self.rust.components {
+            if component.is_empty() || !component.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                problems.push(format!("rust.components: '{}' is not a valid component name", component));
+            }
+        }
+        for target in &self.rust.targets {
+            let parts = target.split('-').count();
+            if !(2..=4).contains(&parts) || !target.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+                problems.push(format!(
+                    "rust.targets: '{}' is not a target triple (see `rustc --print target-list`)",
+                    target
+                ));
+            }
+        }
+        for name in self.cargo.env.keys() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                problems.push(format!("cargo.env: '{}' is not a valid variable name", name));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("invalid manifest:\n   - {}", problems.join("\n   - ")))
+        }
+    }
+
+    /// Environments whose toolchain is installed for a cross target in
+    /// `rust.targets`, besides the primary one.
+    pub fn cross_environments(&self) -> Vec<Environment> {
+        let mut envs = Vec::new();
+        for env in self.rust.targets.iter().filter_map(|target| Environment::for_target(target)) {
+            if env.rust_target() != self.msys2.env.rust_target() && !envs.contains(&env) {
+                envs.push(env);
+            }
+        }
+        envs
+    }
+
+    /// The primary environment followed by the cross ones.
+    pub fn environments(&self) -> Vec<Environment> {
+        let mut envs = vec![self.msys2.env];
+        envs.extend(self.cross_environments());
+        envs
+    }
+
+    /// Which installed environment a `mingw-w64-` package belongs to.
+    pub fn package_environment(&self, package: &str) -> Option<Environment> {
+        self.environments()
+            .into_iter()
+            .find(|env| package.starts_with(&format!("{}-", env.package_prefix())))
+    }
+
+    /// Toolchain name for `rustup override set`, e.g. `stable-x86_64-pc-windows-gnu`.
+    pub fn toolchain(&self) -> String {
+        format!("{}-{}", self.rust.channel, self.msys2.env.rust_target())
+    }
+
+    /// Channel manifest path on a rustup dist server:
+    /// `dist/channel-rust-stable.toml`, `dist/2024-09-05/channel-rust-nightly.toml`.
+    pub fn channel_manifest(&self) -> String {
+        let channel = &self.rust.channel;
+        for name in ["nightly", "beta", "stable"] {
+            if let Some(date) = channel.strip_prefix(name).and_then(|rest| rest.strip_prefix('-')) {
+                return format!("dist/{}/channel-rust-{}.toml", date, name);
+            }
+        }
+        format!("dist/channel-rust-{}.toml", channel)
+    }
+
+    /// Contents of the generated `.cargo/config.toml`. The primary
+    /// environment's tools are expected on PATH; cross environments are
+    /// referenced by absolute path below `msys2_root`.
+    pub fn cargo_config(&self, msys2_root: &Path) -> String {
+        let env = self.msys2.env;
+        let target = env.rust_target();
+        let mut config = String::new();
+
+        let _ = writeln!(config, "[target.{}]", target);
+        let _ = writeln!(config, "linker = \"{}\"", env.linker());
+        let _ = writeln!(config, "ar = \"{}\"", env.ar());
+        config.push('\n');
+
+        let tool = |env: Environment, name: &str| toml::Value::String(cross_tool(msys2_root, env, name));
+        for env in self.cross_environments() {
+            let _ = writeln!(config, "[target.{}]", env.rust_target());
+            let _ = writeln!(config, "linker = {}", tool(env, env.linker()));
+            let _ = writeln!(config, "ar = {}", tool(env, env.ar()));
+            config.push('\n');
+        }
+
+        if self.cargo.default_target {
+            let _ = writeln!(config, "[build]");
+            let _ = writeln!(config, "target = \"{}\"", target);
+            config.push('\n');
+        }
+
+        let _ = writeln!(config, "[env]");
+        for (name, value) in self.compiler_vars(msys2_root) {
+            let _ = writeln!(config, "{} = {}", name, toml::Value::String(value));
+        }
+        for (name, value) in &self.cargo.env {
+            let _ = writeln!(config, "{} = {}", name, toml::Value::String(value.clone()));
+        }
+        config
+    }
+
+    /// `CC_<target>`/`CXX_<target>` for the primary environment (tools on
+    /// PATH) and every cross environment (absolute paths below `msys2_root`).
+    pub fn compiler_vars(&self, msys2_root: &Path) -> Vec<(String, String)> {
+        let primary = self.msys2.env;
+        let mut vars = Vec::new();
+        for env in self.environments() {
+            let suffix = env.rust_target().replace('-', "_");
+            let (cc, cxx) = if env == primary {
+                (env.c_compiler().to_string(), env.cxx_compiler().to_string())
+            } else {
+                (cross_tool(msys2_root, env, env.c_compiler()), cross_tool(msys2_root, env, env.cxx_compiler()))
+            };
+            vars.push((format!("CC_{}", suffix), cc));
+            vars.push((format!("CXX_{}", suffix), cxx));
+        }
+        vars
+    }
+}
+
+/// Absolute path of a cross environment's tool.
+fn cross_tool(msys2_root: &Path, env: Environment, name: &str) -> String {
+    env.bin_dir(msys2_root).join(format!("{}.exe", name)).display().to_string()
+}
+
+/// A URL pacman can fetch from that fits on one config line.
+fn is_url(url: &str) -> bool {
+    ["http://", "https://", "file://"].iter().any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn is_channel(channel: &str) -> bool {
+    let is_date = |date: &str| {
+        let parts: Vec<&str> = date.split('-').collect();
+        parts.len() == 3
+            && [4, 2, 2].iter().zip(&parts).all(|(len, part)| part.len() == *len && part.chars().all(|c| c.is_ascii_digit()))
+    };
+    let is_version = |version: &str| {
+        let parts: Vec<&str> = version.split('.').collect();
+        (2..=3).contains(&parts.len()) && parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+    };
+
+    ["stable", "beta", "nightly"].iter().any(|name| {
+        channel == *name
+            || channel
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(is_date)
+    }) || is_version(channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_manifest_is_the_default_install() {
+        let manifest = Manifest::parse("").unwrap();
+        assert_eq!(manifest, Manifest::default());
+        assert_eq!(manifest.toolchain(), "stable-x86_64-pc-windows-gnu");
+        assert_eq!(
+            manifest.cargo_config(Path::new("C:\\msys64")),
+            r#"[target.x86_64-pc-windows-gnu]
+linker = "x86_64-w64-mingw32-gcc"
+ar = "x86_64-w64-mingw32-ar"
+
+[build]
+target = "x86_64-pc-windows-gnu"
+
+[env]
+CC_x86_64_pc_windows_gnu = "x86_64-w64-mingw32-gcc"
+CXX_x86_64_pc_windows_gnu = "x86_64-w64-mingw32-g++"
+"#
+        );
+    }
+
+    #[test]
+    fn test_parse_full_manifest() {
+        let manifest = Manifest::parse(
+            r#"
+[msys2]
+env = "mingw64"
+packages = ["mingw-w64-x86_64-gdb"]
+
+[rust]
+channel = "nightly-2024-09-05"
+components = ["rust-src"]
+targets = ["wasm32-unknown-unknown"]
+
+[cargo]
+default-target = false
+env = { PKG_CONFIG_ALLOW_CROSS = "1" }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.msys2.packages, ["mingw-w64-x86_64-gdb"]);
+        assert_eq!(manifest.rust.profile, "default");
+        assert_eq!(manifest.channel_manifest(), "dist/2024-09-05/channel-rust-nightly.toml");
+        let config = manifest.cargo_config(Path::new("C:\\msys64"));
+        assert!(!config.contains("[build]"));
+        assert!(config.ends_with("PKG_CONFIG_ALLOW_CROSS = \"1\"\n"));
+    }
+
+    #[test]
+    fn test_clang64_config() {
+        let manifest = Manifest::parse("[msys2]\nenv = \"clang64\"\npackages = [\"mingw-w64-clang-x86_64-gdb\"]\n").unwrap();
+
+        assert_eq!(manifest.toolchain(), "stable-x86_64-pc-windows-gnullvm");
+        let config = manifest.cargo_config(Path::new("C:\\msys64"));
+        assert!(config.starts_with("[target.x86_64-pc-windows-gnullvm]\nlinker = \"x86_64-w64-mingw32-clang\"\nar = \"llvm-ar\"\n"));
+        assert!(config.contains("CXX_x86_64_pc_windows_gnullvm = \"x86_64-w64-mingw32-clang++\""));
+
+        let err = Manifest::parse("[msys2]\nenv = \"ucrt64\"\npackages = [\"mingw-w64-x86_64-gdb\"]\n").unwrap_err();
+        assert!(err.contains("is not a ucrt64 package (they start with mingw-w64-ucrt-x86_64-)"), "{}", err);
+    }
+
+    #[test]
+    fn test_cross_targets_get_their_environment() {
+        let manifest = Manifest::parse(
+            r#"
+[msys2]
+packages = ["mingw-w64-i686-gdb"]
+
+[rust]
+targets = ["i686-pc-windows-gnu", "wasm32-unknown-unknown", "x86_64-pc-windows-gnu", "i686-pc-windows-gnu"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.environments(), [Environment::Mingw64, Environment::Mingw32]);
+        assert_eq!(manifest.package_environment("mingw-w64-i686-gdb"), Some(Environment::Mingw32));
+
+        let root = Path::new("C:\\msys64");
+        let gcc = toml::Value::String(root.join("mingw32").join("bin").join("i686-w64-mingw32-gcc.exe").display().to_string());
+        let config = manifest.cargo_config(root);
+        assert!(config.contains(&format!("[target.i686-pc-windows-gnu]\nlinker = {}\n", gcc)), "{}", config);
+        assert!(config.contains(&format!("CC_i686_pc_windows_gnu = {}\n", gcc)), "{}", config);
+        assert!(!config.contains("[target.wasm32"));
+
+        let manifest = Manifest::parse("[msys2]\nenv = \"clangarm64\"\n[rust]\ntargets = [\"x86_64-pc-windows-gnullvm\"]\n").unwrap();
+        assert_eq!(manifest.toolchain(), "stable-aarch64-pc-windows-gnullvm");
+        assert_eq!(manifest.environments(), [Environment::ClangArm64, Environment::Clang64]);
+
+        let err = Manifest::parse("[msys2]\npackages = [\"mingw-w64-i686-gdb\"]\n").unwrap_err();
+        assert!(err.contains("is not a mingw64 package"), "{}", err);
+    }
+
+    #[test]
+    fn test_errors_point_at_the_problem() {
+        let err = Manifest::parse("[rust]\nchanel = \"stable\"\n").unwrap_err();
+        assert!(err.contains("unknown field `chanel`"), "{}", err);
+        assert!(err.contains("line 2"), "{}", err);
+
+        let err = Manifest::parse("[msys2]\nenv = \"mingw65\"\n").unwrap_err();
+        assert!(err.contains("unknown variant `mingw65`"), "{}", err);
+
+        let err = Manifest::parse(
+            "[rust]\nchannel = \"latest\"\nprofile = \"full\"\ntargets = [\"windows\"]\n[msys2]\npackages = [\"gdb; rm -rf /\"]\n",
+        )
+        .unwrap_err();
+        for expected in ["rust.channel 'latest'", "rust.profile 'full'", "'windows' is not a target triple", "'gdb; rm -rf /'"] {
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_pacman_settings() {
+        let manifest = Manifest::parse(
+            r#"
+[msys2]
+mirror = "https://pkgs.corp.example/msys2"
+
+[[msys2.repos]]
+name = "corp"
+server = "https://pkgs.corp.example/repo/$arch"
+sig-level = "Optional TrustAll"
+"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.msys2.mirror.as_deref(), Some("https://pkgs.corp.example/msys2"));
+        assert_eq!(manifest.msys2.repos[0].sig_level.as_deref(), Some("Optional TrustAll"));
+        assert!(!manifest.msys2.rank_mirrors);
+
+        let err = Manifest::parse(
+            "[msys2]\nmirror = \"mirror.local\"\nrank-mirrors = true\n\
+             [[msys2.repos]]\nname = \"options\"\nserver = \"https://x\"\n\
+             [[msys2.repos]]\nname = \"corp\"\nserver = \"https://x y\"\n\
+             [[msys2.repos]]\nname = \"msys\"\nserver = \"https://x\"\n\
+             [[msys2.repos]]\nname = \"UCRT64\"\nserver = \"https://x\"\n",
+        )
+        .unwrap_err();
+        for expected in [
+            "msys2.mirror 'mirror.local'",
+            "nothing to rank",
+            "'options' is not a valid",
+            "server 'https://x y'",
+            "'msys' is one of MSYS2's own repositories",
+            "'UCRT64' is one of MSYS2's own repositories",
+        ] {
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_command_line_overrides() {
+        let manifest = Manifest::parse("[rust]\nchannel = \"1.81.0\"\n").unwrap();
+        let manifest = manifest.with_overrides(Some(Environment::Ucrt64), Some("nightly")).unwrap();
+        assert_eq!(manifest.msys2.env, Environment::Ucrt64);
+        assert_eq!(manifest.toolchain(), "nightly-x86_64-pc-windows-gnu");
+
+        // Packages from the manifest's environment no longer fit
+        let manifest = Manifest::parse("[msys2]\npackages = [\"mingw-w64-x86_64-gdb\"]\n").unwrap();
+        assert!(manifest.clone().with_overrides(Some(Environment::Ucrt64), None).unwrap_err().contains("not a ucrt64 package"));
+        assert!(manifest.with_overrides(None, Some("latest")).unwrap_err().contains("rust.channel 'latest'"));
+    }
+
+    #[test]
+    fn test_channels() {
+        for channel in ["stable", "beta", "nightly", "nightly-2024-09-05", "1.81.0", "1.81"] {
+            assert!(is_channel(channel), "{}", channel);
+        }
+        for channel in ["latest", "nightly-2024-9-5", "1.x", "stable-x86_64-pc-windows-gnu"] {
+            assert!(!is_channel(channel), "{}", channel);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Locating the MSYS2 installation every step works against, and the
+//! environments (toolchains) inside it.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Where MSYS2 is installed when nothing else says otherwise.
+pub const DEFAULT_ROOT: &str = "C:\\msys64";
+
+/// Environment variable that overrides the MSYS2 root.
+pub const ROOT_ENV: &str = "MSYS2_ROOT";
+
+/// mingw-w64 packages (or groups) installed for the GNU toolchain, as
+/// `(description, name without the environment prefix)`.
+pub const GNU_PACKAGES: &[(&str, &str)] = &[
+    ("Core toolchain", "toolchain"),
+    ("CMake", "cmake"),
+    ("pkg-config", "pkgconf"),
+    ("OpenSSL", "openssl"),
+    ("Additional tools", "make"),
+];
+
+/// MSYS2 environment the toolchain is installed into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Mingw64,
+    Ucrt64,
+    Clang64,
+    /// 32-bit x86.
+    Mingw32,
+    /// ARM64; its packages only run on ARM64 Windows.
+    ClangArm64,
+}
+
+/// What differs between environments.
+struct Spec {
+    name: &'static str,
+    package_prefix: &'static str,
+    rust_target: &'static str,
+    /// Build of `rustup-init.exe` to run when this is the primary environment.
+    rustup_init_host: &'static str,
+    c_compiler: &'static str,
+    cxx_compiler: &'static str,
+    ar: &'static str,
+    /// Builds import libraries for `raw-dylib`.
+    dlltool: &'static str,
+    /// DLLs programs built with the environment's compilers load at runtime.
+    runtime_dlls: &'static [&'static str],
+    /// Packages providing the compiler and linker, pulled in by `toolchain`.
+    compiler_packages: &'static [&'static str],
+}
+
+impl Environment {
+    pub const ALL: [Environment; 5] = [
+        Environment::Mingw64,
+        Environment::Ucrt64,
+        Environment::Clang64,
+        Environment::Mingw32,
+        Environment::ClangArm64,
+    ];
+
+    /// The environment providing the linker for a cross `target`, if any.
+    /// `x86_64-pc-windows-gnu` maps to MINGW64 rather than UCRT64.
+    pub fn for_target(target: &str) -> Option<Environment> {
+        Environment::ALL.into_iter().find(|env| env.rust_target() == target)
+    }
+
+    fn spec(self) -> Spec {
+        match self {
+            Environment::Mingw64 => Spec {
+                name: "mingw64",
+                package_prefix: "mingw-w64-x86_64",
+                rust_target: "x86_64-pc-windows-gnu",
+                rustup_init_host: "x86_64-pc-windows-gnu",
+                c_compiler: "x86_64-w64-mingw32-gcc",
+                cxx_compiler: "x86_64-w64-mingw32-g++",
+                ar: "x86_64-w64-mingw32-ar",
+                dlltool: "dlltool",
+                runtime_dlls: &["libgcc_s_seh-1.dll", "libwinpthread-1.dll"],
+                compiler_packages: &["gcc", "binutils"],
+            },
+            Environment::Ucrt64 => Spec {
+                name: "ucrt64",
+                package_prefix: "mingw-w64-ucrt-x86_64",
+                rust_target: "x86_64-pc-windows-gnu",
+                rustup_init_host: "x86_64-pc-windows-gnu",
+                c_compiler: "x86_64-w64-mingw32-gcc",
+                cxx_compiler: "x86_64-w64-mingw32-g++",
+                ar: "x86_64-w64-mingw32-ar",
+                dlltool: "dlltool",
+                runtime_dlls: &["libgcc_s_seh-1.dll", "libwinpthread-1.dll"],
+                compiler_packages: &["gcc", "binutils"],
+            },
+            Environment::Clang64 => Spec {
+                name: "clang64",
+                package_prefix: "mingw-w64-clang-x86_64",
+                rust_target: "x86_64-pc-windows-gnullvm",
+                rustup_init_host: "x86_64-pc-windows-gnu",
+                c_compiler: "x86_64-w64-mingw32-clang",
+                cxx_compiler: "x86_64-w64-mingw32-clang++",
+                ar: "llvm-ar",
+                dlltool: "llvm-dlltool",
+                runtime_dlls: &["libunwind.dll", "libwinpthread-1.dll"],
+                compiler_packages: &["clang", "lld"],
+            },
+            Environment::Mingw32 => Spec {
+                name: "mingw32",
+                package_prefix: "mingw-w64-i686",
+                rust_target: "i686-pc-windows-gnu",
+                rustup_init_host: "i686-pc-windows-gnu",
+                c_compiler: "i686-w64-mingw32-gcc",
+                cxx_compiler: "i686-w64-mingw32-g++",
+                ar: "i686-w64-mingw32-ar",
+                dlltool: "dlltool",
+                runtime_dlls: &["libgcc_s_dw2-1.dll", "libwinpthread-1.dll"],
+                compiler_packages: &["gcc", "binutils"],
+            },
+            Environment::ClangArm64 => Spec {
+                name: "clangarm64",
+                package_prefix: "mingw-w64-clang-aarch64",
+                rust_target: "aarch64-pc-windows-gnullvm",
+                // rustup itself is published for ARM64 as an MSVC build only
+                rustup_init_host: "aarch64-pc-windows-msvc",
+                c_compiler: "aarch64-w64-mingw32-clang",
+                cxx_compiler: "aarch64-w64-mingw32-clang++",
+                ar: "llvm-ar",
+                dlltool: "llvm-dlltool",
+                runtime_dlls: &["libunwind.dll", "libwinpthread-1.dll"],
+                compiler_packages: &["clang", "lld"],
+            },
+        }
+    }
+
+    /// The environment called `name`, as in `--env ucrt64`.
+    pub fn parse(name: &str) -> Option<Environment> {
+        Environment::ALL.into_iter().find(|env| env.name() == name)
+    }
+
+    /// Directory name below the MSYS2 root, also the pacman repository name.
+    pub fn name(self) -> &'static str {
+        self.spec().name
+    }
+
+    /// Prefix of every package built for this environment, e.g. `mingw-w64-ucrt-x86_64`.
+    pub fn package_prefix(self) -> &'static str {
+        self.spec().package_prefix
+    }
+
+    /// Full package name for `name` in this environment.
+    pub fn package(self, name: &str) -> String {
+        format!("{}-{}", self.package_prefix(), name)
+    }
+
+    /// Rust target whose linker lives in this environment.
+    pub fn rust_target(self) -> &'static str {
+        self.spec().rust_target
+    }
+
+    pub fn rustup_init_host(self) -> &'static str {
+        self.spec().rustup_init_host
+    }
+
+    pub fn linker(self) -> &'static str {
+        self.spec().c_compiler
+    }
+
+    pub fn ar(self) -> &'static str {
+        self.spec().ar
+    }
+
+    pub fn c_compiler(self) -> &'static str {
+        self.spec().c_compiler
+    }
+
+    pub fn cxx_compiler(self) -> &'static str {
+        self.spec().cxx_compiler
+    }
+
+    pub fn dlltool(self) -> &'static str {
+        self.spec().dlltool
+    }
+
+    pub fn runtime_dlls(self) -> &'static [&'static str] {
+        self.spec().runtime_dlls
+    }
+
+    /// [`GNU_PACKAGES`] with this environment's prefix.
+    pub fn toolchain_packages(self) -> Vec<(&'static str, String)> {
+        GNU_PACKAGES
+            .iter()
+            .map(|(description, name)| (*description, self.package(name)))
+            .collect()
+    }
+
+    /// Packages that must be installed for the toolchain to work; unlike
+    /// [`Self::toolchain_packages`] these are never groups.
+    pub fn required_packages(self) -> Vec<String> {
+        // `toolchain` is a group; stand in the packages that provide the compiler
+        let others = GNU_PACKAGES
+            .iter()
+            .map(|(_, name)| *name)
+            .filter(|name| *name != "toolchain");
+        self.spec()
+            .compiler_packages
+            .iter()
+            .copied()
+            .chain(others)
+            .map(|name| self.package(name))
+            .collect()
+    }
+
+    /// Directory with the environment's executables and DLLs.
+    pub fn bin_dir(self, root: &Path) -> PathBuf {
+        root.join(self.name()).join("bin")
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How the MSYS2 root was chosen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootSource {
+    Flag,
+    Env,
+    Detected(&'static str),
+    Default,
+}
+
+impl fmt::Display for RootSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootSource::Flag => write!(f, "from --msys2-root"),
+            RootSource::Env => write!(f, "from {}", ROOT_ENV),
+            RootSource::Detected(kind) => write!(f, "detected {} install", kind),
+            RootSource::Default => write!(f, "default location"),
+        }
+    }
+}
+
+/// `usr\bin\bash.exe` below an MSYS2 root.
+pub fn bash_path(root: &Path) -> PathBuf {
+    root.join("usr").join("bin").join("bash.exe")
+}
+
+/// Windows path as seen from an MSYS2 shell: `C:\bundle\pacman` → `/c/bundle/pacman`.
+pub fn to_msys_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    match path.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => {
+            format!("/{}{}", (*drive as char).to_ascii_lowercase(), &path[2..])
+        }
+        _ => path,
+    }
+}
+
+/// Well-known install locations, in the order they are probed.
+fn candidates(env: &dyn Fn(&str) -> Option<String>) -> Vec<(PathBuf, &'static str)> {
+    let mut candidates = vec![
+        (PathBuf::from(DEFAULT_ROOT), "standard"),
+        (PathBuf::from("C:\\msys32"), "legacy 32-bit"),
+    ];
+
+    let scoop = env("SCOOP")
+        .map(PathBuf::from)
+        .or_else(|| env("USERPROFILE").map(|home| Path::new(&home).join("scoop")));
+    if let Some(scoop) = scoop {
+        candidates.push((scoop.join("apps").join("msys2").join("current"), "Scoop"));
+    }
+    let scoop_global = env("SCOOP_GLOBAL").unwrap_or_else(|| "C:\\ProgramData\\scoop".to_string());
+    candidates.push((
+        Path::new(&scoop_global).join("apps").join("msys2").join("current"),
+        "Scoop (global)",
+    ));
+
+    let choco_tools = env("ChocolateyToolsLocation").unwrap_or_else(|| "C:\\tools".to_string());
+    candidates.push((Path::new(&choco_tools).join("msys64"), "Chocolatey"));
+
+    candidates
+}
+
+/// Pick the MSYS2 root: `--msys2-root`, then `MSYS2_ROOT`, then the first
+/// well-known location that contains `bash.exe`, then [`DEFAULT_ROOT`].
+pub fn resolve_root(flag: Option<&Path>, env: &dyn Fn(&str) -> Option<String>) -> (PathBuf, RootSource) {
+    if let Some(root) = flag {
+        return (root.to_path_buf(), RootSource::Flag);
+    }
+    if let Some(root) = env(ROOT_ENV).filter(|root| !root.is_empty()) {
+        return (PathBuf::from(root), RootSource::Env);
+    }
+
+    candidates(env)
+        .into_iter()
+        .find(|(root, _)| bash_path(root).exists())
+        .map(|(root, kind)| (root, RootSource::Detected(kind)))
+        .unwrap_or_else(|| (PathBuf::from(DEFAULT_ROOT), RootSource::Default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_flag_beats_env() {
+        let env = |name: &str| (name == ROOT_ENV).then(|| "D:\\msys2".to_string());
+
+        let (root, source) = resolve_root(Some(Path::new("E:\\tools\\msys64")), &env);
+        assert_eq!(root, PathBuf::from("E:\\tools\\msys64"));
+        assert_eq!(source, RootSource::Flag);
+
+        let (root, source) = resolve_root(None, &env);
+        assert_eq!(root, PathBuf::from("D:\\msys2"));
+        assert_eq!(source, RootSource::Env);
+    }
+
+    #[test]
+    fn test_detects_scoop_install() {
+        let scoop = std::env::temp_dir().join(format!("rs-easy-install-scoop-{}", std::process::id()));
+        let root = scoop.join("apps").join("msys2").join("current");
+        fs::create_dir_all(root.join("usr").join("bin")).unwrap();
+        fs::write(bash_path(&root), "").unwrap();
+
+        let scoop_dir = scoop.to_string_lossy().into_owned();
+        let env = move |name: &str| (name == "SCOOP").then(|| scoop_dir.clone());
+        let (found, source) = resolve_root(None, &env);
+
+        // Skipped when a real MSYS2 happens to live at a higher-priority location
+        if !bash_path(Path::new(DEFAULT_ROOT)).exists() {
+            assert_eq!(found, root);
+            assert_eq!(source, RootSource::Detected("Scoop"));
+        }
+        let _ = fs::remove_dir_all(&scoop);
+    }
+
+    #[test]
+    fn test_to_msys_path() {
+        assert_eq!(to_msys_path(Path::new("C:\\bundle\\pacman")), "/c/bundle/pacman");
+        assert_eq!(to_msys_path(Path::new("D:/offline")), "/d/offline");
+        assert_eq!(to_msys_path(Path::new("/tmp/bundle")), "/tmp/bundle");
+    }
+
+    #[test]
+    fn test_environments() {
+        assert_eq!(Environment::Mingw64.package("gcc"), "mingw-w64-x86_64-gcc");
+        assert_eq!(Environment::Ucrt64.toolchain_packages()[0].1, "mingw-w64-ucrt-x86_64-toolchain");
+        assert_eq!(Environment::Clang64.rust_target(), "x86_64-pc-windows-gnullvm");
+        assert_eq!(
+            Environment::Clang64.required_packages()[..2],
+            ["mingw-w64-clang-x86_64-clang", "mingw-w64-clang-x86_64-lld"]
+        );
+        assert_eq!(
+            Environment::Ucrt64.bin_dir(Path::new("C:\\msys64")),
+            Path::new("C:\\msys64").join("ucrt64").join("bin")
+        );
+    }
+
+    #[test]
+    fn test_i686_and_arm64() {
+        assert_eq!(Environment::Mingw32.package("gcc"), "mingw-w64-i686-gcc");
+        assert_eq!(Environment::Mingw32.linker(), "i686-w64-mingw32-gcc");
+        assert_eq!(Environment::ClangArm64.package("clang"), "mingw-w64-clang-aarch64-clang");
+        assert_eq!(Environment::ClangArm64.rustup_init_host(), "aarch64-pc-windows-msvc");
+
+        assert_eq!(Environment::for_target("i686-pc-windows-gnu"), Some(Environment::Mingw32));
+        assert_eq!(Environment::for_target("aarch64-pc-windows-gnullvm"), Some(Environment::ClangArm64));
+        assert_eq!(Environment::for_target("x86_64-pc-windows-gnu"), Some(Environment::Mingw64));
+        assert_eq!(Environment::for_target("wasm32-unknown-unknown"), None);
+    }
+
+    #[test]
+    fn test_falls_back_to_default() {
+        let (root, source) = resolve_root(None, &|_| None);
+        if source == RootSource::Default {
+            assert_eq!(root, PathBuf::from(DEFAULT_ROOT));
+        }
+    }
+}
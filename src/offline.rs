@@ -0,0 +1,268 @@
+//! Offline installs from a pre-fetched bundle directory.
+//!
+//! Bundle layout:
+//!
+//! ```text
+//! manifest.sha256            sha256sum-style list of every file below
+//! msys2-x86_64-latest.exe    MSYS2 installer
+//! rustup-init.exe            rustup installer
+//! pacman/*.pkg.tar.zst       mingw-w64 packages and their dependencies
+//! rustup/dist/...            rustup dist server mirror for RUSTUP_DIST_SERVER
+//! ```
+//!
+//! The whole bundle is validated against the manifest before anything runs.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::download::{Sources, path_to_file_url};
+use crate::manifest::Manifest;
+use crate::verify::{self, VerifyPolicy};
+
+pub const MANIFEST: &str = "manifest.sha256";
+pub const MSYS2_INSTALLER: &str = "msys2-x86_64-latest.exe";
+pub const RUSTUP_INIT: &str = "rustup-init.exe";
+pub const PACKAGES_DIR: &str = "pacman";
+pub const RUSTUP_DIST_DIR: &str = "rustup";
+
+/// A validated offline bundle.
+#[derive(Debug)]
+pub struct Bundle {
+    pub dir: PathBuf,
+    /// Relative path (forward slashes) → SHA-256.
+    digests: BTreeMap<String, String>,
+}
+
+impl Bundle {
+    /// Open `dir` and check that every file the installation described by
+    /// `install` needs is listed in the manifest, present, and matches its
+    /// checksum.
+    pub fn open(dir: &Path, install: &Manifest) -> Result<Bundle, String> {
+        let manifest_path = dir.join(MANIFEST);
+        let manifest = fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Cannot read offline bundle manifest {}: {}", manifest_path.display(), e))?;
+        let digests = parse_manifest(&manifest)?;
+
+        let mut problems = Vec::new();
+        let channel_manifest = format!("{}/{}", RUSTUP_DIST_DIR, install.channel_manifest());
+        let required_files = [
+            MSYS2_INSTALLER.to_string(),
+            RUSTUP_INIT.to_string(),
+            channel_manifest.clone(),
+            format!("{}.sha256", channel_manifest),
+        ];
+        for file in &required_files {
+            if !digests.contains_key(file) {
+                problems.push(format!("{} is not in the manifest", file));
+            }
+        }
+
+        let packages: Vec<&str> = digests
+            .keys()
+            .filter_map(|file| file.strip_prefix(&format!("{}/", PACKAGES_DIR)))
+            .filter_map(package_name)
+            .collect();
+        let required: Vec<String> = install
+            .environments()
+            .into_iter()
+            .flat_map(|env| env.required_packages())
+            .collect();
+        for package in required.iter().chain(&install.msys2.packages) {
+            if !packages.contains(&package.as_str()) {
+                problems.push(format!("no {}/ archive for {}", PACKAGES_DIR, package));
+            }
+        }
+
+        for (file, expected) in &digests {
+            let path = relative_path(dir, file);
+            if !path.exists() {
+                problems.push(format!("{} is missing", file));
+            } else if let Err(e) = verify::check_sha256(&path, expected) {
+                problems.push(e);
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(format!(
+                "Offline bundle {} is incomplete:\n   - {}",
+                dir.display(),
+                problems.join("\n   - ")
+            ));
+        }
+
+        Ok(Bundle {
+            dir: dir.to_path_buf(),
+            digests,
+        })
+    }
+
+    /// Installer URLs pointing into the bundle.
+    pub fn sources(&self) -> Sources {
+        Sources {
+            msys2_installer: path_to_file_url(&self.dir.join(MSYS2_INSTALLER)),
+            rustup_init: path_to_file_url(&self.dir.join(RUSTUP_INIT)),
+            mingw_mirror: path_to_file_url(&self.packages_dir()),
+            rust_dist: self.rustup_dist_server(),
+        }
+    }
+
+    /// Manifest checksum of a bundled file.
+    pub fn digest(&self, file: &str) -> Option<&str> {
+        self.digests.get(file).map(String::as_str)
+    }
+
+    /// `policy` with the installers pinned to their manifest checksums,
+    /// unless pinned on the command line already.
+    pub fn pin(&self, policy: &VerifyPolicy) -> VerifyPolicy {
+        let mut policy = policy.clone();
+        if policy.msys2_sha256.is_none() {
+            policy.msys2_sha256 = self.digest(MSYS2_INSTALLER).map(str::to_string);
+        }
+        if policy.rustup_sha256.is_none() {
+            policy.rustup_sha256 = self.digest(RUSTUP_INIT).map(str::to_string);
+        }
+        policy
+    }
+
+    pub fn packages_dir(&self) -> PathBuf {
+        self.dir.join(PACKAGES_DIR)
+    }
+
+    /// Value for `RUSTUP_DIST_SERVER`.
+    pub fn rustup_dist_server(&self) -> String {
+        path_to_file_url(&self.dir.join(RUSTUP_DIST_DIR))
+    }
+}
+
+fn relative_path(dir: &Path, file: &str) -> PathBuf {
+    file.split('/').fold(dir.to_path_buf(), |path, part| path.join(part))
+}
+
+/// Parse `sha256sum` output: `<digest>  <path>` (or `<digest> *<path>`) per line.
+pub fn parse_manifest(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut digests = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line
+            .split_once(' ')
+            .map(|(digest, file)| (digest, file.trim_start().trim_start_matches('*')))
+            .filter(|(digest, file)| verify::is_sha256(digest) && !file.is_empty());
+        match parsed {
+            Some((digest, file)) => {
+                digests.insert(file.replace('\\', "/"), digest.to_ascii_lowercase());
+            }
+            None => return Err(format!("{} line {}: expected '<sha256>  <path>'", MANIFEST, number + 1)),
+        }
+    }
+    Ok(digests)
+}
+
+/// Write [`MANIFEST`] listing every file below `dir`.
+pub fn write_manifest(dir: &Path) -> io::Result<()> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path != dir.join(MANIFEST) {
+                files.push(path);
+            }
+        }
+    }
+
+    let mut manifest = Vec::new();
+    for path in files {
+        let relative = path
+            .strip_prefix(dir)
+            .map_err(io::Error::other)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        manifest.push(format!("{}  {}\n", verify::sha256_file(&path)?, relative));
+    }
+    manifest.sort_by(|a, b| a[66..].cmp(&b[66..]));
+    fs::write(dir.join(MANIFEST), manifest.concat())
+}
+
+/// Package name from an archive file name:
+/// `mingw-w64-x86_64-gcc-13.2.0-6-any.pkg.tar.zst` → `mingw-w64-x86_64-gcc`.
+pub fn package_name(file: &str) -> Option<&str> {
+    if file.ends_with(".sig") {
+        return None;
+    }
+    let stem = &file[..file.find(".pkg.tar")?];
+    // Strip `-<arch>`, `-<pkgrel>` and `-<pkgver>`
+    let mut parts = stem.rsplitn(4, '-');
+    let (_arch, _rel, _ver) = (parts.next()?, parts.next()?, parts.next()?);
+    parts.next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{fake_bundle, scratch_dir};
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("mingw-w64-x86_64-gcc-13.2.0-6-any.pkg.tar.zst"), Some("mingw-w64-x86_64-gcc"));
+        assert_eq!(
+            package_name("mingw-w64-x86_64-gcc-libs-13.2.0-6-any.pkg.tar.xz"),
+            Some("mingw-w64-x86_64-gcc-libs")
+        );
+        assert_eq!(package_name("zlib-1:1.3-1-x86_64.pkg.tar.zst"), Some("zlib"));
+        assert_eq!(package_name("mingw-w64-x86_64-gcc-13.2.0-6-any.pkg.tar.zst.sig"), None);
+        assert_eq!(package_name("README.txt"), None);
+    }
+
+    #[test]
+    fn test_open_complete_bundle() {
+        let dir = scratch_dir("bundle-ok");
+        fake_bundle(&dir);
+
+        let bundle = Bundle::open(&dir, &Manifest::default()).unwrap();
+        assert!(bundle.digest(RUSTUP_INIT).is_some());
+        assert!(bundle.sources().rustup_init.starts_with("file://"));
+    }
+
+    #[test]
+    fn test_open_reports_every_problem() {
+        let dir = scratch_dir("bundle-broken");
+        fake_bundle(&dir);
+        fs::remove_file(dir.join(RUSTUP_INIT)).unwrap();
+        fs::write(dir.join(MSYS2_INSTALLER), "tampered").unwrap();
+        let manifest = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        let manifest: String = manifest.lines().filter(|l| !l.contains("-cmake-")).map(|l| format!("{}\n", l)).collect();
+        fs::write(dir.join(MANIFEST), manifest).unwrap();
+
+        let err = Bundle::open(&dir, &Manifest::default()).unwrap_err();
+        assert!(err.contains("no pacman/ archive for mingw-w64-x86_64-cmake"));
+        assert!(err.contains("rustup-init.exe is missing"));
+        assert!(err.contains("Checksum mismatch"));
+    }
+
+    #[test]
+    fn test_write_manifest_round_trips() {
+        let dir = scratch_dir("bundle-rewrite");
+        fake_bundle(&dir);
+        let original = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        fs::remove_file(dir.join(MANIFEST)).unwrap();
+
+        write_manifest(&dir).unwrap();
+
+        let rewritten = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        assert_eq!(parse_manifest(&rewritten).unwrap(), parse_manifest(&original).unwrap());
+        Bundle::open(&dir, &Manifest::default()).unwrap();
+    }
+
+    #[test]
+    fn test_open_without_manifest() {
+        let dir = scratch_dir("bundle-empty");
+        assert!(Bundle::open(&dir, &Manifest::default()).unwrap_err().contains("manifest"));
+    }
+}
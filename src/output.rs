@@ -0,0 +1,45 @@
+//! Console output for the install steps.
+//!
+//! Step narration goes through [`say!`] so it can be silenced when the
+//! program is producing something else on stdout, such as a dry-run plan,
+//! or when `--quiet` is given. [`detail!`] is for what only `--verbose`
+//! shows, such as every command run.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SILENT: AtomicBool = AtomicBool::new(false);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_silent(silent: bool) {
+    SILENT.store(silent, Ordering::Relaxed);
+}
+
+pub fn is_silent() -> bool {
+    SILENT.load(Ordering::Relaxed)
+}
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// `println!` that respects [`set_silent`].
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_silent() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// [`say!`] that only prints with `--verbose`.
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if $crate::output::is_verbose() {
+            say!($($arg)*);
+        }
+    };
+}
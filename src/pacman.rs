@@ -0,0 +1,434 @@
+//! Reading pacman's output.
+//!
+//! pacman reports what it did line by line: `installing <name>...`,
+//! `upgrading <name>...`, `warning: <name>-<ver> is up to date -- skipping`,
+//! and `error: ...` lines whose wording tells the failures apart. Its exit
+//! code alone only says that something went wrong.
+//!
+//! On MSYS2, `pacman -Syu` first upgrades the core system (the runtime, bash
+//! and pacman itself) on its own and then closes every MSYS2 process, so the
+//! rest of the upgrade needs another run in a fresh shell.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::runner::CommandOutput;
+
+/// Why a pacman transaction failed, with the packages to blame where pacman
+/// names them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// Files or packages conflict with ones already installed.
+    Conflict(Vec<String>),
+    /// A requested package or a dependency does not exist in any repository.
+    MissingTarget(Vec<String>),
+    /// A signature could not be checked: unknown key, bad trust or a corrupt
+    /// package or database.
+    Keyring(Vec<String>),
+    /// Another pacman holds the database lock.
+    DatabaseLocked,
+    DiskFull,
+    /// Any other `error:` line.
+    Other(String),
+}
+
+impl Failure {
+    pub fn packages(&self) -> &[String] {
+        match self {
+            Failure::Conflict(packages) | Failure::MissingTarget(packages) | Failure::Keyring(packages) => packages,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let with = |packages: &[String]| {
+            if packages.is_empty() { String::new() } else { format!(": {}", packages.join(", ")) }
+        };
+        match self {
+            Failure::Conflict(packages) => write!(f, "conflicting packages or files{}", with(packages)),
+            Failure::MissingTarget(packages) => write!(f, "target not found{}", with(packages)),
+            Failure::Keyring(packages) => write!(
+                f,
+                "signature check failed{} (try `pacman-key --init && pacman-key --populate msys2`)",
+                with(packages)
+            ),
+            Failure::DatabaseLocked => write!(
+                f,
+                "the package database is locked by another pacman; if none is running, remove /var/lib/pacman/db.lck"
+            ),
+            Failure::DiskFull => write!(f, "not enough free disk space"),
+            Failure::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+/// What one pacman run did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    pub installed: Vec<String>,
+    pub upgraded: Vec<String>,
+    /// Packages pacman skipped or reinstalled because they were current.
+    pub up_to_date: Vec<String>,
+    /// `there is nothing to do`.
+    pub nothing_to_do: bool,
+    /// A `-Syu` upgraded MSYS2's core packages and must be run again after
+    /// the MSYS2 processes are restarted.
+    pub core_upgrade: bool,
+    pub failure: Option<Failure>,
+    /// `warning:` lines other than up-to-date notices.
+    pub warnings: Vec<String>,
+    /// Every `error:` line, without the prefix.
+    pub errors: Vec<String>,
+}
+
+impl Transaction {
+    /// Classify the output of a pacman run. A failing exit code without a
+    /// recognizable error line still counts as a failure, unless pacman said
+    /// there was nothing to do.
+    pub fn parse(output: &CommandOutput) -> Transaction {
+        let mut transaction = Transaction::default();
+        let mut found = Found::default();
+        let mut in_core_upgrade = false;
+
+        for line in output.stdout.lines().chain(output.stderr.lines()) {
+            let line = line.trim();
+            let lower = line.to_lowercase();
+            if lower.contains("starting core system upgrade") {
+                in_core_upgrade = true;
+            } else if lower.contains("starting full system upgrade") {
+                in_core_upgrade = false;
+            } else if lower.contains("all msys2 processes including this terminal will be closed") {
+                transaction.core_upgrade = true;
+            }
+
+            if let Some(name) = action(line, "installing ") {
+                push(&mut transaction.installed, name);
+                transaction.core_upgrade |= in_core_upgrade;
+            } else if let Some(name) = action(line, "upgrading ") {
+                push(&mut transaction.upgraded, name);
+                transaction.core_upgrade |= in_core_upgrade;
+            } else if let Some(rest) = line.strip_prefix("warning: ") {
+                match rest.split_once(" is up to date -- ") {
+                    Some((package, _)) => push(&mut transaction.up_to_date, name_of(package)),
+                    None => transaction.warnings.push(rest.to_string()),
+                }
+            } else if lower.contains("there is nothing to do") {
+                transaction.nothing_to_do = true;
+            } else if let Some(rest) = line.strip_prefix("error: ") {
+                transaction.errors.push(rest.to_string());
+            }
+
+            if lower.contains("unable to lock database") || lower.contains("could not lock database") {
+                found.locked = true;
+            } else if lower.contains("not enough free disk space")
+                || lower.contains("no space left on device")
+                || (lower.starts_with("error: partition") && lower.contains("too full"))
+            {
+                found.disk_full = true;
+            } else if let Some(rest) = line.strip_prefix("error: target not found: ") {
+                push(&mut found.missing, rest);
+            } else if let Some(dependency) = quoted_after(line, "unable to satisfy dependency ") {
+                push(&mut found.missing, dependency);
+            } else if lower.contains("could not satisfy dependencies") {
+                found.unsatisfied = true;
+            } else if let Some(package) = keyring_package(line) {
+                found.keyring = true;
+                if !package.is_empty() {
+                    push(&mut found.keyring_packages, package);
+                }
+            } else if lower.contains("pgp signature")
+                || lower.contains("required key missing from keyring")
+                || lower.contains("could not be looked up remotely")
+                || lower.contains("unknown trust")
+            {
+                found.keyring = true;
+            } else if let Some(package) = line.strip_suffix(" exists in filesystem").and_then(|rest| rest.split_once(": ")) {
+                found.conflict = true;
+                push(&mut found.conflicts, package.0);
+            } else if let Some(packages) = in_conflict(line) {
+                found.conflict = true;
+                for package in packages {
+                    push(&mut found.conflicts, package);
+                }
+            } else if let Some(package) = line.strip_prefix(":: installing ").and_then(|rest| rest.split(' ').next())
+                && lower.contains("breaks dependency")
+            {
+                found.conflict = true;
+                push(&mut found.conflicts, package);
+            } else if lower.contains("conflicting files") || lower.contains("unresolvable package conflicts") {
+                found.conflict = true;
+            } else if found.other.is_none() && line.starts_with("error: ") {
+                found.other = Some(line.trim_start_matches("error: ").to_string());
+            }
+        }
+
+        transaction.failure = found.failure(output.success() || transaction.nothing_to_do);
+        transaction
+    }
+}
+
+/// The members of each group in `pacman -Sg <names>` output, which has one
+/// `<group> <package>` line per member; names that are not groups are absent.
+pub fn groups(output: &str) -> BTreeMap<String, Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (group, package) in output.lines().filter_map(|line| line.trim().split_once(' ')) {
+        groups.entry(group.to_string()).or_default().push(package.trim().to_string());
+    }
+    groups
+}
+
+/// The packages in `wanted` that still need installing: those `pacman -Qq`
+/// did not list, where a group counts as installed once all its members are.
+pub fn missing(wanted: &[String], installed: &BTreeSet<String>, groups: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    wanted
+        .iter()
+        .filter(|name| match groups.get(*name) {
+            Some(members) => !members.iter().all(|member| installed.contains(member)),
+            None => !installed.contains(*name),
+        })
+        .cloned()
+        .collect()
+}
+
+/// Failure evidence gathered while reading the lines.
+#[derive(Default)]
+struct Found {
+    locked: bool,
+    disk_full: bool,
+    keyring: bool,
+    keyring_packages: Vec<String>,
+    conflict: bool,
+    conflicts: Vec<String>,
+    unsatisfied: bool,
+    missing: Vec<String>,
+    other: Option<String>,
+}
+
+impl Found {
+    /// The most specific failure: a lock or a full disk explains any other
+    /// error that follows from it.
+    fn failure(self, success: bool) -> Option<Failure> {
+        if self.locked {
+            Some(Failure::DatabaseLocked)
+        } else if self.disk_full {
+            Some(Failure::DiskFull)
+        } else if self.keyring {
+            Some(Failure::Keyring(self.keyring_packages))
+        } else if self.conflict {
+            Some(Failure::Conflict(self.conflicts))
+        } else if !self.missing.is_empty() || self.unsatisfied {
+            Some(Failure::MissingTarget(self.missing))
+        } else if let Some(message) = self.other {
+            Some(Failure::Other(message))
+        } else if !success {
+            Some(Failure::Other("pacman failed without an error message".to_string()))
+        } else {
+            None
+        }
+    }
+}
+
+fn push(list: &mut Vec<String>, name: &str) {
+    if !list.iter().any(|existing| existing == name) {
+        list.push(name.to_string());
+    }
+}
+
+/// `<name>` from `installing <name>...` or `(1/3) installing <name>  [###] 100%`.
+fn action<'a>(line: &'a str, verb: &str) -> Option<&'a str> {
+    let line = match line.strip_prefix('(') {
+        Some(rest) => rest.split_once(") ")?.1,
+        None => line,
+    };
+    let name = line.strip_prefix(verb)?.split_whitespace().next()?.trim_end_matches("...");
+    (!name.is_empty()).then_some(name)
+}
+
+/// `mingw-w64-x86_64-gcc` from `mingw-w64-x86_64-gcc-13.2.0-6`, dropping the
+/// version and release pacman appends.
+fn name_of(package: &str) -> &str {
+    let mut parts = package.rsplitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(_release), Some(_version), Some(name)) => name,
+        _ => package,
+    }
+}
+
+/// `libfoo` from `... unable to satisfy dependency 'libfoo>=2' required by bar`.
+fn quoted_after<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = &line[line.find(marker)? + marker.len()..];
+    let quoted = rest.strip_prefix('\'')?.split('\'').next()?;
+    Some(quoted.split(['<', '>', '=']).next().unwrap_or(quoted))
+}
+
+/// The package in `error: <name>: signature from "..." is ...` or
+/// `:: File /var/cache/pacman/pkg/<name>-<ver>-<rel>-<arch>.pkg.tar.zst is corrupted (...)`;
+/// an empty name when the line is about the keyring but names no package.
+fn keyring_package(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix("error: ")
+        && let Some((package, _)) = rest.split_once(": signature from ")
+    {
+        return Some(package);
+    }
+    let file = line.strip_prefix(":: File ")?.split(' ').next()?;
+    if !line.contains("is corrupted") {
+        return None;
+    }
+    let file_name = file.rsplit(['/', '\\']).next()?;
+    let stem = file_name.split(".pkg.tar").next()?;
+    // <name>-<ver>-<rel>-<arch>
+    Some(stem.rsplit_once('-').map(|(rest, _arch)| name_of(rest)).unwrap_or(""))
+}
+
+/// Both names in `:: <a> and <b> are in conflict`, with any `(<provides>)`
+/// suffix removed.
+fn in_conflict(line: &str) -> Option<[&str; 2]> {
+    let rest = line.strip_prefix(":: ")?;
+    let (pair, _) = rest.split_once(" are in conflict")?;
+    let (a, b) = pair.split_once(" and ")?;
+    Some([a.split(' ').next()?, b.split(' ').next()?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(code: i32, fixture: &str) -> Transaction {
+        Transaction::parse(&CommandOutput {
+            code: Some(code),
+            stdout: fixture.to_string(),
+            stderr: String::new(),
+        })
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_install() {
+        let transaction = parse(0, include_str!("../testdata/pacman/install.txt"));
+        assert!(transaction.failure.is_none());
+        assert_eq!(
+            transaction.installed,
+            names(&["mingw-w64-x86_64-gmp", "mingw-w64-x86_64-isl", "mingw-w64-x86_64-gcc"])
+        );
+        assert!(transaction.upgraded.is_empty() && transaction.up_to_date.is_empty());
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let transaction = parse(0, include_str!("../testdata/pacman/upgrade.txt"));
+        assert!(transaction.failure.is_none());
+        assert_eq!(transaction.upgraded, names(&["mingw-w64-x86_64-cmake", "mingw-w64-x86_64-openssl"]));
+        assert_eq!(transaction.installed, names(&["mingw-w64-x86_64-libuv"]));
+    }
+
+    #[test]
+    fn test_up_to_date() {
+        let transaction = parse(0, include_str!("../testdata/pacman/up_to_date.txt"));
+        assert!(transaction.failure.is_none());
+        assert!(transaction.nothing_to_do);
+        assert_eq!(transaction.up_to_date, names(&["mingw-w64-x86_64-make", "mingw-w64-x86_64-pkgconf"]));
+        assert!(transaction.warnings.is_empty());
+
+        // Older pacman exits with 1 when there is nothing to do
+        assert!(parse(1, include_str!("../testdata/pacman/up_to_date.txt")).failure.is_none());
+    }
+
+    #[test]
+    fn test_system_upgrade() {
+        // pacman closes the shell running it, so the exit code is not reliable
+        let transaction = parse(1, include_str!("../testdata/pacman/core_upgrade.txt"));
+        assert!(transaction.core_upgrade);
+        assert!(transaction.upgraded.contains(&"msys2-runtime".to_string()));
+
+        let transaction = parse(0, include_str!("../testdata/pacman/full_upgrade.txt"));
+        assert!(!transaction.core_upgrade && transaction.failure.is_none());
+        assert_eq!(transaction.upgraded, names(&["libgcrypt", "gnupg"]));
+
+        let transaction = parse(0, include_str!("../testdata/pacman/system_up_to_date.txt"));
+        assert!(!transaction.core_upgrade && transaction.nothing_to_do);
+    }
+
+    #[test]
+    fn test_conflicting_files() {
+        let transaction = parse(1, include_str!("../testdata/pacman/conflicting_files.txt"));
+        assert_eq!(transaction.failure, Some(Failure::Conflict(names(&["mingw-w64-x86_64-pkgconf"]))));
+    }
+
+    #[test]
+    fn test_conflicting_packages() {
+        let transaction = parse(1, include_str!("../testdata/pacman/package_conflict.txt"));
+        assert_eq!(
+            transaction.failure,
+            Some(Failure::Conflict(names(&["mingw-w64-x86_64-pkgconf", "mingw-w64-x86_64-pkg-config"])))
+        );
+    }
+
+    #[test]
+    fn test_missing_target() {
+        let transaction = parse(1, include_str!("../testdata/pacman/target_not_found.txt"));
+        assert_eq!(
+            transaction.failure,
+            Some(Failure::MissingTarget(names(&["mingw-w64-x86_64-gdbb", "mingw-w64-x86_64-opensll"])))
+        );
+
+        let transaction = parse(1, include_str!("../testdata/pacman/unsatisfied_dependency.txt"));
+        assert_eq!(transaction.failure, Some(Failure::MissingTarget(names(&["mingw-w64-x86_64-libtre"]))));
+    }
+
+    #[test]
+    fn test_keyring() {
+        let transaction = parse(1, include_str!("../testdata/pacman/unknown_trust.txt"));
+        assert_eq!(transaction.failure, Some(Failure::Keyring(names(&["mingw-w64-x86_64-gcc-libs"]))));
+
+        let transaction = parse(1, include_str!("../testdata/pacman/corrupted_package.txt"));
+        assert_eq!(transaction.failure, Some(Failure::Keyring(names(&["mingw-w64-x86_64-binutils"]))));
+        assert!(transaction.failure.unwrap().to_string().contains("pacman-key --populate msys2"));
+    }
+
+    #[test]
+    fn test_database_lock() {
+        let transaction = parse(1, include_str!("../testdata/pacman/db_lock.txt"));
+        assert_eq!(transaction.failure, Some(Failure::DatabaseLocked));
+    }
+
+    #[test]
+    fn test_disk_full() {
+        let transaction = parse(1, include_str!("../testdata/pacman/disk_full.txt"));
+        assert_eq!(transaction.failure, Some(Failure::DiskFull));
+    }
+
+    #[test]
+    fn test_missing() {
+        let groups = groups(
+            "mingw-w64-x86_64-toolchain mingw-w64-x86_64-binutils\n\
+             mingw-w64-x86_64-toolchain mingw-w64-x86_64-gcc\n",
+        );
+        assert_eq!(groups["mingw-w64-x86_64-toolchain"], names(&["mingw-w64-x86_64-binutils", "mingw-w64-x86_64-gcc"]));
+
+        let wanted = names(&["mingw-w64-x86_64-toolchain", "mingw-w64-x86_64-cmake", "mingw-w64-x86_64-make"]);
+        let mut installed: BTreeSet<String> = names(&["mingw-w64-x86_64-gcc", "mingw-w64-x86_64-make"]).into_iter().collect();
+        assert_eq!(
+            missing(&wanted, &installed, &groups),
+            names(&["mingw-w64-x86_64-toolchain", "mingw-w64-x86_64-cmake"])
+        );
+
+        installed.extend(names(&["mingw-w64-x86_64-binutils", "mingw-w64-x86_64-cmake"]));
+        assert!(missing(&wanted, &installed, &groups).is_empty());
+    }
+
+    #[test]
+    fn test_unrecognized_failure() {
+        let transaction = parse(1, "");
+        assert!(matches!(transaction.failure, Some(Failure::Other(_))));
+        let transaction = parse(1, "error: failed retrieving file 'x.db' from repo.msys2.org : Could not resolve host\n");
+        assert_eq!(
+            transaction.failure,
+            Some(Failure::Other("failed retrieving file 'x.db' from repo.msys2.org : Could not resolve host".to_string()))
+        );
+    }
+}
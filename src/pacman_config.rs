@@ -0,0 +1,265 @@
+//! pacman's configuration inside the MSYS2 root: the server lists in
+//! `etc/pacman.d/mirrorlist.*` and extra repositories in `etc/pacman.conf`.
+//!
+//! Every rewrite is idempotent, so rerunning the installer with the same
+//! manifest leaves the files as they are.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::download::Downloader;
+use crate::manifest::Repo;
+
+/// Marks what the installer added, so a rerun can find and replace it.
+const MARKER: &str = "# Added by rs-easy-installer-windows";
+
+/// How long a mirror gets to answer before it counts as down.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn conf_path(msys2_root: &Path) -> PathBuf {
+    msys2_root.join("etc").join("pacman.conf")
+}
+
+/// The `mirrorlist.<repo>` files, without pacman's `.pacnew` copies or
+/// backups; empty when MSYS2 is not installed yet.
+pub fn mirrorlists(msys2_root: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = msys2_root.join("etc").join("pacman.d");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut lists = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.strip_prefix("mirrorlist.").is_some_and(|repo| !repo.is_empty() && !repo.contains('.')) {
+            lists.push(path);
+        }
+    }
+    lists.sort();
+    Ok(lists)
+}
+
+/// URL of an active `Server = <url>` line.
+fn server(line: &str) -> Option<&str> {
+    let (key, value) = line.split_once('=')?;
+    (key.trim() == "Server").then(|| value.trim())
+}
+
+/// The servers a mirrorlist uses, in order.
+pub fn servers(mirrorlist: &str) -> Vec<&str> {
+    mirrorlist.lines().filter_map(server).collect()
+}
+
+/// `mirrorlist` pointed at `mirror` alone. The repository path of its first
+/// server (`msys/$arch/`, `mingw/$repo/`, ...) is kept; the servers it had
+/// are commented out. `None` if it has no server to take that path from.
+pub fn use_mirror(mirrorlist: &str, mirror: &str) -> Option<String> {
+    let ours = |line: &str| line == MARKER || line.starts_with(&format!("Server = {}/", mirror.trim_end_matches('/')));
+    let first = mirrorlist.lines().filter(|line| !ours(line)).find_map(server);
+    // Our own line stands in if an earlier run already commented the rest out
+    let first = first.or_else(|| mirrorlist.lines().find_map(server))?;
+    let path = ["/msys/", "/mingw/"]
+        .iter()
+        .filter_map(|segment| first.rfind(segment))
+        .max()
+        .map(|start| &first[start + 1..])?;
+
+    let mut rewritten = format!("{}\nServer = {}/{}\n", MARKER, mirror.trim_end_matches('/'), path);
+    for line in mirrorlist.lines().filter(|line| !ours(line)) {
+        if server(line).is_some() {
+            rewritten.push('#');
+        }
+        rewritten.push_str(line);
+        rewritten.push('\n');
+    }
+    Some(rewritten)
+}
+
+/// `mirrorlist` with its servers reordered fastest first. Servers without a
+/// `latency` (unreachable) keep their order after the reachable ones.
+pub fn rank(mirrorlist: &str, latency: &dyn Fn(&str) -> Option<Duration>) -> String {
+    let lines: Vec<&str> = mirrorlist.lines().collect();
+    let slots: Vec<usize> = (0..lines.len()).filter(|&i| server(lines[i]).is_some()).collect();
+    let mut ranked: Vec<&str> = slots.iter().map(|&i| lines[i]).collect();
+    ranked.sort_by_key(|line| latency(server(line).unwrap_or_default()).unwrap_or(Duration::MAX));
+
+    let mut lines = lines;
+    for (slot, line) in slots.into_iter().zip(ranked) {
+        lines[slot] = line;
+    }
+    let mut text = lines.join("\n");
+    if mirrorlist.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+/// The URL to time for a server: the part before its first `$repo` or
+/// `$arch`, which pacman fills in.
+pub fn probe_url(server: &str) -> &str {
+    &server[..server.find('$').unwrap_or(server.len())]
+}
+
+/// How long each of `urls` takes to answer a HEAD request through
+/// `downloader` (and so through the proxy), measured in parallel; `None`
+/// for those that did not answer within a few seconds.
+pub fn measure(downloader: &Downloader, urls: &[String]) -> BTreeMap<String, Option<Duration>> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = urls
+            .iter()
+            .map(|url| scope.spawn(move || downloader.latency(url, PROBE_TIMEOUT)))
+            .collect();
+        urls.iter()
+            .cloned()
+            .zip(handles.into_iter().map(|handle| handle.join().ok().flatten()))
+            .collect()
+    })
+}
+
+/// `conf` with a section for `repo` at the end, replacing an earlier section
+/// of the same name.
+pub fn with_repo(conf: &str, repo: &Repo) -> String {
+    let header = format!("[{}]", repo.name);
+    let mut text = String::new();
+    let mut skipping = false;
+    for line in conf.lines() {
+        if line.trim_start().starts_with('[') {
+            skipping = line.trim() == header;
+        }
+        if !skipping {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    while text.ends_with("\n\n") {
+        text.pop();
+    }
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    text.push_str(&header);
+    text.push('\n');
+    text.push_str(MARKER);
+    text.push('\n');
+    if let Some(sig_level) = &repo.sig_level {
+        text.push_str(&format!("SigLevel = {}\n", sig_level));
+    }
+    text.push_str(&format!("Server = {}\n", repo.server));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{Faults, TestServer, scratch_dir};
+
+    const MINGW: &str = "\
+##
+## MINGW64 repository mirrorlist
+##
+
+## Primary
+Server = https://mirror.msys2.org/mingw/$repo/
+Server = https://repo.msys2.org/mingw/$repo/
+## Tier 1
+Server = https://mirrors.tuna.tsinghua.edu.cn/msys2/mingw/$repo/
+";
+
+    #[test]
+    fn test_use_mirror() {
+        let rewritten = use_mirror(MINGW, "https://pkgs.corp.example/msys2/").unwrap();
+        assert!(rewritten.starts_with(&format!("{}\nServer = https://pkgs.corp.example/msys2/mingw/$repo/\n", MARKER)));
+        assert_eq!(servers(&rewritten), ["https://pkgs.corp.example/msys2/mingw/$repo/"]);
+        assert!(rewritten.contains("#Server = https://mirrors.tuna.tsinghua.edu.cn/msys2/mingw/$repo/\n"));
+
+        // Rerunning changes nothing; another mirror replaces ours
+        assert_eq!(use_mirror(&rewritten, "https://pkgs.corp.example/msys2").unwrap(), rewritten);
+        let moved = use_mirror(&rewritten, "http://10.0.0.5:8080").unwrap();
+        assert_eq!(servers(&moved), ["http://10.0.0.5:8080/mingw/$repo/"]);
+
+        let msys = use_mirror("Server = https://mirror.msys2.org/msys/$arch/\n", "https://pkgs.corp.example/msys2").unwrap();
+        assert_eq!(servers(&msys), ["https://pkgs.corp.example/msys2/msys/$arch/"]);
+        assert_eq!(use_mirror("## no servers\n", "https://pkgs.corp.example"), None);
+    }
+
+    #[test]
+    fn test_rank() {
+        let latency = |url: &str| match probe_url(url) {
+            "https://mirror.msys2.org/mingw/" => Some(Duration::from_millis(120)),
+            "https://mirrors.tuna.tsinghua.edu.cn/msys2/mingw/" => Some(Duration::from_millis(15)),
+            _ => None,
+        };
+        let ranked = rank(MINGW, &latency);
+        assert_eq!(
+            servers(&ranked),
+            [
+                "https://mirrors.tuna.tsinghua.edu.cn/msys2/mingw/$repo/",
+                "https://mirror.msys2.org/mingw/$repo/",
+                "https://repo.msys2.org/mingw/$repo/",
+            ]
+        );
+        // Comments stay where they were
+        assert_eq!(ranked.lines().nth(4), Some("## Primary"));
+        assert_eq!(rank(&ranked, &latency), ranked);
+    }
+
+    #[test]
+    fn test_measure_goes_through_the_proxy() {
+        // The proxy answers for a mirror that cannot be resolved directly
+        let proxy = TestServer::start(&[], Faults::default());
+        let downloader = Downloader::new(Some(ureq::Proxy::new(&proxy.base_url).unwrap()));
+        let urls = [
+            probe_url("http://mirror.invalid/mingw/$repo/").to_string(),
+            probe_url("file:///c/repo/$arch").to_string(),
+        ];
+
+        let latencies = measure(&downloader, &urls);
+
+        assert!(latencies["http://mirror.invalid/mingw/"].is_some(), "{:?}", latencies);
+        assert_eq!(latencies["file:///c/repo/"], None);
+        assert_eq!(proxy.requests(), ["CONNECT mirror.invalid:80", "HEAD /mingw/"]);
+    }
+
+    #[test]
+    fn test_with_repo() {
+        let conf = "[options]\nArchitecture = auto\n\n[msys]\nInclude = /etc/pacman.d/mirrorlist.msys\n";
+        let repo = Repo {
+            name: "corp".to_string(),
+            server: "https://pkgs.corp.example/repo/$arch".to_string(),
+            sig_level: Some("Optional TrustAll".to_string()),
+        };
+        let added = with_repo(conf, &repo);
+        assert_eq!(
+            added,
+            format!(
+                "{}\n[corp]\n{}\nSigLevel = Optional TrustAll\nServer = https://pkgs.corp.example/repo/$arch\n",
+                conf, MARKER
+            )
+        );
+        assert_eq!(with_repo(&added, &repo), added);
+
+        let moved = Repo { server: "https://other.example/$arch".to_string(), sig_level: None, ..repo };
+        let replaced = with_repo(&added, &moved);
+        assert!(replaced.ends_with("[corp]\n# Added by rs-easy-installer-windows\nServer = https://other.example/$arch\n"));
+        assert_eq!(replaced.matches("[corp]").count(), 1);
+    }
+
+    #[test]
+    fn test_mirrorlists() {
+        let root = scratch_dir("mirrorlists");
+        assert!(mirrorlists(&root).unwrap().is_empty());
+        let dir = root.join("etc").join("pacman.d");
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["mirrorlist.msys", "mirrorlist.mingw", "mirrorlist.mingw.pacnew", "mirrorlist.msys.bak", "gnupg"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(mirrorlists(&root).unwrap(), [dir.join("mirrorlist.mingw"), dir.join("mirrorlist.msys")]);
+    }
+}
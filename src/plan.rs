@@ -0,0 +1,133 @@
+//! Dry-run support: record what an installation would do instead of doing it.
+//!
+//! The install steps run unchanged against a [`DryRunRunner`], which lets
+//! read-only probes (`--version`, `target list`) through to the real system so
+//! the same decisions are taken, and records every other command in a
+//! [`Plan`] together with the reason the step gave for running it.
+
+use std::cell::RefCell;
+use std::io;
+
+use serde::Serialize;
+
+use crate::runner::{CommandOutput, CommandRunner, command_line};
+
+/// One entry of the installation plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedAction {
+    pub skipped: bool,
+    pub what: String,
+    pub why: String,
+}
+
+/// Ordered list of actions an installation would perform.
+#[derive(Default)]
+pub struct Plan {
+    actions: RefCell<Vec<PlannedAction>>,
+    reason: RefCell<String>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the reason attached to the actions recorded after this call.
+    pub fn because(&self, why: &str) {
+        *self.reason.borrow_mut() = why.to_string();
+    }
+
+    /// Record an action that would be performed.
+    pub fn record(&self, what: &str) {
+        self.actions.borrow_mut().push(PlannedAction {
+            skipped: false,
+            what: what.to_string(),
+            why: self.reason.borrow().clone(),
+        });
+    }
+
+    /// Record an action that would be skipped, and why.
+    pub fn skip(&self, what: &str, why: &str) {
+        self.actions.borrow_mut().push(PlannedAction {
+            skipped: true,
+            what: what.to_string(),
+            why: why.to_string(),
+        });
+    }
+
+    pub fn actions(&self) -> Vec<PlannedAction> {
+        self.actions.borrow().clone()
+    }
+
+    pub fn print(&self) {
+        println!("📋 Installation plan");
+        println!("--------------------");
+
+        let actions = self.actions();
+        if actions.is_empty() {
+            println!("Nothing to do.");
+        }
+        for (index, action) in actions.iter().enumerate() {
+            let marker = if action.skipped { "⏭️  skip" } else { "▶️  run " };
+            println!("{:>3}. {} {}", index + 1, marker, action.what);
+            if !action.why.is_empty() {
+                println!("          why: {}", action.why);
+            }
+        }
+        println!();
+    }
+}
+
+/// Runner that executes read-only probes and records everything else.
+pub struct DryRunRunner<'a> {
+    pub inner: &'a dyn CommandRunner,
+    pub plan: &'a Plan,
+}
+
+/// Whether a command only inspects the system.
+fn is_probe(args: &[&str]) -> bool {
+    args.iter()
+        .any(|arg| *arg == "--version" || arg.ends_with(" --version"))
+        || args.starts_with(&["target", "list"])
+}
+
+impl CommandRunner for DryRunRunner<'_> {
+    fn run_with_env(&self, program: &str, args: &[&str], env: &[(&str, &str)]) -> io::Result<CommandOutput> {
+        if is_probe(args) {
+            return self.inner.run_with_env(program, args, env);
+        }
+
+        self.plan.record(&command_line(program, args, env));
+        Ok(CommandOutput::ok(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::FakeRunner;
+
+    #[test]
+    fn test_probes_run_and_actions_are_recorded() {
+        let inner = FakeRunner::new().on("rustup --version", CommandOutput::ok("rustup 1.27.1"));
+        let plan = Plan::new();
+        let runner = DryRunRunner { inner: &inner, plan: &plan };
+
+        let version = runner.run("rustup", &["--version"]).unwrap();
+        assert_eq!(version.stdout, "rustup 1.27.1");
+
+        plan.because("rustup is installed");
+        runner.run("rustup", &["target", "add", "x86_64-pc-windows-gnu"]).unwrap();
+        runner.run("bash.exe", &["-l", "-c", "gcc --version"]).unwrap();
+
+        assert_eq!(inner.calls(), vec!["rustup --version", "bash.exe -l -c gcc --version"]);
+        assert_eq!(
+            plan.actions(),
+            vec![PlannedAction {
+                skipped: false,
+                what: "rustup target add x86_64-pc-windows-gnu".to_string(),
+                why: "rustup is installed".to_string(),
+            }]
+        );
+    }
+}
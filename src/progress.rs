@@ -0,0 +1,183 @@
+//! Installation steps and the record of which ones have finished, so a rerun
+//! after a failure resumes at the first step that did not complete.
+//!
+//! The state file lives next to the install journal and is removed once every
+//! step has completed. It carries a fingerprint of the settings it was made
+//! with; a rerun with a different manifest or MSYS2 root starts over.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// One step of `run_installation_process`, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    Check,
+    Msys2,
+    Toolchain,
+    Rust,
+    Configure,
+    Verify,
+}
+
+impl Step {
+    pub const ALL: [Step; 6] = [
+        Step::Check,
+        Step::Msys2,
+        Step::Toolchain,
+        Step::Rust,
+        Step::Configure,
+        Step::Verify,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Step::Check => "check",
+            Step::Msys2 => "msys2",
+            Step::Toolchain => "toolchain",
+            Step::Rust => "rust",
+            Step::Configure => "configure",
+            Step::Verify => "verify",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Step> {
+        Step::ALL.into_iter().find(|step| step.name() == name)
+    }
+
+    /// `check, msys2, ...` for error messages and help.
+    pub fn names() -> String {
+        Step::ALL.map(Step::name).join(", ")
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Which steps a run goes through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Selection {
+    /// Every step the previous run did not complete.
+    #[default]
+    Resume,
+    /// This step and all after it (`--from-step`).
+    From(Step),
+    /// Just this step (`--only-step`).
+    Only(Step),
+}
+
+impl Selection {
+    /// The steps to run, given those an earlier run completed.
+    pub fn steps(self, completed: &[Step]) -> Vec<Step> {
+        match self {
+            Selection::Resume => Step::ALL.into_iter().filter(|step| !completed.contains(step)).collect(),
+            Selection::From(first) => Step::ALL.into_iter().skip_while(|step| *step != first).collect(),
+            Selection::Only(step) => vec![step],
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ProgressFile {
+    fingerprint: String,
+    #[serde(default)]
+    completed: Vec<Step>,
+}
+
+/// The step state file.
+pub struct Progress {
+    pub path: PathBuf,
+}
+
+impl Progress {
+    pub fn at(path: PathBuf) -> Progress {
+        Progress { path }
+    }
+
+    /// Steps completed by earlier runs with the same `fingerprint`; empty if
+    /// there is no state or it was made with other settings.
+    pub fn completed(&self, fingerprint: &str) -> Result<Vec<Step>, String> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Cannot read step state {}: {}", self.path.display(), e)),
+        };
+        let file: ProgressFile = toml::from_str(&text)
+            .map_err(|e| format!("Cannot read step state {}: {}", self.path.display(), e))?;
+        Ok(if file.fingerprint == fingerprint { file.completed } else { Vec::new() })
+    }
+
+    /// Record `step` as completed, on top of what `completed` already lists.
+    pub fn complete(&self, fingerprint: &str, completed: &[Step], step: Step) -> io::Result<()> {
+        let mut file = ProgressFile {
+            fingerprint: fingerprint.to_string(),
+            completed: completed.to_vec(),
+        };
+        if !file.completed.contains(&step) {
+            file.completed.push(step);
+        }
+        // Keep the file in step order however the steps were run
+        file.completed.sort_by_key(|step| Step::ALL.iter().position(|s| s == step));
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, toml::to_string(&file).map_err(io::Error::other)?)
+    }
+
+    /// Forget all progress, once the installation has finished.
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The state file next to the journal at `journal`.
+pub fn path_beside(journal: &Path) -> PathBuf {
+    journal.with_file_name("steps.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::scratch_dir;
+
+    #[test]
+    fn test_selection() {
+        let completed = [Step::Check, Step::Msys2];
+        assert_eq!(
+            Selection::Resume.steps(&completed),
+            [Step::Toolchain, Step::Rust, Step::Configure, Step::Verify]
+        );
+        assert_eq!(Selection::From(Step::Configure).steps(&completed), [Step::Configure, Step::Verify]);
+        assert_eq!(Selection::Only(Step::Msys2).steps(&completed), [Step::Msys2]);
+        assert_eq!(Step::parse("toolchain"), Some(Step::Toolchain));
+        assert_eq!(Step::parse("pacman"), None);
+    }
+
+    #[test]
+    fn test_progress_round_trip() {
+        let progress = Progress::at(scratch_dir("progress").join("state").join("steps.toml"));
+        assert!(progress.completed("abc").unwrap().is_empty());
+
+        progress.complete("abc", &[], Step::Msys2).unwrap();
+        progress.complete("abc", &[Step::Msys2], Step::Check).unwrap();
+        assert_eq!(progress.completed("abc").unwrap(), [Step::Check, Step::Msys2]);
+        assert!(fs::read_to_string(&progress.path).unwrap().contains("completed = [\"check\", \"msys2\"]"));
+
+        // Other settings start over
+        assert!(progress.completed("def").unwrap().is_empty());
+
+        progress.clear().unwrap();
+        assert!(!progress.path.exists());
+        progress.clear().unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! Machine-readable output for `--format json`.
+//!
+//! Every event is printed as one JSON object per line on stdout while the
+//! human narration is silenced: `step-started` and `step-finished` for each
+//! installation step, `check` for each `doctor` result, `planned` for each
+//! dry-run action, and a closing `summary`. With the text format the report
+//! is disabled and every call is a no-op.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::doctor::Check;
+use crate::plan::PlannedAction;
+use crate::progress::Step;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Format> {
+        match value {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Completed,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    StepStarted {
+        step: Step,
+    },
+    StepFinished {
+        step: Step,
+        status: StepStatus,
+        duration_ms: u64,
+        /// Tool versions detected during the step, e.g. `rustc`.
+        versions: BTreeMap<String, String>,
+        warnings: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Check(Check),
+    Planned(PlannedAction),
+    Summary {
+        command: String,
+        dry_run: bool,
+        /// `succeeded` or `failed`.
+        status: String,
+        duration_ms: u64,
+        completed: Vec<Step>,
+        versions: BTreeMap<String, String>,
+        warnings: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    /// The running step and when it started.
+    step: Option<(Step, Instant)>,
+    step_versions: BTreeMap<String, String>,
+    step_warnings: Vec<String>,
+    versions: BTreeMap<String, String>,
+    warnings: Vec<String>,
+    completed: Vec<Step>,
+    events: Vec<Event>,
+}
+
+/// Collects events for `--format json`.
+pub struct Report {
+    enabled: bool,
+    /// Print events as they happen; tests only collect them.
+    print: bool,
+    started: Instant,
+    state: RefCell<State>,
+}
+
+impl Report {
+    pub fn new(format: Format) -> Report {
+        Report {
+            enabled: format == Format::Json,
+            print: true,
+            started: Instant::now(),
+            state: RefCell::default(),
+        }
+    }
+
+    /// An enabled report that keeps its events for inspection instead of
+    /// printing them.
+    #[cfg(test)]
+    pub fn collecting() -> Report {
+        Report {
+            enabled: true,
+            print: false,
+            started: Instant::now(),
+            state: RefCell::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn emit(&self, event: Event) {
+        if !self.enabled {
+            return;
+        }
+        if self.print {
+            println!("{}", serde_json::to_string(&event).expect("events serialize"));
+        }
+        self.state.borrow_mut().events.push(event);
+    }
+
+    #[cfg(test)]
+    pub fn events(&self) -> Vec<Event> {
+        self.state.borrow().events.clone()
+    }
+
+    pub fn step_started(&self, step: Step) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.step = Some((step, Instant::now()));
+            state.step_versions.clear();
+            state.step_warnings.clear();
+        }
+        self.emit(Event::StepStarted { step });
+    }
+
+    /// End the running step with the outcome of its function.
+    pub fn step_finished(&self, step: Step, error: Option<String>) {
+        let event = {
+            let mut state = self.state.borrow_mut();
+            let duration_ms = match state.step.take() {
+                Some((_, started)) => started.elapsed().as_millis() as u64,
+                None => 0,
+            };
+            if error.is_none() {
+                state.completed.push(step);
+            }
+            Event::StepFinished {
+                step,
+                status: if error.is_none() { StepStatus::Completed } else { StepStatus::Failed },
+                duration_ms,
+                versions: std::mem::take(&mut state.step_versions),
+                warnings: std::mem::take(&mut state.step_warnings),
+                reason: None,
+                error,
+            }
+        };
+        self.emit(event);
+    }
+
+    pub fn step_skipped(&self, step: Step, why: &str) {
+        self.emit(Event::StepFinished {
+            step,
+            status: StepStatus::Skipped,
+            duration_ms: 0,
+            versions: BTreeMap::new(),
+            warnings: Vec::new(),
+            reason: Some(why.to_string()),
+            error: None,
+        });
+    }
+
+    pub fn warn(&self, message: &str) {
+        let mut state = self.state.borrow_mut();
+        state.step_warnings.push(message.to_string());
+        state.warnings.push(message.to_string());
+    }
+
+    /// Note the version of a tool that was found, e.g. `("rustc", "1.80.0")`.
+    pub fn version(&self, tool: &str, version: &str) {
+        let mut state = self.state.borrow_mut();
+        state.step_versions.insert(tool.to_string(), version.to_string());
+        state.versions.insert(tool.to_string(), version.to_string());
+    }
+
+    pub fn check(&self, check: &Check) {
+        self.emit(Event::Check(check.clone()));
+    }
+
+    pub fn planned(&self, action: &PlannedAction) {
+        self.emit(Event::Planned(action.clone()));
+    }
+
+    /// The closing event of every run.
+    pub fn summary(&self, command: &str, dry_run: bool, error: Option<String>) {
+        let event = {
+            let state = self.state.borrow();
+            Event::Summary {
+                command: command.to_string(),
+                dry_run,
+                status: if error.is_none() { "succeeded" } else { "failed" }.to_string(),
+                duration_ms: self.started.elapsed().as_millis() as u64,
+                completed: state.completed.clone(),
+                versions: state.versions.clone(),
+                warnings: state.warnings.clone(),
+                error,
+            }
+        };
+        self.emit(event);
+    }
+}
+
+/// `1.80.0` from `rustc 1.80.0 (051478957 2024-07-21)` and the like.
+pub fn version_number(output: &str) -> Option<&str> {
+    output
+        .lines()
+        .next()?
+        .split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_events() {
+        let report = Report::collecting();
+        report.step_started(Step::Check);
+        report.version("rustc", "1.80.0");
+        report.warn("Current installation uses MSVC toolchain.");
+        report.step_finished(Step::Check, None);
+        report.step_skipped(Step::Msys2, "completed by an earlier run");
+        report.step_started(Step::Toolchain);
+        report.step_finished(Step::Toolchain, Some("MSYS2 bash not found".to_string()));
+        report.summary("install", false, Some("MSYS2 bash not found".to_string()));
+
+        let events = report.events();
+        let json: Vec<serde_json::Value> = events.iter().map(|event| serde_json::to_value(event).unwrap()).collect();
+        assert_eq!(json[0], serde_json::json!({"event": "step-started", "step": "check"}));
+        assert_eq!(json[1]["event"], "step-finished");
+        assert_eq!(json[1]["status"], "completed");
+        assert_eq!(json[1]["versions"]["rustc"], "1.80.0");
+        assert_eq!(json[1]["warnings"][0], "Current installation uses MSVC toolchain.");
+        assert!(json[1].get("error").is_none());
+        assert_eq!(json[2]["status"], "skipped");
+        assert_eq!(json[2]["reason"], "completed by an earlier run");
+        assert_eq!(json[4]["status"], "failed");
+        assert!(json[4]["warnings"].as_array().unwrap().is_empty());
+
+        let summary = &json[5];
+        assert_eq!(summary["event"], "summary");
+        assert_eq!(summary["status"], "failed");
+        assert_eq!(summary["completed"], serde_json::json!(["check"]));
+        assert_eq!(summary["error"], "MSYS2 bash not found");
+    }
+
+    #[test]
+    fn test_text_format_is_silent() {
+        let report = Report::new(Format::Text);
+        report.step_started(Step::Check);
+        report.summary("install", false, None);
+        assert!(report.events().is_empty());
+    }
+
+    #[test]
+    fn test_version_number() {
+        assert_eq!(version_number("rustc 1.80.0 (051478957 2024-07-21)\n"), Some("1.80.0"));
+        assert_eq!(version_number("rustup 1.27.1 (54dd3d00f 2024-04-24)\ninfo: ..."), Some("1.27.1"));
+        assert_eq!(version_number(""), None);
+    }
+}
@@ -0,0 +1,170 @@
+//! Command execution layer used by every install step.
+//!
+//! Steps never call `std::process::Command` directly; they go through a
+//! [`CommandRunner`] so the whole flow can be exercised off Windows against
+//! canned pacman/rustup/rustc output.
+
+use std::io;
+use std::process::{Command, Stdio};
+
+/// Captured result of running an external program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// Successful exit with the given stdout.
+    pub fn ok(stdout: &str) -> Self {
+        CommandOutput {
+            code: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    /// Exit code 1 with the given stderr.
+    #[cfg(test)]
+    pub fn failed(stderr: &str) -> Self {
+        CommandOutput {
+            code: Some(1),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs an external program to completion and captures its output.
+///
+/// An `Err` means the program could not be started at all (typically
+/// `NotFound`); a program that ran and failed is an `Ok` with a non-zero code.
+pub trait CommandRunner {
+    /// Run with extra environment variables on top of the inherited ones.
+    fn run_with_env(&self, program: &str, args: &[&str], env: &[(&str, &str)]) -> io::Result<CommandOutput>;
+
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<CommandOutput> {
+        self.run_with_env(program, args, &[])
+    }
+}
+
+/// Runs commands on the real system.
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run_with_env(&self, program: &str, args: &[&str], env: &[(&str, &str)]) -> io::Result<CommandOutput> {
+        detail!("   $ {}", command_line(program, args, env));
+        let output = Command::new(program)
+            .args(args)
+            .envs(env.iter().copied())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .inspect_err(|e| detail!("     could not start: {}", e))?;
+
+        let output = CommandOutput {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        };
+        if !output.success() {
+            detail!("     exit code {:?}: {}", output.code, output.stderr.trim());
+        }
+        Ok(output)
+    }
+}
+
+/// Renders a command the way it is shown to users and matched by fakes,
+/// with any extra environment as `NAME=value` prefixes.
+pub fn command_line(program: &str, args: &[&str], env: &[(&str, &str)]) -> String {
+    let mut line = String::new();
+    for (name, value) in env {
+        line.push_str(&format!("{}={} ", name, value));
+    }
+    line.push_str(program);
+    for arg in args {
+        line.push(' ');
+        line.push_str(arg);
+    }
+    line
+}
+
+#[cfg(test)]
+pub use fake::FakeRunner;
+
+#[cfg(test)]
+mod fake {
+    use super::{CommandOutput, CommandRunner, command_line};
+    use std::cell::RefCell;
+    use std::io;
+
+    struct Rule {
+        pattern: String,
+        response: Option<CommandOutput>,
+    }
+
+    /// Scripted runner that records every invocation.
+    ///
+    /// Rules are matched in order against the rendered command line by
+    /// substring; unmatched commands succeed with empty output.
+    #[derive(Default)]
+    pub struct FakeRunner {
+        rules: Vec<Rule>,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl FakeRunner {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Answer commands containing `pattern` with `output`.
+        pub fn on(self, pattern: &str, output: CommandOutput) -> Self {
+            self.push(pattern, Some(output))
+        }
+
+        /// Pretend the program matching `pattern` is not installed.
+        pub fn missing(self, pattern: &str) -> Self {
+            self.push(pattern, None)
+        }
+
+        fn push(mut self, pattern: &str, response: Option<CommandOutput>) -> Self {
+            self.rules.push(Rule {
+                pattern: pattern.to_string(),
+                response,
+            });
+            self
+        }
+
+        /// Every command line run so far, in order.
+        pub fn calls(&self) -> Vec<String> {
+            self.calls.borrow().clone()
+        }
+
+        /// Whether any command line run so far contains `pattern`.
+        pub fn ran(&self, pattern: &str) -> bool {
+            self.calls.borrow().iter().any(|call| call.contains(pattern))
+        }
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run_with_env(&self, program: &str, args: &[&str], env: &[(&str, &str)]) -> io::Result<CommandOutput> {
+            let line = command_line(program, args, env);
+            self.calls.borrow_mut().push(line.clone());
+
+            match self.rules.iter().find(|rule| line.contains(&rule.pattern)) {
+                Some(rule) => {
+                    rule.response.clone().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, format!("{} not found", rule.pattern))
+                    })
+                }
+                None => Ok(CommandOutput::ok("")),
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Helpers shared by the unit tests.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// Held by whichever test has output silenced: the setting is process-wide,
+/// so two such tests overlapping would un-silence each other.
+static SILENCED: Mutex<()> = Mutex::new(());
+
+/// Run `f` with [`say!`] output silenced, one test at a time.
+pub fn silenced<T>(f: impl FnOnce() -> T) -> T {
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            crate::output::set_silent(false);
+        }
+    }
+
+    let _lock = SILENCED.lock().unwrap_or_else(PoisonError::into_inner);
+    crate::output::set_silent(true);
+    let _restore = Restore;
+    f()
+}
+
+/// Fresh, empty directory under the system temp dir.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "rs-easy-install-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Lay out a minimal MSYS2 root containing `usr/bin/bash.exe`.
+pub fn fake_msys2_root(dir: &Path) -> PathBuf {
+    let root = dir.join("msys64");
+    fs::create_dir_all(root.join("usr").join("bin")).unwrap();
+    fs::write(root.join("usr").join("bin").join("bash.exe"), "").unwrap();
+    root
+}
+
+/// Write a complete offline bundle with placeholder files into `dir`.
+pub fn fake_bundle(dir: &Path) {
+    use crate::msys2::Environment;
+    use crate::offline::{MANIFEST, MSYS2_INSTALLER, RUSTUP_INIT};
+    use sha2::{Digest, Sha256};
+
+    let mut files = vec![
+        (MSYS2_INSTALLER.to_string(), "msys2 installer".to_string()),
+        (RUSTUP_INIT.to_string(), "rustup-init".to_string()),
+        ("rustup/dist/channel-rust-stable.toml".to_string(), "manifest-version = \"2\"\n".to_string()),
+        ("rustup/dist/channel-rust-stable.toml.sha256".to_string(), "0".repeat(64)),
+    ];
+    for package in Environment::default().required_packages() {
+        files.push((format!("pacman/{}-1.0-1-any.pkg.tar.zst", package), package.to_string()));
+    }
+
+    let mut manifest = String::new();
+    for (file, contents) in &files {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        manifest.push_str(&format!("{}  {}\n", crate::verify::hex(&Sha256::digest(contents)), file));
+    }
+    fs::write(dir.join(MANIFEST), manifest).unwrap();
+}
+
+/// Faults the [`TestServer`] injects, counted over all requests.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Answer this many requests with `503 Service Unavailable`.
+    pub fail_first: usize,
+    /// Drop the connection after this many body bytes on the first
+    /// successful response.
+    pub cut_first_at: Option<usize>,
+}
+
+/// Minimal HTTP/1.1 file server on localhost that understands `Range`, and
+/// acts as a proxy by serving `CONNECT` tunnels itself.
+pub struct TestServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn start(files: &[(&str, &[u8])], faults: Faults) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let files: HashMap<String, Vec<u8>> = files
+            .iter()
+            .map(|(path, body)| (path.to_string(), body.to_vec()))
+            .collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let counter = AtomicUsize::new(0);
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let index = counter.fetch_add(1, Ordering::SeqCst);
+                serve(stream, index, &files, &faults, &recorded);
+            }
+        });
+
+        TestServer { base_url, requests }
+    }
+
+    /// `"GET /path"` (or `HEAD`) for every request so far, with ` range=N`
+    /// when resuming.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Method, path and `Range` start of the next request on `reader`.
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<(String, String, Option<usize>)> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return None;
+    }
+    let method = request_line.split_whitespace().next().unwrap_or("GET").to_string();
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+    let mut range_start = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap_or(0) == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("range")
+        {
+            range_start = value
+                .trim()
+                .strip_prefix("bytes=")
+                .and_then(|v| v.trim_end_matches('-').parse::<usize>().ok());
+        }
+    }
+    Some((method, path, range_start))
+}
+
+fn serve(
+    mut stream: TcpStream,
+    index: usize,
+    files: &HashMap<String, Vec<u8>>,
+    faults: &Faults,
+    requests: &Mutex<Vec<String>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let Some((mut method, mut path, mut range_start)) = read_request(&mut reader) else {
+        return;
+    };
+    if method == "CONNECT" {
+        requests.lock().unwrap().push(format!("CONNECT {}", path));
+        let _ = write!(stream, "HTTP/1.1 200 Connection established\r\n\r\n");
+        let Some(tunneled) = read_request(&mut reader) else {
+            return;
+        };
+        (method, path, range_start) = tunneled;
+    }
+
+    let mut record = format!("{} {}", method, path);
+    if let Some(start) = range_start {
+        record.push_str(&format!(" range={}", start));
+    }
+    requests.lock().unwrap().push(record);
+
+    let respond = |stream: &mut TcpStream, status: &str, headers: &str, body: &[u8]| {
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+            status,
+            body.len(),
+            headers
+        );
+        let _ = stream.write_all(body);
+    };
+
+    if index < faults.fail_first {
+        respond(&mut stream, "503 Service Unavailable", "", b"");
+        return;
+    }
+    let Some(body) = files.get(&path) else {
+        respond(&mut stream, "404 Not Found", "", b"");
+        return;
+    };
+
+    let start = range_start.unwrap_or(0);
+    if start > body.len() {
+        respond(&mut stream, "416 Range Not Satisfiable", "", b"");
+        return;
+    }
+    let (status, headers) = match range_start {
+        Some(start) => (
+            "206 Partial Content",
+            format!("Content-Range: bytes {}-{}/{}\r\n", start, body.len().saturating_sub(1), body.len()),
+        ),
+        None => ("200 OK", String::new()),
+    };
+    let slice = &body[start..];
+
+    match faults.cut_first_at {
+        Some(cut) if index == faults.fail_first && cut < slice.len() => {
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+                status,
+                slice.len(),
+                headers
+            );
+            let _ = stream.write_all(&slice[..cut]);
+        }
+        _ => respond(&mut stream, status, &headers, slice),
+    }
+}
@@ -0,0 +1,289 @@
+//! The persistent per-user PATH (`HKCU\Environment`).
+//!
+//! Steps go through [`UserEnvironment`] so the PATH update can be computed
+//! and checked off Windows; [`RegistryEnvironment`] is the real one.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Read and write the user's persistent PATH.
+pub trait UserEnvironment {
+    /// The raw user PATH, with `%VAR%` references unexpanded; `None` if unset.
+    fn user_path(&self) -> io::Result<Option<String>>;
+
+    /// Store a new user PATH and tell running programs the environment changed.
+    fn set_user_path(&self, path: &str) -> io::Result<()>;
+}
+
+/// `HKCU\Environment` plus a `WM_SETTINGCHANGE` broadcast, so Explorer and
+/// new terminals pick the change up without logging off.
+pub struct RegistryEnvironment;
+
+#[cfg(windows)]
+impl UserEnvironment for RegistryEnvironment {
+    fn user_path(&self) -> io::Result<Option<String>> {
+        use winreg::RegKey;
+        use winreg::enums::HKEY_CURRENT_USER;
+
+        let environment = RegKey::predef(HKEY_CURRENT_USER).open_subkey("Environment")?;
+        match environment.get_value::<String, _>("Path") {
+            Ok(path) => Ok(Some(path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_user_path(&self, path: &str) -> io::Result<()> {
+        use winreg::enums::{HKEY_CURRENT_USER, KEY_READ, KEY_WRITE, REG_EXPAND_SZ};
+        use winreg::{RegKey, RegValue};
+
+        let environment = RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags("Environment", KEY_READ | KEY_WRITE)?;
+        // REG_EXPAND_SZ keeps entries like %USERPROFILE%\.cargo\bin working
+        let bytes: Vec<u8> = path
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        environment.set_raw_value(
+            "Path",
+            &RegValue {
+                bytes,
+                vtype: REG_EXPAND_SZ,
+            },
+        )?;
+        broadcast_environment_change();
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn broadcast_environment_change() {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        HWND_BROADCAST, SMTO_ABORTIFHUNG, SendMessageTimeoutW, WM_SETTINGCHANGE,
+    };
+
+    let area: Vec<u16> = "Environment".encode_utf16().chain(std::iter::once(0)).collect();
+    // SAFETY: `area` is a NUL-terminated UTF-16 string that outlives the call.
+    unsafe {
+        SendMessageTimeoutW(
+            HWND_BROADCAST,
+            WM_SETTINGCHANGE,
+            0,
+            area.as_ptr() as isize,
+            SMTO_ABORTIFHUNG,
+            5000,
+            std::ptr::null_mut(),
+        );
+    }
+}
+
+#[cfg(not(windows))]
+impl UserEnvironment for RegistryEnvironment {
+    fn user_path(&self) -> io::Result<Option<String>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the user PATH is only stored on Windows"))
+    }
+
+    fn set_user_path(&self, _path: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the user PATH is only stored on Windows"))
+    }
+}
+
+/// In-memory user PATH for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeEnvironment {
+    pub path: std::cell::RefCell<Option<String>>,
+}
+
+#[cfg(test)]
+impl FakeEnvironment {
+    pub fn with_path(path: &str) -> Self {
+        FakeEnvironment {
+            path: std::cell::RefCell::new(Some(path.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+impl UserEnvironment for FakeEnvironment {
+    fn user_path(&self) -> io::Result<Option<String>> {
+        Ok(self.path.borrow().clone())
+    }
+
+    fn set_user_path(&self, path: &str) -> io::Result<()> {
+        *self.path.borrow_mut() = Some(path.to_string());
+        Ok(())
+    }
+}
+
+/// The user PATH with the MSYS2 directories in place, or `None` if `current`
+/// already has them in the right order.
+///
+/// `toolchain_dirs` (the environments' `bin`) go right after `cargo_bin`, so
+/// rustup's proxies win over any `cargo.exe`/`rustc.exe` from MSYS2 packages;
+/// without `cargo_bin` on the PATH they go at the end. `usr_bin` is always
+/// last because its Unix tools (`link.exe`, `find.exe`) would shadow Windows
+/// ones. Existing copies of these directories are moved rather than duplicated.
+pub fn updated_path(
+    current: &str,
+    cargo_bin: &Path,
+    toolchain_dirs: &[PathBuf],
+    usr_bin: &Path,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Option<String> {
+    let ours: Vec<String> = toolchain_dirs
+        .iter()
+        .map(PathBuf::as_path)
+        .chain([usr_bin])
+        .map(|dir| normalize(&dir.to_string_lossy(), env))
+        .collect();
+    let cargo_bin = normalize(&cargo_bin.to_string_lossy(), env);
+
+    let existing: Vec<&str> = current.split(';').filter(|entry| !entry.trim().is_empty()).collect();
+    let mut entries: Vec<String> = existing
+        .iter()
+        .filter(|entry| !ours.contains(&normalize(entry, env)))
+        .map(|entry| entry.to_string())
+        .collect();
+
+    let toolchain: Vec<String> = toolchain_dirs.iter().map(|dir| dir.display().to_string()).collect();
+    match entries.iter().position(|entry| normalize(entry, env) == cargo_bin) {
+        Some(index) => {
+            entries.splice(index + 1..index + 1, toolchain);
+        }
+        None => entries.extend(toolchain),
+    }
+    entries.push(usr_bin.display().to_string());
+
+    let updated = entries.join(";");
+    (updated != existing.join(";")).then_some(updated)
+}
+
+/// Whether `dir` is one of the entries of the PATH value `current`.
+pub fn contains(current: &str, dir: &Path, env: &dyn Fn(&str) -> Option<String>) -> bool {
+    let dir = normalize(&dir.to_string_lossy(), env);
+    current.split(';').any(|entry| normalize(entry, env) == dir)
+}
+
+/// `current` without the entries matching `dirs`.
+pub fn without(current: &str, dirs: &[String], env: &dyn Fn(&str) -> Option<String>) -> String {
+    let dirs: Vec<String> = dirs.iter().map(|dir| normalize(dir, env)).collect();
+    current
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty() && !dirs.contains(&normalize(entry, env)))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// `previous`, the PATH before an update that added `added`, with what
+/// changed since kept: entries removed from `current` stay removed and new
+/// ones are appended.
+pub fn restored(current: &str, previous: &str, added: &[String], env: &dyn Fn(&str) -> Option<String>) -> String {
+    let present = |value: &str, entry: &str| value.split(';').any(|other| normalize(other, env) == normalize(entry, env));
+    let added: Vec<String> = added.iter().map(|dir| normalize(dir, env)).collect();
+    let kept = previous.split(';').filter(|entry| !entry.trim().is_empty() && present(current, entry));
+    let new = current
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty() && !present(previous, entry) && !added.contains(&normalize(entry, env)));
+    kept.chain(new).collect::<Vec<_>>().join(";")
+}
+
+/// Comparable form of a PATH entry: `%VAR%` expanded, no trailing
+/// separator, `/` as `\`, case-folded.
+fn normalize(entry: &str, env: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = entry.trim();
+    while let Some(start) = rest.find('%') {
+        let Some(len) = rest[start + 1..].find('%') else {
+            break;
+        };
+        let name = &rest[start + 1..start + 1 + len];
+        expanded.push_str(&rest[..start]);
+        match env(name) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    expanded.push_str(rest);
+    expanded.replace('/', "\\").trim_end_matches('\\').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        (name == "USERPROFILE").then(|| "C:\\Users\\dev".to_string())
+    }
+
+    fn update(current: &str) -> Option<String> {
+        updated_path(
+            current,
+            Path::new("C:\\Users\\dev\\.cargo\\bin"),
+            &[PathBuf::from("C:\\msys64\\mingw64\\bin")],
+            Path::new("C:\\msys64\\usr\\bin"),
+            &env,
+        )
+    }
+
+    #[test]
+    fn test_inserts_after_cargo_bin() {
+        assert_eq!(
+            update("%USERPROFILE%\\.cargo\\bin;C:\\Tools").as_deref(),
+            Some("%USERPROFILE%\\.cargo\\bin;C:\\msys64\\mingw64\\bin;C:\\Tools;C:\\msys64\\usr\\bin")
+        );
+    }
+
+    #[test]
+    fn test_moves_and_deduplicates_existing_entries() {
+        assert_eq!(
+            update("c:/msys64/usr/bin/;C:\\MSYS64\\mingw64\\bin;C:\\Users\\dev\\.cargo\\bin;;C:\\msys64\\mingw64\\bin\\")
+                .as_deref(),
+            Some("C:\\Users\\dev\\.cargo\\bin;C:\\msys64\\mingw64\\bin;C:\\msys64\\usr\\bin")
+        );
+    }
+
+    #[test]
+    fn test_already_in_place_is_unchanged() {
+        assert_eq!(
+            update("C:\\Users\\dev\\.cargo\\bin;C:\\msys64\\mingw64\\bin;C:\\Tools;C:\\msys64\\usr\\bin"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_contains_and_without() {
+        let path = "%USERPROFILE%\\.cargo\\bin;C:\\msys64\\mingw64\\bin\\;C:\\Tools";
+        assert!(contains(path, Path::new("C:\\Users\\dev\\.cargo\\bin"), &env));
+        assert!(!contains(path, Path::new("C:\\msys64\\usr\\bin"), &env));
+        assert_eq!(
+            without(path, &["c:/msys64/mingw64/bin".to_string()], &env),
+            "%USERPROFILE%\\.cargo\\bin;C:\\Tools"
+        );
+    }
+
+    #[test]
+    fn test_restored() {
+        let previous = "C:\\msys64\\usr\\bin;%USERPROFILE%\\.cargo\\bin;C:\\Tools";
+        let updated = update(previous).unwrap();
+        let added = ["C:\\msys64\\mingw64\\bin".to_string()];
+        assert_eq!(restored(&updated, previous, &added, &env), previous);
+
+        // Later edits by the user survive
+        let edited = format!("{};D:\\bin", without(&updated, &["C:\\Tools".to_string()], &env));
+        assert_eq!(
+            restored(&edited, previous, &added, &env),
+            "C:\\msys64\\usr\\bin;%USERPROFILE%\\.cargo\\bin;D:\\bin"
+        );
+    }
+
+    #[test]
+    fn test_without_cargo_bin_appends() {
+        assert_eq!(update("").as_deref(), Some("C:\\msys64\\mingw64\\bin;C:\\msys64\\usr\\bin"));
+        assert_eq!(
+            update("C:\\Tools").as_deref(),
+            Some("C:\\Tools;C:\\msys64\\mingw64\\bin;C:\\msys64\\usr\\bin")
+        );
+    }
+}
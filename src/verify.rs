@@ -0,0 +1,229 @@
+//! Integrity checks for downloaded installers.
+//!
+//! Every installer is checked against a SHA-256 digest before it is run,
+//! either one pinned on the command line or the `.sha256` file published next
+//! to it. Detached GPG or minisign signatures can be checked on top of that.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::download::Downloader;
+use crate::error::InstallError;
+use crate::runner::CommandRunner;
+
+/// Tool used to check detached signatures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureTool {
+    /// `gpg --verify`; the signer's key must already be in the keyring.
+    Gpg,
+    /// `minisign -V` against the given public key.
+    Minisign { public_key: String },
+}
+
+impl SignatureTool {
+    /// Extension of the detached signature published next to the file.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SignatureTool::Gpg => "sig",
+            SignatureTool::Minisign { .. } => "minisig",
+        }
+    }
+}
+
+/// How downloaded installers are verified before they run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyPolicy {
+    pub msys2_sha256: Option<String>,
+    pub rustup_sha256: Option<String>,
+    pub signature: Option<SignatureTool>,
+}
+
+/// Lowercase hex SHA-256 of a file.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether `value` looks like a SHA-256 hex digest.
+pub fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Digest from a `.sha256` file: either a bare digest or `sha256sum` output.
+pub fn parse_sidecar(text: &str) -> Option<String> {
+    let digest = text.split_whitespace().next()?.trim_start_matches('\u{feff}');
+    is_sha256(digest).then(|| digest.to_ascii_lowercase())
+}
+
+/// Fail unless `path` hashes to `expected`.
+pub fn check_sha256(path: &Path, expected: &str) -> Result<(), String> {
+    let actual = sha256_file(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Checksum mismatch for {}: expected {}, got {}. The download may be corrupted or tampered with.",
+            path.display(),
+            expected.to_ascii_lowercase(),
+            actual
+        ))
+    }
+}
+
+/// Check the detached signature `signature` of `path` with `tool`.
+pub fn check_signature(
+    runner: &dyn CommandRunner,
+    tool: &SignatureTool,
+    path: &Path,
+    signature: &Path,
+) -> Result<(), String> {
+    let path_arg = path.to_string_lossy();
+    let signature_arg = signature.to_string_lossy();
+    let (program, output) = match tool {
+        SignatureTool::Gpg => ("gpg", runner.run("gpg", &["--verify", &signature_arg, &path_arg])),
+        SignatureTool::Minisign { public_key } => (
+            "minisign",
+            runner.run("minisign", &["-V", "-P", public_key, "-m", &path_arg, "-x", &signature_arg]),
+        ),
+    };
+
+    match output {
+        Ok(output) if output.success() => Ok(()),
+        Ok(output) => Err(format!(
+            "Signature verification failed for {}: {}",
+            path.display(),
+            output.stderr.lines().next().unwrap_or("bad signature")
+        )),
+        Err(e) => Err(format!("Cannot verify signature of {}: {} is not available ({})", path.display(), program, e)),
+    }
+}
+
+/// Check the file downloaded from `url` to `path` against `pinned`, or the
+/// digest published at `<url>.sha256`, then, with `signature`, against the
+/// detached signature published next to it. A file that fails is deleted.
+///
+/// Returns the downloaded signature file, which the caller may keep or remove.
+/// Failing to fetch the checksum or signature is a [`InstallError::Download`];
+/// everything else is a [`InstallError::Checksum`].
+pub fn verify_download(
+    downloader: &Downloader,
+    runner: &dyn CommandRunner,
+    url: &str,
+    path: &Path,
+    pinned: Option<&str>,
+    pin_flag: &str,
+    signature: Option<&SignatureTool>,
+) -> Result<Option<PathBuf>, InstallError> {
+    let failed = |reason: String| InstallError::Checksum {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let expected = match pinned {
+        Some(digest) => digest.to_string(),
+        None => {
+            let sidecar_url = format!("{}.sha256", url);
+            let text = downloader.fetch_string(&sidecar_url).map_err(|e| InstallError::Download {
+                url: sidecar_url.clone(),
+                reason: format!("{}. Pass {} <SHA256> to pin a checksum instead.", e, pin_flag),
+            })?;
+            parse_sidecar(&text).ok_or_else(|| failed(format!("{} does not contain a SHA-256 checksum", sidecar_url)))?
+        }
+    };
+
+    if let Err(e) = check_sha256(path, &expected) {
+        let _ = fs::remove_file(path);
+        return Err(failed(e));
+    }
+    say!("✅ SHA-256 verified: {}", expected);
+
+    let Some(tool) = signature else {
+        return Ok(None);
+    };
+    let signature_url = format!("{}.{}", url, tool.extension());
+    let mut signature_name = path.file_name().unwrap_or_default().to_os_string();
+    signature_name.push(format!(".{}", tool.extension()));
+    let signature_path = path.with_file_name(signature_name);
+    downloader
+        .download(&signature_url, &signature_path)
+        .map_err(|e| InstallError::Download {
+            url: signature_url.clone(),
+            reason: e.to_string(),
+        })?;
+    if let Err(e) = check_signature(runner, tool, path, &signature_path) {
+        let _ = fs::remove_file(&signature_path);
+        let _ = fs::remove_file(path);
+        return Err(failed(e));
+    }
+    say!("✅ Signature verified");
+
+    Ok(Some(signature_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{CommandOutput, FakeRunner};
+    use crate::testutil::scratch_dir;
+    use std::fs;
+
+    // SHA-256 of "abc"
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_check_sha256() {
+        let file = scratch_dir("sha256").join("installer.exe");
+        fs::write(&file, "abc").unwrap();
+
+        assert_eq!(sha256_file(&file).unwrap(), ABC);
+        check_sha256(&file, &ABC.to_ascii_uppercase()).unwrap();
+
+        let err = check_sha256(&file, &"0".repeat(64)).unwrap_err();
+        assert!(err.starts_with("Checksum mismatch"));
+        assert!(err.contains(ABC));
+    }
+
+    #[test]
+    fn test_parse_sidecar() {
+        assert_eq!(parse_sidecar(&format!("{}\n", ABC)), Some(ABC.to_string()));
+        assert_eq!(
+            parse_sidecar(&format!("{} *msys2-x86_64-latest.exe\n", ABC.to_ascii_uppercase())),
+            Some(ABC.to_string())
+        );
+        assert_eq!(parse_sidecar("<html>Not Found</html>"), None);
+        assert_eq!(parse_sidecar(""), None);
+    }
+
+    #[test]
+    fn test_check_signature_reports_tool_failure() {
+        let runner = FakeRunner::new()
+            .on("gpg --verify", CommandOutput::failed("gpg: BAD signature from \"MSYS2\""))
+            .missing("minisign");
+        let file = Path::new("installer.exe");
+        let sig = Path::new("installer.exe.sig");
+
+        let err = check_signature(&runner, &SignatureTool::Gpg, file, sig).unwrap_err();
+        assert!(err.contains("BAD signature"));
+
+        let minisign = SignatureTool::Minisign {
+            public_key: "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".to_string(),
+        };
+        let err = check_signature(&runner, &minisign, file, sig).unwrap_err();
+        assert!(err.contains("minisign is not available"));
+    }
+}